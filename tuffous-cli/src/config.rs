@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+use tuffous_core::{icons::IconSet, palette::Palette};
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct ConfigInstance {
+    /// Saved `list --format` templates by name.
+    #[serde(default)]
+    pub formats: BTreeMap<String, String>,
+    /// Icons to draw with, detected from the terminal if not set.
+    #[serde(default)]
+    pub icons: Option<IconSet>,
+    /// Colors of the output, the dark palette if not set.
+    #[serde(default)]
+    pub palette: Option<Palette>,
+}
+
+impl ConfigInstance {
+    pub fn get() -> Self {
+        Self::read_from_file(Self::path()).unwrap_or_default()
+    }
+
+    pub fn read_from_file<P: AsRef<Path>>(path: P) -> Option<Self> {
+        if let Ok(s) = fs::read_to_string(path) {
+            if let Ok(x) = serde_json::from_str::<Self>(&s) {
+                return Some(x);
+            }
+        };
+        None
+    }
+
+    pub fn write(&self) {
+        let _ = fs::write(Self::path(), serde_json::to_string(&self).unwrap());
+    }
+
+    fn path() -> String {
+        String::from("./.tuffous/config_cli.json")
+    }
+}
@@ -1,98 +1,635 @@
-use chrono::{Datelike, Local, NaiveDate, Timelike};
-use clap::{arg, Arg, ArgMatches, Command};
+mod config;
+mod output;
+mod style;
+mod template;
+
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, Timelike, Utc};
+use clap::{arg, parser::ValueSource, Arg, ArgMatches, Command};
+use output::{AgendaRecord, OutputMode, TodoDetail, TodoRecord, TrashRecord};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs::File,
-    io::{Read, Write},
+    collections::HashMap,
+    fs::{self, File},
+    io::{IsTerminal, Read, Write},
 };
+use style::{ColorMode, Style};
+use template::Template;
 use tuffous_core::{
+    agenda::AgendaEntry,
+    config::{Perspective, RepoConfig},
+    crypt, fsck,
+    icons::{Icon, IconSet},
+    order::{OrderScope, Sort, VIEWS},
+    palette::Rgb,
+    path::PathError,
+    progress::Strategy,
+    query::Query,
+    search::Matcher,
+    stats::Period,
+    storage::{self, Storage},
     util::{parse_date, parse_date_and_time},
-    version, Todo, TodoInstance,
+    version, CheckItem, Todo, TodoInstance,
 };
 
 pub fn main() {
-    match cli().get_matches().subcommand() {
+    let matches = cli().get_matches();
+    let style = style(&matches);
+    if !matches!(matches.subcommand_name(), Some("init" | "crypt")) && crypt::is_encrypted(".") {
+        unlock(&matches);
+    }
+    match matches.subcommand() {
         Some(("init", _)) => {
             tuffous_core::init_repo(".");
         }
         Some(("new", matches)) => {
             let mut instance = TodoInstance::create(".");
             instance.read_all();
+            apply_policies(&mut instance);
             instance.refresh();
             let mut todo = Todo::create(matches.get_one::<String>("TITLE").unwrap().to_owned());
             process_edit_todo(matches, &mut todo);
-            instance.todos.push(todo);
+            let id = todo.id();
+            instance.insert(todo);
             instance.write_all();
+            output::report(&instance, &[id], output_mode(matches));
         }
         Some(("list", matches)) => {
-            let mut scanner = TodoScanner::new(TodoInstance::create("."));
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
             scanner.instance.read_all();
             scanner.instance.refresh();
+            scanner.output = output_mode(matches);
             scanner.apply_filters(matches);
+            scanner.apply_list_options(matches);
             scanner.list(false);
         }
         Some(("edit", matches)) => {
-            let mut scanner = TodoScanner::new(TodoInstance::create("."));
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
             scanner.instance.read_all();
+            apply_policies(&mut scanner.instance);
             scanner.instance.refresh();
+            scanner.output = output_mode(matches);
             scanner.apply_filters(matches);
-            for todo_id in scanner.list(true) {
-                if let Some(todo) = scanner.instance.get_mut(todo_id) {
+            let selection = scanner.select(matches);
+            for todo_id in &selection {
+                if let Some(todo) = scanner.instance.get_mut(*todo_id) {
                     process_edit_todo(matches, todo);
                 }
             }
             scanner.instance.write_all();
+            output::report(&scanner.instance, &selection, scanner.output);
         }
         Some(("complete", matches)) => {
-            let mut scanner = TodoScanner::new(TodoInstance::create("."));
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
             scanner.instance.read_all();
+            apply_policies(&mut scanner.instance);
             scanner.instance.refresh();
+            scanner.output = output_mode(matches);
             scanner.apply_filters(matches);
-            for todo_id in scanner.list(true) {
-                if let Some(todo) = scanner.instance.get_mut(todo_id) {
-                    todo.completed = true;
+            let selection = scanner.select(matches);
+            for todo_id in &selection {
+                if let Some(todo) = scanner.instance.get_mut(*todo_id) {
+                    todo.set_completed(true);
                 }
             }
             scanner.instance.write_all();
+            output::report(&scanner.instance, &selection, scanner.output);
         }
         Some(("father", matches)) => {
-            let mut scanner = TodoScanner::new(TodoInstance::create("."));
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
             let mut cache = TodoCache::create();
             scanner.instance.read_all();
+            apply_policies(&mut scanner.instance);
             scanner.instance.refresh();
+            scanner.output = output_mode(matches);
             scanner.apply_filters(matches);
-            if let Some(todo_id) = scanner.list(true).into_iter().next() {
+            let father = scanner.select(matches).into_iter().next();
+            if let Some(todo_id) = father {
                 cache.father = Some(todo_id);
             }
             cache.process(&mut scanner.instance);
             cache.write();
             scanner.instance.write_all();
+            output::report(
+                &scanner.instance,
+                &father.into_iter().collect::<Vec<u64>>(),
+                scanner.output,
+            );
         }
         Some(("child", matches)) => {
-            let mut scanner = TodoScanner::new(TodoInstance::create("."));
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
             let mut cache = TodoCache::create();
             scanner.instance.read_all();
+            apply_policies(&mut scanner.instance);
             scanner.instance.refresh();
+            scanner.output = output_mode(matches);
             scanner.apply_filters(matches);
-            for todo_id in scanner.list(true) {
-                cache.child.push(todo_id);
+            let selection = scanner.select(matches);
+            for todo_id in &selection {
+                cache.child.push(*todo_id);
             }
             cache.process(&mut scanner.instance);
             cache.write();
             scanner.instance.write_all();
+            output::report(&scanner.instance, &selection, scanner.output);
         }
         Some(("remove", matches)) => {
-            let mut scanner = TodoScanner::new(TodoInstance::create("."));
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
             let mut cache = TodoCache::create();
             scanner.instance.read_all();
+            apply_policies(&mut scanner.instance);
             scanner.instance.refresh();
+            scanner.output = output_mode(matches);
             scanner.apply_filters(matches);
-            for todo_id in scanner.list(true) {
-                scanner.instance.remove(todo_id);
-            }
+            let selection = scanner.select(matches);
+            output::report(&scanner.instance, &selection, scanner.output);
+            let short_ids = scanner.instance.short_ids();
+            let failed = selection.iter().find_map(|todo_id| {
+                let removed = scanner.instance.remove(*todo_id);
+                removed.err().map(|err| (short_ids[todo_id].clone(), err))
+            });
             cache.clean();
             cache.write();
             scanner.instance.write_all();
+            if let Some((id, err)) = failed {
+                fail(&format!("Can't move {id} to the trash: {err}"));
+            }
+            if scanner.output.is_text() && !selection.is_empty() {
+                println!(
+                    "Moved {} todo(s) to the trash, see `tuffouscli trash`",
+                    selection.len()
+                );
+            }
+        }
+        Some(("move", matches)) => {
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
+            scanner.instance.read_all();
+            apply_policies(&mut scanner.instance);
+            scanner.instance.refresh();
+            scanner.output = output_mode(matches);
+            scanner.apply_filters(matches);
+            let Some(todo_id) = scanner.select(matches).into_iter().next() else {
+                return;
+            };
+
+            if let Some(n) = matches.get_one::<String>("under") {
+                let father = find_todo(&scanner.instance, n);
+                let fathers = scanner.instance.get(todo_id).unwrap().dependents.clone();
+                for old in &fathers {
+                    scanner.instance.unchild(*old, todo_id);
+                }
+                if !scanner.instance.is_child_able(father, todo_id) {
+                    fail("The todo can't be moved under one of its own children")
+                }
+                scanner.instance.child(father, todo_id);
+                scanner.instance.write_all();
+                scanner.show_tree(father);
+                return;
+            }
+
+            let (target, after) = if let Some(n) = matches.get_one::<String>("before") {
+                (find_todo(&scanner.instance, n), false)
+            } else if let Some(n) = matches.get_one::<String>("after") {
+                (find_todo(&scanner.instance, n), true)
+            } else {
+                fail("One of --before, --after or --under is required")
+            };
+
+            let view = matches.get_one::<String>("view").unwrap();
+            if !VIEWS.contains(&view.as_str()) {
+                fail(&format!("Unknown view: {view}"))
+            }
+
+            let (scope, siblings) = sibling_scope(&scanner.instance, todo_id, target, view);
+            scanner.instance.reorder(
+                &siblings,
+                todo_id,
+                target,
+                after,
+                &scope,
+                &scanner.config.secondary_sort,
+            );
+            scanner.instance.write_all();
+            output::report(&scanner.instance, &[todo_id], scanner.output);
+        }
+        Some(("link", matches)) => {
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
+            scanner.instance.read_all();
+            apply_policies(&mut scanner.instance);
+            scanner.instance.refresh();
+            scanner.output = output_mode(matches);
+            scanner.apply_filters(matches);
+            let father = find_todo(&scanner.instance, matches.get_one::<String>("to").unwrap());
+            let selection = scanner.select(matches);
+            for todo_id in &selection {
+                if scanner
+                    .instance
+                    .get(*todo_id)
+                    .unwrap()
+                    .dependents
+                    .contains(&father)
+                {
+                    continue;
+                }
+                if !scanner.instance.is_child_able(father, *todo_id) {
+                    fail(&format!(
+                        "Can't link {} to {}, as it would make a cycle",
+                        scanner.instance.todo_path(*todo_id),
+                        scanner.instance.todo_path(father)
+                    ))
+                }
+                scanner.instance.child(father, *todo_id);
+            }
+            scanner.instance.write_all();
+            scanner.show_tree(father);
+        }
+        Some(("unlink", matches)) => {
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
+            scanner.instance.read_all();
+            apply_policies(&mut scanner.instance);
+            scanner.instance.refresh();
+            scanner.output = output_mode(matches);
+            scanner.apply_filters(matches);
+            let father = matches
+                .get_one::<String>("from")
+                .map(|n| find_todo(&scanner.instance, n));
+            let selection = scanner.select(matches);
+            for todo_id in &selection {
+                let fathers = scanner.instance.get(*todo_id).unwrap().dependents.clone();
+                for old in fathers {
+                    if father.is_none() || father == Some(old) {
+                        scanner.instance.unchild(old, *todo_id);
+                    }
+                }
+            }
+            scanner.instance.write_all();
+            if let Some(father) = father {
+                scanner.show_tree(father);
+            } else {
+                output::report(&scanner.instance, &selection, scanner.output);
+            }
+        }
+        Some(("tree", matches)) => {
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
+            scanner.instance.read_all();
+            scanner.instance.refresh();
+            scanner.output = output_mode(matches);
+            scanner.apply_filters(matches);
+            if let Some(n) = matches.get_one::<String>("depth") {
+                scanner.depth = Some(
+                    n.parse::<usize>()
+                        .unwrap_or_else(|_| fail(&format!("Invalid depth: {n}"))),
+                );
+            }
+            scanner.collapse_completed = matches.get_flag("collapse-completed");
+            let root = matches
+                .get_one::<String>("root")
+                .map(|n| find_todo(&scanner.instance, n));
+            scanner.tree(root);
+        }
+        Some(("agenda", matches)) => {
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
+            scanner.instance.read_all();
+            scanner.instance.refresh();
+            scanner.output = output_mode(matches);
+            scanner.apply_filters(matches);
+            let days = matches
+                .get_one::<String>("days")
+                .unwrap()
+                .parse::<u32>()
+                .unwrap_or_else(|_| fail("Invalid number of days"));
+            scanner.agenda(days, matches.get_flag("undated"));
+        }
+        Some(("stats", matches)) => {
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
+            scanner.instance.read_all();
+            scanner.instance.refresh();
+            scanner.output = output_mode(matches);
+            scanner.apply_filters(matches);
+            let period = matches
+                .get_one::<String>("by")
+                .unwrap()
+                .parse::<Period>()
+                .unwrap_or_else(|err| fail(&err));
+            scanner.stats(period);
+        }
+        Some(("archive", matches)) => {
+            if let Some(n) = matches.get_one::<String>("auto") {
+                let mut config = RepoConfig::get(".");
+                config.archive_after_days = if n == "off" {
+                    None
+                } else {
+                    Some(parse_days(n).unwrap_or_else(|| fail(&format!("Invalid age: {n}"))))
+                };
+                config
+                    .write(".")
+                    .unwrap_or_else(|err| fail(&format!("Can't write the config: {err}")));
+                match config.archive_after_days {
+                    Some(days) => {
+                        println!("Completed todos are archived {days} days after their completion")
+                    }
+                    None => println!("Completed todos aren't archived automatically"),
+                }
+            }
+
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
+            scanner.instance.read_all();
+            scanner.instance.refresh();
+            scanner.output = output_mode(matches);
+            let days = match matches.get_one::<String>("older-than") {
+                Some(n) => parse_days(n).unwrap_or_else(|| fail(&format!("Invalid age: {n}"))),
+                None => match scanner.config.archive_after_days {
+                    Some(days) => days,
+                    // The policy was just turned off
+                    None if matches.get_one::<String>("auto").is_some() => return,
+                    None => fail("Give an age with --older-than or set a policy with --auto"),
+                },
+            };
+            let ids = scanner
+                .instance
+                .archivable(Utc::now().naive_utc() - Duration::days(days as i64));
+            scanner.archive(&ids, matches.get_flag("dry-run"));
+        }
+        Some(("trash", matches)) => {
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
+            scanner.instance.read_all();
+            scanner.instance.refresh();
+            match matches.subcommand() {
+                Some(("restore", matches)) => {
+                    scanner.output = output_mode(matches);
+                    let mut restored = Vec::new();
+                    for n in matches.get_many::<String>("TODO").unwrap() {
+                        let ids = scanner.instance.find_trashed(n);
+                        match ids.len() {
+                            0 => fail(&format!("Todo not found in the trash: {n}")),
+                            1 => (),
+                            _ => fail(&format!("More than one removed todo matches {n}")),
+                        }
+                        scanner.instance.restore(ids[0]);
+                        restored.push(ids[0]);
+                    }
+                    if scanner.output.is_text() {
+                        scanner.short_ids = scanner.instance.short_ids();
+                        for id in &restored {
+                            println!("{}", scanner.todo_line(*id));
+                        }
+                        println!("Restored {} todo(s)", restored.len());
+                    } else {
+                        output::report(&scanner.instance, &restored, scanner.output);
+                    }
+                }
+                Some(("empty", matches)) => {
+                    if let Some(n) = matches.get_one::<String>("auto") {
+                        let mut config = RepoConfig::get(".");
+                        config.trash_purge_days = if n == "off" {
+                            None
+                        } else {
+                            Some(
+                                parse_days(n).unwrap_or_else(|| fail(&format!("Invalid age: {n}"))),
+                            )
+                        };
+                        config
+                            .write(".")
+                            .unwrap_or_else(|err| fail(&format!("Can't write the config: {err}")));
+                        match config.trash_purge_days {
+                            Some(days) => println!(
+                                "Removed todos are deleted {days} days after their removal"
+                            ),
+                            None => println!("Removed todos are kept until the trash is emptied"),
+                        }
+                        if matches.get_one::<String>("older-than").is_none() {
+                            let count = scanner.instance.purge_trash_by_policy();
+                            if count > 0 {
+                                println!("Deleted {count} todo(s) for good");
+                            }
+                            return;
+                        }
+                    }
+                    let before = matches.get_one::<String>("older-than").map(|n| {
+                        let days =
+                            parse_days(n).unwrap_or_else(|| fail(&format!("Invalid age: {n}")));
+                        Utc::now().naive_utc() - Duration::days(days as i64)
+                    });
+                    println!(
+                        "Deleted {} todo(s) for good",
+                        scanner.instance.empty_trash(before)
+                    );
+                }
+                Some(("list", matches)) => {
+                    scanner.output = output_mode(matches);
+                    scanner.trash();
+                }
+                _ => scanner.trash(),
+            }
+        }
+        Some(("fsck", matches)) => {
+            let problem = |text: String| style.paint(&text, Some(style.palette.flag));
+            if matches.get_flag("fix") {
+                for (p, result) in fsck::repair(".") {
+                    match result {
+                        Ok(()) => println!("Fixed: {p}\n  {}", p.remedy()),
+                        Err(err) => println!("{}\n  {err}", problem(format!("Can't fix: {p}"))),
+                    }
+                }
+                if let Some(p) = TodoCache::problem() {
+                    TodoCache::create_empty().write();
+                    println!("Fixed: {p}\n  clean the cache");
+                }
+                let left = fsck::check(".").len();
+                if left > 0 {
+                    fail(&format!("{left} problem(s) left"));
+                }
+                return;
+            }
+
+            let mut problems: Vec<(String, String)> = fsck::check(".")
+                .iter()
+                .map(|p| (p.to_string(), p.remedy()))
+                .collect();
+            if let Some(p) = TodoCache::problem() {
+                problems.push((p, String::from("clean the cache")));
+            }
+            if problems.is_empty() {
+                println!("No problems found");
+                return;
+            }
+            for (text, remedy) in &problems {
+                println!("{}\n  fix: {remedy}", problem(text.to_owned()));
+            }
+            fail(&format!(
+                "{} problem(s), run `tuffouscli fsck --fix` to fix them",
+                problems.len()
+            ));
+        }
+        Some(("storage", matches)) => match matches.subcommand() {
+            Some(("convert", matches)) => {
+                let n = matches.get_one::<String>("STORAGE").unwrap();
+                let to = n.parse::<Storage>().unwrap_or_else(|err| fail(&err));
+                match storage::convert(".", to) {
+                    Ok(count) => println!("Moved {count} todo(s) to {to}"),
+                    Err(err) => fail(&err),
+                }
+            }
+            Some(("compact", _)) => {
+                let mut instance = TodoInstance::create(".");
+                if instance.storage() != Storage::Jsonl {
+                    fail("Only the jsonl storage can be compacted");
+                }
+                match instance.compact() {
+                    Ok((before, after)) => {
+                        println!("Compacted the log from {before} to {after} lines")
+                    }
+                    Err(err) => fail(&err),
+                }
+            }
+            _ => {
+                let mut instance = TodoInstance::create(".");
+                instance.read_all();
+                match instance.storage() {
+                    Storage::Files => {
+                        println!("files: {} todo(s) in .tuffous/todos", instance.todos.len())
+                    }
+                    Storage::Jsonl => println!(
+                        "jsonl: {} todo(s) in {} line(s) of .tuffous/todos.jsonl",
+                        instance.todos.len(),
+                        instance.log_lines()
+                    ),
+                }
+            }
+        },
+        Some(("crypt", matches)) => match matches.subcommand() {
+            Some(("enable", matches)) => {
+                if crypt::is_encrypted(".") {
+                    fail("The repo is already encrypted");
+                }
+                let passphrase = new_passphrase(matches);
+                match crypt::enable(".", &passphrase) {
+                    Ok(count) => println!("Encrypted {count} file(s)"),
+                    Err(err) => fail(&err),
+                }
+            }
+            Some(("rotate", matches)) => {
+                if !crypt::is_encrypted(".") {
+                    fail("The repo isn't encrypted");
+                }
+                unlock(matches);
+                let passphrase = new_passphrase(matches);
+                match crypt::rotate(".", &passphrase) {
+                    Ok(count) => println!("Encrypted {count} file(s) with a new key"),
+                    Err(err) => fail(&err),
+                }
+            }
+            Some(("disable", matches)) => {
+                if !crypt::is_encrypted(".") {
+                    fail("The repo isn't encrypted");
+                }
+                unlock(matches);
+                match crypt::disable(".") {
+                    Ok(count) => println!("Decrypted {count} file(s)"),
+                    Err(err) => fail(&err),
+                }
+            }
+            _ => {
+                if crypt::is_encrypted(".") {
+                    println!("The repo is encrypted");
+                } else {
+                    println!("The repo isn't encrypted");
+                }
+            }
+        },
+        Some(("show", matches)) => {
+            let mut scanner = TodoScanner::new(TodoInstance::create("."), style);
+            scanner.instance.read_all();
+            scanner.instance.refresh();
+            scanner.output = output_mode(matches);
+            let id = find_todo(
+                &scanner.instance,
+                matches.get_one::<String>("TODO").unwrap(),
+            );
+            scanner.show(id);
+        }
+        Some(("perspective", matches)) => {
+            let mut config = RepoConfig::get(".");
+            let name = matches.get_one::<String>("NAME");
+            let name = name.map(|n| n.strip_prefix('@').unwrap_or(n));
+            match (name, matches.get_one::<String>("QUERY")) {
+                (Some(name), _) if matches.get_flag("remove") => {
+                    config
+                        .perspectives
+                        .retain(|p| p.name.to_lowercase() != name.to_lowercase());
+                    config
+                        .write(".")
+                        .unwrap_or_else(|err| fail(&format!("Can't write the config: {err}")));
+                }
+                (Some(name), Some(query)) => {
+                    if let Err(err) = Query::parse(query) {
+                        fail(&err)
+                    }
+                    let icon = matches.get_one::<String>("icon").map(|n| {
+                        n.chars()
+                            .next()
+                            .unwrap_or_else(|| fail("The icon can't be empty"))
+                    });
+                    let color = matches
+                        .get_one::<String>("icon-color")
+                        .map(|n| n.parse::<Rgb>().unwrap_or_else(|err| fail(&err)));
+                    let perspective = Perspective {
+                        name: name.to_owned(),
+                        query: query.to_owned(),
+                        icon,
+                        color,
+                        completed: matches.get_flag("completed"),
+                    };
+                    if let Some(p) = config
+                        .perspectives
+                        .iter_mut()
+                        .find(|p| p.name.to_lowercase() == name.to_lowercase())
+                    {
+                        *p = perspective;
+                    } else {
+                        config.perspectives.push(perspective);
+                    }
+                    config
+                        .write(".")
+                        .unwrap_or_else(|err| fail(&format!("Can't write the config: {err}")));
+                }
+                (Some(name), None) => match config.perspective(name) {
+                    Some(perspective) => println!("{}", perspective.query),
+                    None => fail(&format!("Perspective not found: {name}")),
+                },
+                (None, _) => {
+                    for perspective in &config.perspectives {
+                        println!("@{}: {}", perspective.name, perspective.query);
+                    }
+                }
+            }
+        }
+        Some(("format", matches)) => {
+            let mut config = config::ConfigInstance::get();
+            match (
+                matches.get_one::<String>("NAME"),
+                matches.get_one::<String>("TEMPLATE"),
+            ) {
+                (Some(name), _) if matches.get_flag("remove") => {
+                    config.formats.remove(name);
+                    config.write();
+                }
+                (Some(name), Some(template)) => {
+                    if let Err(err) = Template::parse(template) {
+                        fail(&err)
+                    }
+                    config.formats.insert(name.to_owned(), template.to_owned());
+                    config.write();
+                }
+                (Some(name), None) => match config.formats.get(name) {
+                    Some(template) => println!("{template}"),
+                    None => fail(&format!("Format preset not found: {name}")),
+                },
+                (None, _) => {
+                    for (name, template) in &config.formats {
+                        println!("{name}: {template}");
+                    }
+                }
+            }
         }
         Some(("cleancache", _)) => {
             let mut cache = TodoCache::create();
@@ -112,47 +649,248 @@ fn cli() -> Command {
         .subcommand_required(false)
         .arg_required_else_help(true)
         .allow_external_subcommands(true)
+        .arg(
+            arg!(--icons <SET> "Icons to draw with: nerd, emoji or ascii")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(--color <WHEN> "Color the output: auto, always or never")
+                .default_value("auto")
+                .global(true),
+        )
+        .arg(
+            arg!(--keyfile <FILE> "The file holding the passphrase of an encrypted repo")
+                .required(false)
+                .global(true),
+        )
         .subcommand(Command::new("init").about("Initialize a new todo repo"))
         .subcommand(
             Command::new("new")
+                .args(output_args())
                 .about("Create a new todo")
                 .arg(arg!(<TITLE> "The name of the todo"))
                 .args(edit_args()),
         )
         .subcommand(
             Command::new("list")
+                .args(output_args())
                 .about("List todo(s) with filter(s)")
-                .args(filter_args()),
+                .arg(arg!([PERSPECTIVE] "A perspective to list, like @waiting"))
+                .args(filter_args())
+                .args(list_args()),
         )
         .subcommand(
             Command::new("edit")
+                .args(target_args())
+                .args(output_args())
                 .about("Edit todo(s) with filter(s)")
                 .args(filter_args())
                 .args(edit_args()),
         )
         .subcommand(
             Command::new("complete")
+                .args(target_args())
+                .args(output_args())
                 .about("Complete todo(s) with filter(s)")
                 .args(filter_args()),
         )
         .subcommand(
             Command::new("father")
+                .args(target_args())
+                .args(output_args())
                 .about("Mark a todo as father with filter(s) in the cache")
                 .args(filter_args()),
         )
         .subcommand(
             Command::new("child")
+                .args(target_args())
+                .args(output_args())
                 .about("Mark todo(s) as children with filter(s) in the cache")
                 .args(filter_args()),
         )
         .subcommand(
             Command::new("remove")
+                .args(target_args())
+                .args(output_args())
                 .about("Remove todo(s) as children with filter(s)")
                 .args(filter_args()),
         )
+        .subcommand(
+            Command::new("move")
+                .args(target_args())
+                .args(output_args())
+                .about("Move a todo with filter(s) before or after another todo, or under a father")
+                .args(filter_args())
+                .arg(
+                    arg!(--before <TODO> "Move before the todo with the id or path")
+                        .required(false),
+                )
+                .arg(
+                    arg!(--after <TODO> "Move after the todo with the id or path")
+                        .required(false)
+                        .conflicts_with("before"),
+                )
+                .arg(
+                    arg!(--under <FATHER> "Move under the father, unlinking it from the others")
+                        .required(false)
+                        .conflicts_with_all(["before", "after"]),
+                )
+                .arg(
+                    arg!(--view <VIEW> "The view to order top-level todos in").default_value("all"),
+                ),
+        )
+        .subcommand(
+            Command::new("link")
+                .about("Link todo(s) as children of a father")
+                .args(target_args())
+                .args(output_args())
+                .args(filter_args())
+                .arg(arg!(--to <FATHER> "The father todo to link to")),
+        )
+        .subcommand(
+            Command::new("unlink")
+                .about("Unlink todo(s) from a father, or from all fathers")
+                .args(target_args())
+                .args(output_args())
+                .args(filter_args())
+                .arg(arg!(--from <FATHER> "The father todo to unlink from").required(false)),
+        )
+        .subcommand(
+            Command::new("tree")
+                .about("Draw the tree of todo(s) with filter(s)")
+                .args(output_args())
+                .args(filter_args())
+                .arg(arg!(--depth <DEPTH> "Only draw todos up to the depth").required(false))
+                .arg(
+                    arg!(--"collapse-completed" "Hide the children of completed todos")
+                        .required(false),
+                )
+                .arg(arg!(--root <TODO> "Draw the tree under the todo only").required(false)),
+        )
+        .subcommand(
+            Command::new("agenda")
+                .about("Show overdue todos and the todos of the next days")
+                .args(output_args())
+                .args(filter_args())
+                .arg(arg!(--days <DAYS> "The number of days to show").default_value("7"))
+                .arg(arg!(--undated "Show todos without a date or deadline at the end")),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Count created, completed and overdue todos, by period, tag and project")
+                .args(output_args())
+                .args(filter_args())
+                .mut_arg("flogged", |arg| arg.default_value("any"))
+                .arg(arg!(--by <PERIOD> "Count by day, week or month").default_value("week")),
+        )
+        .subcommand(
+            Command::new("archive")
+                .about("Move old completed todos and fully completed subtrees to the archive")
+                .args(output_args())
+                .arg(
+                    arg!(--"older-than" <AGE> "Archive todos completed longer ago than the age, like 30d or 4w")
+                        .required(false),
+                )
+                .arg(
+                    arg!(--auto <AGE> "Archive todos automatically after the age, or never with off")
+                        .required(false),
+                )
+                .arg(arg!(-n --"dry-run" "Only print the todos that would be archived")),
+        )
+        .subcommand(
+            Command::new("trash")
+                .about("List, restore or delete the removed todos")
+                .subcommand(
+                    Command::new("list")
+                        .about("List the removed todos, the last removed first")
+                        .args(output_args()),
+                )
+                .subcommand(
+                    Command::new("restore")
+                        .about("Restore removed todo(s) and link their children again")
+                        .arg(arg!(<TODO>... "The id, id prefix or name of the removed todo(s)"))
+                        .args(output_args()),
+                )
+                .subcommand(
+                    Command::new("empty")
+                        .about("Delete the removed todos for good")
+                        .arg(
+                            arg!(--"older-than" <AGE> "Only delete todos removed longer ago than the age, like 30d or 4w")
+                                .required(false),
+                        )
+                        .arg(
+                            arg!(--auto <AGE> "Delete removed todos automatically after the age, or never with off")
+                                .required(false),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("fsck")
+                .about("Check the files of the repo for problems")
+                .arg(arg!(--fix "Fix the problems found")),
+        )
+        .subcommand(
+            Command::new("storage")
+                .about("Show, convert or compact how the todos are stored")
+                .subcommand(
+                    Command::new("convert")
+                        .about("Move the todos to another storage")
+                        .arg(arg!(<STORAGE> "files, a file per todo, or jsonl, a single append-only log")),
+                )
+                .subcommand(
+                    Command::new("compact")
+                        .about("Rewrite the jsonl log with a line per todo"),
+                ),
+        )
+        .subcommand(
+            Command::new("crypt")
+                .about("Show whether the repo is encrypted, or encrypt, rotate the key of or decrypt it")
+                .subcommand(
+                    Command::new("enable")
+                        .about("Encrypt the todos of the repo with a passphrase")
+                        .arg(new_keyfile_arg()),
+                )
+                .subcommand(
+                    Command::new("rotate")
+                        .about("Encrypt the todos with a new key and passphrase")
+                        .arg(new_keyfile_arg()),
+                )
+                .subcommand(Command::new("disable").about("Decrypt the todos of the repo for good")),
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Show everything about a todo")
+                .arg(arg!(<TODO> "The id, unique id prefix or path of the todo"))
+                .args(output_args()),
+        )
+        .subcommand(
+            Command::new("perspective")
+                .about("Show, save or remove perspectives, the saved queries list @name runs")
+                .arg(arg!([NAME] "The name of the perspective"))
+                .arg(arg!([QUERY] "The query to save as the perspective"))
+                .arg(arg!(--icon <ICON> "The icon shown in the GUI").required(false))
+                .arg(
+                    arg!(--"icon-color" <COLOR> "The color of the icon in the GUI, like #d74044")
+                        .required(false),
+                )
+                .arg(arg!(--completed "Show completed todos too"))
+                .arg(arg!(--remove "Remove the perspective")),
+        )
+        .subcommand(
+            Command::new("format")
+                .about("Show, save or remove format presets for list")
+                .arg(arg!([NAME] "The name of the preset"))
+                .arg(arg!([TEMPLATE] "The template to save as the preset"))
+                .arg(arg!(--remove "Remove the preset")),
+        )
         .subcommand(Command::new("cleancache").about("Clean cache"))
 }
 
+fn new_keyfile_arg() -> Arg {
+    arg!(--"new-keyfile" <FILE> "The file holding the new passphrase").required(false)
+}
+
 fn edit_args() -> Vec<Arg> {
     vec![
         arg!(-n --name <NAME> "Change name of the target").required(false),
@@ -162,6 +900,14 @@ fn edit_args() -> Vec<Arg> {
         arg!(--weight <WEIGHT> "Change weight of the target").required(false),
         arg!(-t --tag <TAGS>... "Bind/unbind tags for the target").required(false),
         arg!(-c --complete <BOOLEAN>... "Complete/uncomplete the target").required(false),
+        arg!(--cancel <BOOLEAN> "Cancel/uncancel the target").required(false),
+        arg!(--estimate <DURATION> "Change estimate of the target, like 45m, 2h or 1h30m, or none")
+            .required(false),
+        arg!(--progress <STRATEGY> "Measure progress of the target and its subtree by count, weight or estimate, with :done to count cancelled todos as done, or default")
+            .required(false),
+        arg!(-i --item <ITEMS>... "Add/remove checklist items for the target").required(false),
+        arg!(--check <ITEMS>... "Check checklist items by index or name").required(false),
+        arg!(--uncheck <ITEMS>... "Uncheck checklist items by index or name").required(false),
     ]
 }
 
@@ -179,12 +925,95 @@ fn filter_args() -> Vec<Arg> {
         arg!(--flogged <LOGGED> "Filter with logged todo(s)").default_value("false"),
         arg!(--ftag <TAGS>... "Filter with tags").required(false),
         arg!(--fname <NAME> "Search with name").required(false),
+        arg!(--"fname-re" <REGEX> "Search with a regex on the name").required(false),
+        arg!(-s --search <TEXT> "Search fuzzily in names, details and tags, by relevance")
+            .required(false),
+        arg!(-q --query <QUERY> "Filter with a query like 'tag:work and not status:done'")
+            .required(false),
+        arg!(--"include-archived" "Look in the archive too"),
+    ]
+}
+
+fn target_args() -> Vec<Arg> {
+    vec![
+        arg!([TODO]... "Target todo(s) by id, unique id prefix or path of names instead of filter(s)"),
+        arg!(--id <ID>... "Target todo(s) by id or unique id prefix instead of filter(s)")
+            .required(false),
+        arg!(--"all-matching" "Target all todo(s) matching the filter(s) without asking"),
+        arg!(-y --yes "Target the only todo matching the filter(s) without asking, failing if none or several match"),
+    ]
+}
+
+/// The icon set from `--icons`, the config or the terminal, and whether to color.
+fn style(matches: &ArgMatches) -> Style {
+    let config = config::ConfigInstance::get();
+    let icons = match matches.get_one::<String>("icons") {
+        Some(n) => n.parse().unwrap_or_else(|err: String| fail(&err)),
+        None => config.icons.unwrap_or_else(IconSet::detect),
+    };
+    let color = matches
+        .get_one::<String>("color")
+        .unwrap()
+        .parse::<ColorMode>()
+        .unwrap_or_else(|err| fail(&err));
+
+    Style {
+        icons,
+        color: color.enabled(),
+        palette: config.palette.unwrap_or_default(),
+    }
+}
+
+fn output_args() -> Vec<Arg> {
+    vec![
+        arg!(-o --output <FORMAT> "Output format: text, json, jsonl, tsv or csv")
+            .default_value("text"),
+    ]
+}
+
+fn output_mode(matches: &ArgMatches) -> OutputMode {
+    let n = matches.get_one::<String>("output").unwrap();
+    n.parse::<OutputMode>().unwrap_or_else(|err| fail(&err))
+}
+
+fn list_args() -> Vec<Arg> {
+    vec![
+        arg!(--sort <KEYS>... "Sort by deadline, date, weight, name, created or completed, with optional :asc/:desc")
+            .required(false),
+        arg!(--limit <LIMIT> "Show at most this many todo(s)").required(false),
+        arg!(--offset <OFFSET> "Skip this many todo(s) first").required(false),
+        arg!(--flat "List matching todo(s) only, without their fathers and children"),
+        arg!(--format <TEMPLATE> "Format each line with a template or a saved preset")
+            .required(false),
     ]
 }
 
 fn process_edit_todo(matches: &ArgMatches, todo: &mut Todo) {
     if let Some(n) = matches.get_one::<String>("complete") {
-        todo.completed = n.eq("true");
+        todo.set_completed(n.eq("true"));
+    }
+
+    if let Some(n) = matches.get_one::<String>("cancel") {
+        todo.cancelled = n.eq("true");
+    }
+
+    if let Some(n) = matches.get_one::<String>("estimate") {
+        todo.estimate = if n == "none" {
+            None
+        } else {
+            Some(
+                template::parse_minutes(n)
+                    .unwrap_or_else(|| fail(&format!("Invalid estimate: {n}"))),
+            )
+        };
+    }
+
+    if let Some(n) = matches.get_one::<String>("progress") {
+        todo.progress = if n == "default" {
+            None
+        } else {
+            Some(n.parse::<Strategy>().unwrap_or_else(|err| fail(&err)))
+        };
     }
 
     if let Some(n) = matches.get_one::<String>("name") {
@@ -192,7 +1021,7 @@ fn process_edit_todo(matches: &ArgMatches, todo: &mut Todo) {
     }
 
     if let Some(n) = matches.get_one::<String>("details") {
-        todo.metadata.details = n.to_owned();
+        todo.metadata.set_details(n.to_owned());
     }
 
     if let Some(n) = matches.get_one::<String>("weight") {
@@ -237,27 +1066,347 @@ fn process_edit_todo(matches: &ArgMatches, todo: &mut Todo) {
             }
         }
     }
+
+    if let Some(ns) = matches.get_many::<String>("item") {
+        for n in ns {
+            if let Some(x) = n.strip_suffix('!') {
+                if let Some(index) = find_check_item(todo, x) {
+                    todo.metadata.checklist.remove(index);
+                }
+            } else {
+                todo.metadata.checklist.push(CheckItem::new(n.to_owned()));
+            }
+        }
+    }
+
+    for (arg, checked) in [("check", true), ("uncheck", false)] {
+        if let Some(ns) = matches.get_many::<String>(arg) {
+            for n in ns {
+                if let Some(index) = find_check_item(todo, n) {
+                    todo.metadata.checklist[index].checked = checked;
+                }
+            }
+        }
+    }
+}
+
+/// Find a checklist item by its 1-based index or its case-insensitive name.
+fn find_check_item(todo: &Todo, key: &str) -> Option<usize> {
+    if let Ok(index) = key.parse::<usize>() {
+        if index > 0 && index <= todo.metadata.checklist.len() {
+            return Some(index - 1);
+        }
+    }
+
+    todo.metadata
+        .checklist
+        .iter()
+        .position(|item| item.name.to_lowercase().eq(&key.to_lowercase()))
+}
+
+/// Find a todo by its id, a unique prefix of its hex id or the path of its names.
+fn find_todo(instance: &TodoInstance, reference: &str) -> u64 {
+    let candidates = match instance.find(reference) {
+        Ok(id) => return id,
+        Err(PathError::NotFound(segment)) => fail(&format!("Todo not found: {segment}")),
+        Err(PathError::Ambiguous(candidates)) => candidates,
+    };
+
+    let short_ids = instance.short_ids();
+    eprintln!("{reference} is ambiguous, candidates:");
+    for id in candidates {
+        eprintln!("  {} {}", short_ids[&id], instance.todo_path(id));
+    }
+    std::process::exit(1)
+}
+
+/// Find a todo by its full id or a unique prefix of its hex id.
+fn find_by_id(instance: &TodoInstance, reference: &str) -> Option<u64> {
+    if let Ok(id) = reference.parse::<u64>() {
+        if instance.get(id).is_some() {
+            return Some(id);
+        }
+    }
+
+    match instance.find_by_prefix(reference).as_slice() {
+        [] => None,
+        [id] => Some(*id),
+        ids => fail(&format!(
+            "Id prefix {reference} is ambiguous between {} todos",
+            ids.len()
+        )),
+    }
+}
+
+/// Find the list in which both todos can be ordered against each other.
+fn sibling_scope(
+    instance: &TodoInstance,
+    id: u64,
+    target: u64,
+    view: &str,
+) -> (OrderScope, Vec<u64>) {
+    let todo = instance.get(id).unwrap();
+    let target_todo = instance.get(target).unwrap();
+
+    if let Some(father) = todo
+        .dependents
+        .iter()
+        .find(|dep| target_todo.dependents.contains(dep))
+    {
+        (OrderScope::Parent(*father), instance.children_once(*father))
+    } else if todo.dependents.is_empty() && target_todo.dependents.is_empty() {
+        (
+            OrderScope::View(view.to_string()),
+            instance
+                .todos
+                .iter()
+                .filter(|t| t.dependents.is_empty())
+                .map(|t| t.id())
+                .collect(),
+        )
+    } else {
+        fail("The todos don't share a father, so they can't be ordered against each other")
+    }
+}
+
+/// Days from an age like `30d`, `4w` or `30`.
+fn parse_days(string: &str) -> Option<u32> {
+    if let Some(n) = string.strip_suffix('w') {
+        n.parse::<u32>().ok().map(|weeks| weeks * 7)
+    } else {
+        string.strip_suffix('d').unwrap_or(string).parse().ok()
+    }
+}
+
+/// Apply the archive and trash policies of the repo to the instance of a
+/// command changing its todos, before it does.
+fn apply_policies(instance: &mut TodoInstance) {
+    if let Err(err) = instance.archive_by_policy() {
+        fail(&format!("Can't archive by the policy of the repo: {err}"));
+    }
+    instance.purge_trash_by_policy();
+}
+
+/// Unlock the encrypted repo with the passphrase in `--keyfile` or the
+/// environment, or else typed in.
+fn unlock(matches: &ArgMatches) {
+    let passphrase = match matches.get_one::<String>("keyfile") {
+        Some(file) => crypt::read_keyfile(file).map(Some),
+        None => crypt::passphrase_from_env(),
+    }
+    .unwrap_or_else(|err| fail(&err))
+    .or_else(|| {
+        std::io::stdin()
+            .is_terminal()
+            .then(|| rpassword::prompt_password("Passphrase: ").ok())
+            .flatten()
+    })
+    .unwrap_or_else(|| {
+        fail("The repo is encrypted, give the passphrase with TUFFOUS_PASSPHRASE, TUFFOUS_KEYFILE or --keyfile")
+    });
+    if let Err(err) = crypt::unlock(".", &passphrase) {
+        fail(&format!("Can't unlock the repo: {err}"));
+    }
+}
+
+/// The new passphrase in `--new-keyfile` or `TUFFOUS_NEW_PASSPHRASE`, or else
+/// typed in twice.
+fn new_passphrase(matches: &ArgMatches) -> String {
+    let passphrase = if let Some(file) = matches.get_one::<String>("new-keyfile") {
+        crypt::read_keyfile(file).unwrap_or_else(|err| fail(&err))
+    } else if let Ok(passphrase) = std::env::var("TUFFOUS_NEW_PASSPHRASE") {
+        passphrase
+    } else if std::io::stdin().is_terminal() {
+        let passphrase = rpassword::prompt_password("New passphrase: ")
+            .unwrap_or_else(|err| fail(&err.to_string()));
+        let again = rpassword::prompt_password("Type it again: ")
+            .unwrap_or_else(|err| fail(&err.to_string()));
+        if passphrase != again {
+            fail("The passphrases don't match");
+        }
+        passphrase
+    } else {
+        fail("Give the new passphrase with TUFFOUS_NEW_PASSPHRASE or --new-keyfile")
+    };
+    if passphrase.is_empty() {
+        fail("The passphrase can't be empty");
+    }
+    passphrase
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1)
 }
 
 struct TodoScanner {
     pub instance: TodoInstance,
     pub cache: Vec<u64>,
+    pub matched: Vec<u64>,
+    pub short_ids: HashMap<u64, String>,
+    pub config: RepoConfig,
+    pub sorts: Vec<Sort>,
+    pub flat: bool,
+    pub limit: Option<usize>,
+    pub offset: usize,
+    pub output: OutputMode,
+    pub template: Option<Template>,
+    pub style: Style,
+    /// Scores of the todos matched by a search, which lists are ordered by.
+    pub relevance: HashMap<u64, i64>,
+    pub depth: Option<usize>,
+    pub collapse_completed: bool,
 }
 
 impl TodoScanner {
-    pub fn new(instance: TodoInstance) -> Self {
+    pub fn new(instance: TodoInstance, style: Style) -> Self {
         TodoScanner {
             instance,
             cache: Vec::new(),
+            matched: Vec::new(),
+            short_ids: HashMap::new(),
+            config: RepoConfig::get("."),
+            sorts: Vec::new(),
+            flat: false,
+            limit: None,
+            offset: 0,
+            output: OutputMode::Text,
+            template: None,
+            style,
+            relevance: HashMap::new(),
+            depth: None,
+            collapse_completed: false,
+        }
+    }
+
+    /// Select the target todos of a command, through the targeting arguments
+    /// or by asking the user to choose from the filtered todos.
+    pub fn select(&self, matches: &ArgMatches) -> Vec<u64> {
+        if let Some(ns) = matches.get_many::<String>("id") {
+            return ns
+                .map(|n| {
+                    find_by_id(&self.instance, n)
+                        .unwrap_or_else(|| fail(&format!("Todo not found: {n}")))
+                })
+                .collect();
+        }
+
+        if let Some(ns) = matches.get_many::<String>("TODO") {
+            return ns.map(|n| find_todo(&self.instance, n)).collect();
+        }
+
+        if matches.get_flag("all-matching") {
+            return self.matched.clone();
+        }
+
+        if matches.get_flag("yes") {
+            return match self.matched.len() {
+                0 => fail("No todo matches the filter(s)"),
+                1 => self.matched.clone(),
+                n => fail(&format!(
+                    "{n} todos match the filter(s), use --all-matching or narrow them down"
+                )),
+            };
+        }
+
+        if !std::io::stdin().is_terminal() {
+            fail("Can't ask for a selection as stdin is not a terminal, use --id, --all-matching or --yes")
+        }
+
+        self.list(true)
+    }
+
+    pub fn apply_list_options(&mut self, matches: &ArgMatches) {
+        if let Some(ns) = matches.get_many::<String>("sort") {
+            for n in ns {
+                match n.parse::<Sort>() {
+                    Ok(sort) => self.sorts.push(sort),
+                    Err(err) => fail(&err),
+                }
+            }
+        }
+
+        self.flat = matches.get_flag("flat");
+
+        if let Some(n) = matches.get_one::<String>("limit") {
+            self.limit = Some(
+                n.parse::<usize>()
+                    .unwrap_or_else(|_| fail(&format!("Invalid limit: {n}"))),
+            );
+        }
+
+        if let Some(n) = matches.get_one::<String>("format") {
+            let config = config::ConfigInstance::get();
+            let template = config.formats.get(n).unwrap_or(n);
+            self.template = Some(Template::parse(template).unwrap_or_else(|err| fail(&err)));
+        }
+
+        if let Some(n) = matches.get_one::<String>("offset") {
+            self.offset = n
+                .parse::<usize>()
+                .unwrap_or_else(|_| fail(&format!("Invalid offset: {n}")));
         }
     }
 
     pub fn apply_filters(&mut self, matches: &ArgMatches) {
+        if matches.get_flag("include-archived") {
+            self.instance.read_archive();
+        }
         self.cache.clear();
+        self.matched.clear();
+        self.short_ids = self.instance.short_ids();
+        let mut query = matches
+            .get_one::<String>("query")
+            .map(|n| Query::parse(n).unwrap_or_else(|err| fail(&err)));
+        if let Some(perspective) = self.perspective(matches) {
+            let perspective = Query::parse(&perspective.query).unwrap_or_else(|err| {
+                fail(&format!(
+                    "Invalid query of perspective {}: {err}",
+                    perspective.name
+                ))
+            });
+            query = Some(match query {
+                Some(query) => Query::And(Box::new(perspective), Box::new(query)),
+                None => perspective,
+            });
+        }
+        let query = query.map(|query| query.resolve(&self.instance));
+
+        // Searches filter the todos and rank them by relevance
+        self.relevance.clear();
+        let mut searched = false;
+        if let Some(n) = matches.get_one::<String>("search") {
+            searched = true;
+            for hit in self.instance.search(&Matcher::words(n)) {
+                self.relevance.insert(hit.id, hit.score);
+            }
+        }
+        if let Some(n) = matches.get_one::<String>("fname-re") {
+            let regex = Matcher::regex(n).unwrap_or_else(|err| fail(&err));
+            let mut relevance = HashMap::new();
+            for todo in &self.instance.todos {
+                if let Some((score, _)) = regex.find(&todo.metadata.name) {
+                    let previous = self.relevance.get(&todo.id()).copied().unwrap_or(0);
+                    if !searched || previous > 0 {
+                        relevance.insert(todo.id(), previous + score);
+                    }
+                }
+            }
+            self.relevance = relevance;
+            searched = true;
+        }
+
         for todo_id in self.instance.todos() {
-            if !self.cache.contains(&todo_id)
-                && Self::match_filters(matches, self.instance.get(todo_id).unwrap(), true)
-            {
+            let matched = self.match_filters(matches, self.instance.get(todo_id).unwrap(), true)
+                && query
+                    .as_ref()
+                    .is_none_or(|query| query.matches(&self.instance, todo_id))
+                && (!searched || self.relevance.contains_key(&todo_id));
+            if matched {
+                self.matched.push(todo_id);
+            }
+
+            if !self.cache.contains(&todo_id) && matched {
                 self.cache.push(todo_id);
                 for father_todo_id in self.instance.all_deps(todo_id) {
                     if !self.cache.contains(&father_todo_id) {
@@ -267,7 +1416,7 @@ impl TodoScanner {
 
                 for child_todo_id in self.instance.children(todo_id) {
                     if !self.cache.contains(&child_todo_id)
-                        && Self::match_filters(matches, self.instance.get(todo_id).unwrap(), false)
+                        && self.match_filters(matches, self.instance.get(todo_id).unwrap(), false)
                     {
                         self.cache.push(child_todo_id);
                     }
@@ -276,8 +1425,25 @@ impl TodoScanner {
         }
     }
 
-    fn match_filters(matches: &ArgMatches, todo: &Todo, strict: bool) -> bool {
-        if matches.get_one::<String>("flogged").unwrap().eq("true") {
+    /// The perspective named by the `@name` argument of the command.
+    fn perspective(&self, matches: &ArgMatches) -> Option<&Perspective> {
+        let n = matches.try_get_one::<String>("PERSPECTIVE").ok()??;
+        let name = n.strip_prefix('@').unwrap_or(n);
+        Some(
+            self.config
+                .perspective(name)
+                .unwrap_or_else(|| fail(&format!("Perspective not found: {name}"))),
+        )
+    }
+
+    fn match_filters(&self, matches: &ArgMatches, todo: &Todo, strict: bool) -> bool {
+        // A query decides on completed todos itself, unless --flogged is given
+        let by_query = (matches.get_one::<String>("query").is_some()
+            || self.perspective(matches).is_some_and(|p| p.completed))
+            && matches.value_source("flogged") == Some(ValueSource::DefaultValue);
+        if by_query {
+            // Checked by the query
+        } else if matches.get_one::<String>("flogged").unwrap().eq("true") {
             if !todo.completed {
                 return false;
             }
@@ -421,40 +1587,78 @@ impl TodoScanner {
 
     pub fn list(&self, choose: bool) -> Vec<u64> {
         let mut vec = Vec::new();
+        let mut roots = Vec::new();
+
+        if self.flat {
+            roots = self.matched.clone();
+        } else {
+            for todo_id in &self.cache {
+                let todo = self.instance.get(*todo_id).unwrap();
+                let mut has_dep = false;
+                for dep in &todo.dependents {
+                    if self.cache.contains(dep) {
+                        has_dep = true;
+                        break;
+                    }
+                }
 
-        for todo_id in &self.cache {
-            let todo = self.instance.get(*todo_id).unwrap();
-            let mut has_dep = false;
-            for dep in &todo.dependents {
-                if self.cache.contains(dep) {
-                    has_dep = true;
-                    break;
+                if !has_dep {
+                    roots.push(*todo_id);
                 }
             }
+        }
 
-            if !has_dep {
-                vec.append(&mut self.as_tree(*todo_id, &self.cache));
+        self.sort(&mut roots, &OrderScope::View(String::from("all")));
+        for root in roots
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+        {
+            if self.flat {
+                vec.push(FormattedTodo::of(root, self.format_line(root, 0), 0));
+            } else {
+                vec.append(&mut self.as_tree(root, &self.cache, 0));
             }
         }
 
+        if !choose && !self.output.is_text() {
+            let records: Vec<TodoRecord> = vec
+                .iter()
+                .filter_map(|todo| {
+                    TodoRecord::of(&self.instance, &self.short_ids, todo.id, todo.depth)
+                })
+                .collect();
+            output::print_records(self.output, &records);
+            return Vec::new();
+        }
+
+        // Keep the standard output clean for machine-readable results
+        let print = |line: String| {
+            if self.output.is_text() {
+                println!("{line}");
+            } else {
+                eprintln!("{line}");
+            }
+        };
+
         if vec.is_empty() {
             Vec::new()
         } else {
-            println!("{} todos:", vec.len());
+            print(format!("{} todos:", vec.len()));
             if choose {
                 for todo in vec.iter().enumerate() {
-                    println!("[{}] {}", todo.0 + 1, todo.1.string);
+                    print(format!("[{}] {}", todo.0 + 1, todo.1.string));
                 }
 
-                println!("\nPlease enter your selection:");
+                print(String::from("\nPlease enter your selection:"));
 
                 let mut ret_vec = Vec::new();
 
                 for sel in parse_selection(&input_string()) {
-                    for v in vec.iter().enumerate() {
-                        if v.0 == sel as usize - 1 {
-                            ret_vec.push(v.1.id);
-                        }
+                    if let Some(v) = sel.checked_sub(1).and_then(|i| vec.get(i as usize)) {
+                        ret_vec.push(v.id);
+                    } else {
+                        eprintln!("Selection out of range: {sel}");
                     }
                 }
 
@@ -468,61 +1672,624 @@ impl TodoScanner {
         }
     }
 
-    fn as_tree(&self, id: u64, range: &Vec<u64>) -> Vec<FormattedTodo> {
-        let todo = self.instance.get(id).unwrap();
-        let mut vec = Vec::new();
+    /// Print the todo with all of its children.
+    pub fn show_tree(&mut self, id: u64) {
+        self.cache = vec![id];
+        self.cache.append(&mut self.instance.children(id));
+        self.matched = self.cache.clone();
+        self.short_ids = self.instance.short_ids();
+        self.list(false);
+    }
+
+    /// Draw the filtered todos, or the ones under the root, as a tree.
+    pub fn tree(&self, root: Option<u64>) {
+        let mut roots = Vec::new();
+        let mut range = self.cache.clone();
+        if let Some(root) = root {
+            let children = self.instance.children(root);
+            range.retain(|id| children.contains(id));
+            range.push(root);
+            roots.push(root);
+        } else {
+            for todo_id in &range {
+                let todo = self.instance.get(*todo_id).unwrap();
+                if !todo.dependents.iter().any(|dep| range.contains(dep)) {
+                    roots.push(*todo_id);
+                }
+            }
+            self.sort(&mut roots, &OrderScope::View(String::from("all")));
+        }
+
+        let mut lines = Vec::new();
+        let mut drawn = Vec::new();
+        for root in roots {
+            self.draw_tree(root, &range, "", None, 0, &mut drawn, &mut lines);
+        }
+
+        if self.output.is_text() {
+            for line in &lines {
+                println!("{}", line.string);
+            }
+        } else {
+            let records: Vec<TodoRecord> = lines
+                .iter()
+                .filter_map(|todo| {
+                    TodoRecord::of(&self.instance, &self.short_ids, todo.id, todo.depth)
+                })
+                .collect();
+            output::print_records(self.output, &records);
+        }
+    }
+
+    /// Draw the todo with box-drawing connectors, where `last` is whether it's
+    /// the last of its siblings and is `None` for the roots.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_tree(
+        &self,
+        id: u64,
+        range: &Vec<u64>,
+        prefix: &str,
+        last: Option<bool>,
+        depth: usize,
+        drawn: &mut Vec<u64>,
+        lines: &mut Vec<FormattedTodo>,
+    ) {
+        let connector = match last {
+            None => String::new(),
+            Some(true) => format!("{} ", self.style.icons.icon(Icon::LastBranch)),
+            Some(false) => format!("{} ", self.style.icons.icon(Icon::Branch)),
+        };
+        let mut children = self.instance.children_once(id);
+        children.retain(|child| range.contains(child));
+        self.sort(&mut children, &OrderScope::Parent(id));
 
-        vec.push(FormattedTodo::of(
+        // Todos with several fathers are only expanded the first time they're drawn
+        let repeated = drawn.contains(&id);
+        drawn.push(id);
+
+        let mut mark = String::new();
+        if repeated && !children.is_empty() {
+            mark = String::from(" (see above)");
+        } else if repeated {
+            mark = String::from(" (also above)");
+        } else if !children.is_empty()
+            && (self.depth.is_some_and(|max| depth >= max)
+                || (self.collapse_completed && self.instance.get(id).unwrap().completed))
+        {
+            let mut hidden = self.instance.children(id);
+            hidden.sort();
+            hidden.dedup();
+            hidden.retain(|child| range.contains(child));
+            mark = format!(" (+{} hidden)", hidden.len());
+        }
+
+        lines.push(FormattedTodo::of(
             id,
             format!(
-                "{}{}",
-                format_todo(todo),
-                if self.instance.children_once(id).is_empty() {
-                    String::new()
-                } else {
-                    format!(
-                        " ({}/{})",
-                        self.instance.weight(id, true),
-                        self.instance.weight(id, false)
-                    )
-                }
+                "{prefix}{connector}{}{}",
+                self.todo_line(id),
+                self.style.paint(&mark, Some(self.style.palette.gray))
             ),
+            depth,
         ));
-        for child in self.instance.children_once(id) {
-            if range.contains(&child) {
-                for mut i in self.as_tree(child, range) {
-                    i.string = format!("   {}", i.string);
-                    vec.push(i);
+        if !mark.is_empty() {
+            return;
+        }
+
+        let prefix = match last {
+            None => String::new(),
+            Some(true) => format!("{prefix}   "),
+            Some(false) => format!("{prefix}{:<3}", self.style.icons.icon(Icon::Pipe)),
+        };
+        let count = children.len();
+        for (index, child) in children.into_iter().enumerate() {
+            self.draw_tree(
+                child,
+                range,
+                &prefix,
+                Some(index + 1 == count),
+                depth + 1,
+                drawn,
+                lines,
+            );
+        }
+    }
+
+    /// Print the overdue todos, then the todos of every day from today on.
+    pub fn agenda(&mut self, days: u32, undated: bool) {
+        let now = Local::now().naive_local();
+        let mut agenda = self.instance.agenda(&self.matched, now, days);
+        self.sort(
+            &mut agenda.overdue,
+            &OrderScope::View(String::from("today")),
+        );
+        self.sort(
+            &mut agenda.undated,
+            &OrderScope::View(String::from("anytime")),
+        );
+        if !undated {
+            agenda.undated.clear();
+        }
+
+        if !self.output.is_text() {
+            let record = |section: String, entry: Option<&str>, id: u64| {
+                TodoRecord::of(&self.instance, &self.short_ids, id, 0).map(|todo| AgendaRecord {
+                    section,
+                    entry: entry.map(String::from),
+                    todo,
+                })
+            };
+            let mut records = Vec::new();
+            for id in &agenda.overdue {
+                records.extend(record(String::from("overdue"), None, *id));
+            }
+            for (day, entries) in &agenda.days {
+                for (id, entry) in entries {
+                    let entry = match entry {
+                        AgendaEntry::Date => "date",
+                        AgendaEntry::Deadline(_) => "deadline",
+                    };
+                    records.extend(record(day.to_string(), Some(entry), *id));
                 }
             }
+            for id in &agenda.undated {
+                records.extend(record(String::from("undated"), None, *id));
+            }
+            output::print_agenda(self.output, &records);
+            return;
+        }
+
+        let line = |time: Option<NaiveDateTime>, id: u64| {
+            format!(
+                "   {:<5} {}",
+                time.map(|t| t.format("%H:%M").to_string())
+                    .unwrap_or_default(),
+                self.todo_line(id)
+            )
+        };
+        let heading = |text: String, color: Rgb| {
+            println!("{}", self.style.paint(&text, Some(color)));
+        };
+
+        if !agenda.overdue.is_empty() {
+            heading(String::from("Overdue"), self.style.palette.flag);
+            for id in &agenda.overdue {
+                println!("{}", line(None, *id));
+            }
+            println!();
+        }
+
+        for (day, entries) in &agenda.days {
+            if entries.is_empty() && *day != now.date() {
+                continue;
+            }
+
+            let title = match (*day - now.date()).num_days() {
+                0 => format!("Today, {}", day.format("%a %b %-d")),
+                1 => format!("Tomorrow, {}", day.format("%a %b %-d")),
+                _ => day.format("%a %b %-d").to_string(),
+            };
+            heading(
+                title,
+                if *day == now.date() {
+                    self.style.palette.star
+                } else {
+                    self.style.palette.blue_green
+                },
+            );
+            if entries.is_empty() {
+                println!("   Nothing to do");
+            }
+            for (id, entry) in entries {
+                let time = match entry {
+                    AgendaEntry::Date => None,
+                    AgendaEntry::Deadline(ddl) => Some(*ddl),
+                };
+                println!("{}", line(time, *id));
+            }
+            println!();
+        }
+
+        if !agenda.undated.is_empty() {
+            heading(String::from("Undated"), self.style.palette.gray);
+            for id in &agenda.undated {
+                println!("{}", line(None, *id));
+            }
+        }
+    }
+
+    /// Print the statistics of the matched todos.
+    pub fn stats(&self, period: Period) {
+        let stats = self
+            .instance
+            .stats(&self.matched, period, Local::now().naive_local());
+        if !self.output.is_text() {
+            output::print_stats(self.output, &stats);
+            return;
+        }
+
+        let heading = |text: &str| {
+            println!(
+                "{}",
+                self.style.paint(text, Some(self.style.palette.blue_green))
+            );
+        };
+        let plural = |count: u32| if count == 1 { "" } else { "s" };
+
+        println!(
+            "Todos      {} ({} open, {} completed, {})",
+            stats.total,
+            stats.open,
+            stats.completed,
+            self.style.paint(
+                &format!("{} overdue", stats.overdue),
+                (stats.overdue > 0).then_some(self.style.palette.flag)
+            )
+        );
+        if let Some(days) = stats.average_cycle_days {
+            println!("Cycle time {days:.1} days on average");
+        }
+        println!(
+            "Streak     {} day{} (longest {})",
+            stats.current_streak,
+            plural(stats.current_streak),
+            stats.longest_streak
+        );
+
+        if !stats.periods.is_empty() {
+            println!();
+            let title = match period {
+                Period::Day => "Day",
+                Period::Week => "Week",
+                Period::Month => "Month",
+            };
+            heading(&format!("{title:<12} {:>8} {:>10}", "Created", "Completed"));
+            for p in &stats.periods {
+                println!("{:<12} {:>8} {:>10}", p.period, p.created, p.completed);
+            }
+        }
+
+        for (title, groups) in [("Tag", &stats.tags), ("Project", &stats.projects)] {
+            if groups.is_empty() {
+                continue;
+            }
+            println!();
+            let width = groups
+                .iter()
+                .map(|g| g.name.chars().count())
+                .max()
+                .unwrap_or(0)
+                .max(title.len());
+            heading(&format!(
+                "{title:<width$} {:>6} {:>10} {:>8}",
+                "Todos", "Completed", "Weight"
+            ));
+            for g in groups {
+                println!(
+                    "{:<width$} {:>6} {:>10} {:>8}",
+                    g.name,
+                    g.total,
+                    g.completed,
+                    format!("{}/{}", g.completed_weight, g.weight)
+                );
+            }
+        }
+    }
+
+    /// Move the todos to the archive, printing them.
+    pub fn archive(&mut self, ids: &[u64], dry_run: bool) {
+        if self.output.is_text() {
+            self.short_ids = self.instance.short_ids();
+            for id in ids {
+                println!("{}", self.todo_line(*id));
+            }
+        } else {
+            output::report(&self.instance, ids, self.output);
+        }
+
+        if !dry_run {
+            if let Err(err) = self.instance.archive(ids) {
+                fail(&format!("Can't archive every todo: {err}"));
+            }
+        }
+        if self.output.is_text() {
+            println!(
+                "{} {} todo(s)",
+                if dry_run { "Would archive" } else { "Archived" },
+                ids.len()
+            );
+        }
+    }
+
+    /// Print the removed todos, the last removed first.
+    pub fn trash(&self) {
+        let trashed = self.instance.trashed();
+        let mut trash = TodoInstance::create(".");
+        trash.todos = trashed.iter().map(|t| t.todo.clone()).collect();
+        let short_ids = trash.short_ids();
+
+        if !self.output.is_text() {
+            let records: Vec<TrashRecord> = trashed
+                .iter()
+                .filter_map(|t| {
+                    Some(TrashRecord {
+                        deleted: t.deleted,
+                        linked_children: t.children.iter().map(|c| c.to_string()).collect(),
+                        todo: TodoRecord::of(&trash, &short_ids, t.todo.id(), 0)?,
+                    })
+                })
+                .collect();
+            output::print_trash(self.output, &records);
+            return;
+        }
+
+        if trashed.is_empty() {
+            println!("The trash is empty");
+            return;
+        }
+        let now = Utc::now().naive_utc();
+        for t in &trashed {
+            println!(
+                "{} {} {}",
+                short_ids.get(&t.todo.id()).cloned().unwrap_or_default(),
+                format_todo(&t.todo, &self.style),
+                self.style
+                    .dim(&format!("removed {}", relative_time(t.deleted, now)))
+            );
+        }
+    }
+
+    /// Print every field of the todo, its ancestors and its subtree.
+    pub fn show(&mut self, id: u64) {
+        self.cache = vec![id];
+        self.cache.append(&mut self.instance.children(id));
+        self.short_ids = self.instance.short_ids();
+
+        let mut ancestors = Vec::new();
+        for dep in self.instance.all_deps(id) {
+            if !ancestors.contains(&dep) {
+                ancestors.push(dep);
+            }
+        }
+        let subtree: Vec<FormattedTodo> = self
+            .as_tree(id, &self.cache, 0)
+            .into_iter()
+            .skip(1)
+            .collect();
+
+        if !self.output.is_text() {
+            let subtree: Vec<(u64, usize)> = subtree.iter().map(|t| (t.id, t.depth)).collect();
+            if let Some(detail) =
+                TodoDetail::of(&self.instance, &self.short_ids, id, &ancestors, &subtree)
+            {
+                output::print_detail(self.output, &detail);
+            }
+            return;
+        }
+
+        let todo = self.instance.get(id).unwrap();
+        let now = Local::now().naive_local();
+        let field = |name: &str, value: String| println!("{name:<12}{value}");
+
+        println!("{}", todo.metadata.name);
+        field(
+            "id",
+            format!(
+                "{} ({})",
+                id,
+                self.short_ids.get(&id).cloned().unwrap_or_default()
+            ),
+        );
+        field("path", self.instance.todo_path(id));
+        field(
+            "status",
+            template::todo_value(&self.instance, &self.short_ids, id, 0, "status"),
+        );
+        field(
+            "created",
+            format!(
+                "{} ({})",
+                todo.creation_date().format("%Y-%m-%d %H:%M"),
+                relative_time(*todo.creation_date(), now)
+            ),
+        );
+        if let Some(completion) = todo.completion_date() {
+            field(
+                "completed",
+                format!(
+                    "{} ({})",
+                    completion.format("%Y-%m-%d %H:%M"),
+                    relative_time(*completion, now)
+                ),
+            );
+        }
+        if let Some(date) = todo.time {
+            field(
+                "date",
+                format!("{} ({})", date, relative_date(date, now.date())),
+            );
+        }
+        if let Some(ddl) = todo.deadline {
+            field(
+                "deadline",
+                format!(
+                    "{} ({})",
+                    ddl.format("%Y-%m-%d %H:%M"),
+                    relative_time(ddl, now)
+                ),
+            );
+        }
+        field("weight", todo.weight.to_string());
+        if let Some(estimate) = todo.estimate {
+            field("estimate", template::format_minutes(estimate));
+        }
+        if !todo.tags.is_empty() {
+            field("tags", todo.tags.join(", "));
+        }
+        if self.instance.has_progress(id) {
+            field(
+                "progress",
+                format!(
+                    "{} ({}%, by {})",
+                    template::todo_value(&self.instance, &self.short_ids, id, 0, "progress"),
+                    template::todo_value(&self.instance, &self.short_ids, id, 0, "percent"),
+                    self.instance.strategy(id)
+                ),
+            );
+        }
+
+        if !todo.metadata.details().is_empty() {
+            println!("\nDetails:");
+            for line in todo.metadata.details().lines() {
+                println!("   {line}");
+            }
+        }
+
+        if !todo.metadata.checklist.is_empty() {
+            println!("\nChecklist:");
+            for (index, item) in todo.metadata.checklist.iter().enumerate() {
+                println!(
+                    "   {}. [{}] {}",
+                    index + 1,
+                    if item.checked { "x" } else { " " },
+                    item.name
+                );
+            }
+        }
+
+        if !ancestors.is_empty() {
+            println!("\nAncestors:");
+            for dep in &ancestors {
+                println!(
+                    "   {} {}",
+                    self.short_ids.get(dep).cloned().unwrap_or_default(),
+                    self.instance.todo_path(*dep)
+                );
+            }
+        }
+
+        if !subtree.is_empty() {
+            println!("\nChildren:");
+            for child in &subtree {
+                println!("{}", child.string);
+            }
+        }
+    }
+
+    /// Sort by the keys given to the scanner, or by the manual order in the scope
+    /// and then the relevance of a search.
+    fn sort(&self, ids: &mut [u64], scope: &OrderScope) {
+        if self.sorts.is_empty() {
+            self.instance.sort(ids, scope, &self.config.secondary_sort);
+            if !self.relevance.is_empty() {
+                ids.sort_by_cached_key(|id| -self.relevance_of(*id));
+            }
+        } else {
+            self.instance.sort_by_keys(ids, &self.sorts);
+        }
+    }
+
+    /// The best search score of the todo and its children.
+    fn relevance_of(&self, id: u64) -> i64 {
+        std::iter::once(id)
+            .chain(self.instance.children(id))
+            .filter_map(|id| self.relevance.get(&id))
+            .max()
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn format_line(&self, id: u64, depth: usize) -> String {
+        if let Some(template) = &self.template {
+            return template.render(&|field| {
+                template::todo_value(&self.instance, &self.short_ids, id, depth, field)
+            });
+        }
+
+        format!(
+            "{}{} {}",
+            "   ".repeat(depth),
+            self.style.icons.icon(Icon::LastBranch),
+            self.todo_line(id)
+        )
+    }
+
+    /// The short id, the todo and its progress.
+    fn todo_line(&self, id: u64) -> String {
+        format!(
+            "{} {}{}",
+            self.short_ids.get(&id).cloned().unwrap_or_default(),
+            format_todo(self.instance.get(id).unwrap(), &self.style),
+            match self.instance.progress(id) {
+                Some(progress) if self.instance.has_progress(id) => {
+                    format!(" ({}/{})", progress.done, progress.total)
+                }
+                _ => String::new(),
+            }
+        )
+    }
+
+    fn as_tree(&self, id: u64, range: &Vec<u64>, depth: usize) -> Vec<FormattedTodo> {
+        let mut vec = Vec::new();
+
+        vec.push(FormattedTodo::of(id, self.format_line(id, depth), depth));
+        let mut children = self.instance.children_once(id);
+        self.sort(&mut children, &OrderScope::Parent(id));
+        for child in children {
+            if range.contains(&child) {
+                vec.append(&mut self.as_tree(child, range, depth + 1));
+            }
         }
 
         vec
     }
 }
 
-fn format_todo(todo: &Todo) -> String {
-    format!(
-        "└─ {}{}{} {}{}{}",
+fn format_todo(todo: &Todo, style: &Style) -> String {
+    let icons = style.icons;
+    let palette = &style.palette;
+    let now = Local::now().naive_local();
+    let overdue = todo.deadline.is_some_and(|ddl| ddl <= now);
+    let due_today = !overdue && todo.deadline.is_some_and(|ddl| ddl.date() == now.date());
+    // Completed and cancelled todos are dimmed as a whole instead
+    let paint = |text: &str, color: Option<Rgb>| {
+        if todo.completed || todo.cancelled {
+            text.to_string()
+        } else {
+            style.paint(text, color)
+        }
+    };
+
+    let string = format!(
+        "{}{}{} {}{}{}",
         {
             let mut flags = String::new();
 
             if let Some(date) = &todo.time {
-                if date.eq(&Local::now().date_naive()) {
-                    flags = format!("{flags}");
+                if date.eq(&now.date()) {
+                    flags = format!(
+                        "{flags}{}",
+                        paint(icons.icon(Icon::Today), Some(palette.star))
+                    );
                 }
             }
 
             if todo.completed {
-                flags = format!("{flags}󰄲");
+                flags = format!("{flags}{}", icons.icon(Icon::Completed));
+            } else if todo.cancelled {
+                flags = format!("{flags}{}", icons.icon(Icon::Cancelled));
             }
 
-            if let Some(ddl) = &todo.deadline {
-                if ddl <= &Local::now().naive_local() {
-                    flags = format!("{flags}󱂴");
-                } else if ddl.date().eq(&Local::now().date_naive()) {
-                    flags = format!("{flags}󰈽");
-                }
+            if overdue {
+                flags = format!(
+                    "{flags}{}",
+                    paint(icons.icon(Icon::Overdue), Some(palette.flag))
+                );
+            } else if due_today {
+                flags = format!(
+                    "{flags}{}",
+                    paint(icons.icon(Icon::DueToday), Some(palette.star))
+                );
             }
 
             if flags.is_empty() {
@@ -531,21 +2298,21 @@ fn format_todo(todo: &Todo) -> String {
                 format!("{flags} ")
             }
         },
-        if todo.metadata.details.is_empty() {
+        if todo.metadata.details().is_empty() {
             todo.metadata.name.to_owned()
         } else {
-            format!("{}: {}", todo.metadata.name, todo.metadata.details)
+            format!("{}: {}", todo.metadata.name, todo.metadata.details())
         },
         {
             let mut temp = String::new();
             for tag in &todo.tags {
-                temp = format!("{} [{}]", temp, tag);
+                temp = format!("{} {}", temp, paint(&format!("[{tag}]"), palette.tag(tag)));
             }
             temp
         },
         {
             if let Some(date) = todo.time {
-                format!(" -󰃭 {}", date)
+                format!(" -{} {}", icons.icon(Icon::Date), date)
             } else {
                 String::new()
             }
@@ -553,24 +2320,74 @@ fn format_todo(todo: &Todo) -> String {
         {
             if let Some(ddl) = todo.deadline {
                 let real = ddl.and_local_timezone(Local).unwrap();
-                format!(" -󰈻 {} {}:{}", ddl.date(), real.hour(), real.minute())
+                let string = format!(
+                    " -{} {} {}:{}",
+                    icons.icon(Icon::Deadline),
+                    ddl.date(),
+                    real.hour(),
+                    real.minute()
+                );
+                if overdue {
+                    paint(&string, Some(palette.flag))
+                } else if due_today {
+                    paint(&string, Some(palette.star))
+                } else {
+                    string
+                }
             } else {
                 String::new()
             }
         },
         if todo.weight > 1 {
-            let mut str = String::from(" ");
+            let mut str = String::new();
             let mut i = 1;
             while i < todo.weight {
                 str = format!("{str}!");
                 i += 1;
             }
 
-            str
+            format!(" {}", paint(&str, palette.heat(todo.weight)))
         } else {
             String::new()
         }
-    )
+    );
+
+    if todo.completed || todo.cancelled {
+        style.dim(&string)
+    } else {
+        string
+    }
+}
+
+/// The time relative to now, like `in 3 hours` or `2 days ago`.
+fn relative_time(time: NaiveDateTime, now: NaiveDateTime) -> String {
+    let minutes = (time - now).num_minutes();
+    let (amount, unit) = match minutes.abs() {
+        0 => return String::from("now"),
+        m if m < 60 => (m, "minute"),
+        m if m < 60 * 24 => (m / 60, "hour"),
+        m if m < 60 * 24 * 30 => (m / (60 * 24), "day"),
+        m if m < 60 * 24 * 365 => (m / (60 * 24 * 30), "month"),
+        m => (m / (60 * 24 * 365), "year"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+
+    if minutes > 0 {
+        format!("in {amount} {unit}{plural}")
+    } else {
+        format!("{amount} {unit}{plural} ago")
+    }
+}
+
+/// The date relative to today, like `tomorrow` or `3 days ago`.
+fn relative_date(date: NaiveDate, today: NaiveDate) -> String {
+    match (date - today).num_days() {
+        0 => String::from("today"),
+        1 => String::from("tomorrow"),
+        -1 => String::from("yesterday"),
+        days if days > 0 => format!("in {days} days"),
+        days => format!("{} days ago", -days),
+    }
 }
 
 fn date_eq(date1: &NaiveDate, date2: &NaiveDate) -> bool {
@@ -622,17 +2439,18 @@ fn input_string() -> String {
     std::io::stdin()
         .read_line(&mut input)
         .expect("read_line error!");
-    input.lines().next().unwrap().to_string()
+    input.lines().next().unwrap_or_default().to_string()
 }
 
 struct FormattedTodo {
     pub string: String,
     pub id: u64,
+    pub depth: usize,
 }
 
 impl FormattedTodo {
-    pub fn of(id: u64, string: String) -> Self {
-        FormattedTodo { string, id }
+    pub fn of(id: u64, string: String, depth: usize) -> Self {
+        FormattedTodo { string, id, depth }
     }
 }
 
@@ -649,10 +2467,42 @@ impl TodoCache {
             f.read_to_string(&mut str).unwrap();
             serde_json::from_str::<TodoCache>(&str).unwrap()
         } else {
-            Self {
-                father: None,
-                child: Vec::new(),
-            }
+            Self::create_empty()
+        }
+    }
+
+    pub fn create_empty() -> Self {
+        Self {
+            father: None,
+            child: Vec::new(),
+        }
+    }
+
+    /// Why the cache file is broken or refers to todos that don't exist.
+    pub fn problem() -> Option<String> {
+        let s = fs::read_to_string("./.tuffous/cache.json").ok()?;
+        let Ok(cache) = serde_json::from_str::<TodoCache>(&s) else {
+            return Some(String::from(
+                ".tuffous/cache.json isn't valid, so father and child fail",
+            ));
+        };
+
+        let mut instance = TodoInstance::create(".");
+        instance.read_all();
+        let missing: Vec<String> = cache
+            .father
+            .iter()
+            .chain(cache.child.iter())
+            .filter(|id| instance.get(**id).is_none())
+            .map(|id| id.to_string())
+            .collect();
+        if missing.is_empty() {
+            None
+        } else {
+            Some(format!(
+                ".tuffous/cache.json refers to {}, which don't exist",
+                missing.join(", ")
+            ))
         }
     }
 
@@ -669,9 +2519,25 @@ impl TodoCache {
     }
 
     pub fn process(&mut self, instance: &mut TodoInstance) {
+        // Drop todos removed since they were cached
+        self.child.retain(|child| instance.get(*child).is_some());
+        if self
+            .father
+            .is_some_and(|father| instance.get(father).is_none())
+        {
+            self.father = None;
+        }
+
         if !self.child.is_empty() {
             if let Some(father) = &self.father {
                 for child in &self.child {
+                    if !instance.get(*child).unwrap().dependents.contains(father)
+                        && !instance.is_child_able(*father, *child)
+                    {
+                        eprintln!("Can't link {child} to {father}, as it would make a cycle");
+                        continue;
+                    }
+
                     if instance.get(*child).unwrap().dependents.contains(father) {
                         let mut rm = 0;
                         for dep in instance
@@ -695,3 +2561,61 @@ impl TodoCache {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tuffous_core::palette::Palette;
+
+    fn style(icons: IconSet) -> Style {
+        Style {
+            icons,
+            color: false,
+            palette: Palette::dark(),
+        }
+    }
+
+    /// A completed todo of today, due tomorrow.
+    fn todo() -> Todo {
+        let now = Local::now().naive_local();
+        let mut todo = Todo::create(String::from("Write"));
+        todo.tags = vec![String::from("work")];
+        todo.time = Some(now.date());
+        todo.deadline = Some(now + Duration::days(1));
+        todo.set_completed(true);
+        todo
+    }
+
+    #[test]
+    fn format_nerd() {
+        let text = format_todo(&todo(), &style(IconSet::Nerd));
+        assert!(
+            text.starts_with("\u{f005}\u{f0132} Write [work]  -\u{f00ed} "),
+            "{text}"
+        );
+        assert!(text.contains(" -\u{f023b} "), "{text}");
+    }
+
+    #[test]
+    fn format_emoji() {
+        let text = format_todo(&todo(), &style(IconSet::Emoji));
+        assert!(text.starts_with("⭐✅ Write [work]  -📅 "), "{text}");
+        assert!(text.contains(" -🏁 "), "{text}");
+    }
+
+    #[test]
+    fn format_ascii() {
+        let text = format_todo(&todo(), &style(IconSet::Ascii));
+        assert!(text.is_ascii());
+        assert!(text.starts_with("*[x] Write [work]  -@ "), "{text}");
+        assert!(text.contains(" -due "), "{text}");
+    }
+
+    #[test]
+    fn format_overdue() {
+        let mut todo = Todo::create(String::from("Pay"));
+        todo.deadline = Some(Local::now().naive_local() - Duration::days(1));
+        let text = format_todo(&todo, &style(IconSet::Ascii));
+        assert!(text.starts_with("!! Pay  -due "), "{text}");
+    }
+}
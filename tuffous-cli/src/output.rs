@@ -0,0 +1,506 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use std::{collections::HashMap, str::FromStr};
+use tuffous_core::{progress::Progress, stats::Stats, CheckItem, TodoInstance};
+
+/// Version of the machine-readable record layout, bumped on breaking changes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    Text,
+    Json,
+    Jsonl,
+    Tsv,
+    Csv,
+}
+
+impl OutputMode {
+    pub fn is_text(&self) -> bool {
+        matches!(self, OutputMode::Text)
+    }
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputMode::Text),
+            "json" => Ok(OutputMode::Json),
+            "jsonl" => Ok(OutputMode::Jsonl),
+            "tsv" => Ok(OutputMode::Tsv),
+            "csv" => Ok(OutputMode::Csv),
+            _ => Err(format!("Unknown output format: {s}")),
+        }
+    }
+}
+
+/// A todo as printed by the machine-readable output modes.
+///
+/// Ids are written as decimal strings since they don't fit in a JSON number.
+#[derive(Serialize)]
+pub struct TodoRecord {
+    pub schema: u32,
+    pub id: String,
+    pub short_id: String,
+    pub name: String,
+    pub details: String,
+    pub completed: bool,
+    pub cancelled: bool,
+    pub creation_date: NaiveDateTime,
+    pub completion_date: Option<NaiveDateTime>,
+    pub date: Option<NaiveDate>,
+    pub deadline: Option<NaiveDateTime>,
+    pub weight: u32,
+    /// In minutes.
+    pub estimate: Option<u32>,
+    pub tags: Vec<String>,
+    pub checklist: Vec<CheckItem>,
+    pub parents: Vec<String>,
+    pub children: Vec<String>,
+    pub progress: Progress,
+    pub depth: usize,
+}
+
+impl TodoRecord {
+    pub fn of(
+        instance: &TodoInstance,
+        short_ids: &HashMap<u64, String>,
+        id: u64,
+        depth: usize,
+    ) -> Option<Self> {
+        let todo = instance.get(id)?;
+
+        Some(TodoRecord {
+            schema: SCHEMA_VERSION,
+            id: id.to_string(),
+            short_id: short_ids.get(&id).cloned().unwrap_or_default(),
+            name: todo.metadata.name.clone(),
+            details: todo.metadata.details().to_owned(),
+            completed: todo.completed,
+            cancelled: todo.cancelled,
+            creation_date: *todo.creation_date(),
+            completion_date: todo.completion_date().copied(),
+            date: todo.time,
+            deadline: todo.deadline,
+            weight: todo.weight,
+            estimate: todo.estimate,
+            tags: todo.tags.clone(),
+            checklist: todo.metadata.checklist.clone(),
+            parents: todo.dependents.iter().map(|dep| dep.to_string()).collect(),
+            children: instance
+                .children_once(id)
+                .iter()
+                .map(|child| child.to_string())
+                .collect(),
+            progress: instance.progress(id)?,
+            depth,
+        })
+    }
+
+    const HEADER: [&'static str; 18] = [
+        "id",
+        "short_id",
+        "name",
+        "details",
+        "completed",
+        "creation_date",
+        "completion_date",
+        "date",
+        "deadline",
+        "weight",
+        "tags",
+        "parents",
+        "children",
+        "done",
+        "total",
+        "depth",
+        "cancelled",
+        "estimate",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        vec![
+            self.id.clone(),
+            self.short_id.clone(),
+            self.name.clone(),
+            self.details.clone(),
+            self.completed.to_string(),
+            self.creation_date.to_string(),
+            optional(self.completion_date.map(|d| d.to_string())),
+            optional(self.date.map(|d| d.to_string())),
+            optional(self.deadline.map(|d| d.to_string())),
+            self.weight.to_string(),
+            self.tags.join(","),
+            self.parents.join(","),
+            self.children.join(","),
+            self.progress.done.to_string(),
+            self.progress.total.to_string(),
+            self.depth.to_string(),
+            self.cancelled.to_string(),
+            optional(self.estimate.map(|e| e.to_string())),
+        ]
+    }
+}
+
+/// A todo with its path, ancestors and subtree, as printed by `show`.
+#[derive(Serialize)]
+pub struct TodoDetail {
+    #[serde(flatten)]
+    pub todo: TodoRecord,
+    pub path: String,
+    pub ancestors: Vec<String>,
+    pub subtree: Vec<TodoRecord>,
+}
+
+impl TodoDetail {
+    pub fn of(
+        instance: &TodoInstance,
+        short_ids: &HashMap<u64, String>,
+        id: u64,
+        ancestors: &[u64],
+        subtree: &[(u64, usize)],
+    ) -> Option<Self> {
+        Some(TodoDetail {
+            todo: TodoRecord::of(instance, short_ids, id, 0)?,
+            path: instance.todo_path(id),
+            ancestors: ancestors.iter().map(|dep| dep.to_string()).collect(),
+            subtree: subtree
+                .iter()
+                .filter_map(|(child, depth)| TodoRecord::of(instance, short_ids, *child, *depth))
+                .collect(),
+        })
+    }
+}
+
+/// A todo of a section of the agenda, which is `overdue`, a date or `undated`.
+#[derive(Serialize)]
+pub struct AgendaRecord {
+    pub section: String,
+    /// `date` or `deadline` for the todos of a day.
+    pub entry: Option<String>,
+    #[serde(flatten)]
+    pub todo: TodoRecord,
+}
+
+/// Print the agenda in the mode. Nothing is printed in text mode.
+pub fn print_agenda(mode: OutputMode, records: &[AgendaRecord]) {
+    match mode {
+        OutputMode::Text => (),
+        OutputMode::Json => println!("{}", serde_json::to_string_pretty(records).unwrap()),
+        OutputMode::Jsonl => {
+            for record in records {
+                println!("{}", serde_json::to_string(record).unwrap());
+            }
+        }
+        OutputMode::Tsv | OutputMode::Csv => {
+            let mut header = vec!["section".to_string(), "entry".to_string()];
+            header.extend(TodoRecord::HEADER.map(String::from));
+            println!("{}", row(mode, &header));
+            for record in records {
+                let mut fields = vec![
+                    record.section.clone(),
+                    record.entry.clone().unwrap_or_default(),
+                ];
+                fields.extend(record.todo.fields());
+                println!("{}", row(mode, &fields));
+            }
+        }
+    }
+}
+
+/// A removed todo, as printed by `trash list`.
+#[derive(Serialize)]
+pub struct TrashRecord {
+    pub deleted: NaiveDateTime,
+    /// The todos that had it as a father, linked again when it's restored.
+    pub linked_children: Vec<String>,
+    #[serde(flatten)]
+    pub todo: TodoRecord,
+}
+
+/// Print the removed todos in the mode. Nothing is printed in text mode.
+pub fn print_trash(mode: OutputMode, records: &[TrashRecord]) {
+    match mode {
+        OutputMode::Text => (),
+        OutputMode::Json => println!("{}", serde_json::to_string_pretty(records).unwrap()),
+        OutputMode::Jsonl => {
+            for record in records {
+                println!("{}", serde_json::to_string(record).unwrap());
+            }
+        }
+        OutputMode::Tsv | OutputMode::Csv => {
+            let mut header = vec!["deleted".to_string(), "linked_children".to_string()];
+            header.extend(TodoRecord::HEADER.map(String::from));
+            println!("{}", row(mode, &header));
+            for record in records {
+                let mut fields = vec![record.deleted.to_string(), record.linked_children.join(",")];
+                fields.extend(record.todo.fields());
+                println!("{}", row(mode, &fields));
+            }
+        }
+    }
+}
+
+/// Print the statistics in the mode. Nothing is printed in text mode.
+///
+/// In tsv and csv every number is a row of `section`, `key`, `metric` and
+/// `value`, so the sections can share a table.
+pub fn print_stats(mode: OutputMode, stats: &Stats) {
+    match mode {
+        OutputMode::Text => (),
+        OutputMode::Json => println!("{}", serde_json::to_string_pretty(stats).unwrap()),
+        OutputMode::Jsonl => println!("{}", serde_json::to_string(stats).unwrap()),
+        OutputMode::Tsv | OutputMode::Csv => {
+            let mut rows: Vec<[String; 4]> = Vec::new();
+            let mut push = |section: &str, key: &str, metric: &str, value: String| {
+                rows.push([section.into(), key.into(), metric.into(), value]);
+            };
+
+            push("summary", "", "total", stats.total.to_string());
+            push("summary", "", "open", stats.open.to_string());
+            push("summary", "", "completed", stats.completed.to_string());
+            push("summary", "", "overdue", stats.overdue.to_string());
+            if let Some(days) = stats.average_cycle_days {
+                push("summary", "", "average_cycle_days", format!("{days:.2}"));
+            }
+            push(
+                "summary",
+                "",
+                "current_streak",
+                stats.current_streak.to_string(),
+            );
+            push(
+                "summary",
+                "",
+                "longest_streak",
+                stats.longest_streak.to_string(),
+            );
+            for period in &stats.periods {
+                push(
+                    "period",
+                    &period.period,
+                    "created",
+                    period.created.to_string(),
+                );
+                push(
+                    "period",
+                    &period.period,
+                    "completed",
+                    period.completed.to_string(),
+                );
+            }
+            for (section, groups) in [("tag", &stats.tags), ("project", &stats.projects)] {
+                for group in groups {
+                    push(section, &group.name, "total", group.total.to_string());
+                    push(
+                        section,
+                        &group.name,
+                        "completed",
+                        group.completed.to_string(),
+                    );
+                    push(section, &group.name, "weight", group.weight.to_string());
+                    push(
+                        section,
+                        &group.name,
+                        "completed_weight",
+                        group.completed_weight.to_string(),
+                    );
+                }
+            }
+
+            println!(
+                "{}",
+                row(
+                    mode,
+                    &["section", "key", "metric", "value"].map(String::from)
+                )
+            );
+            for fields in &rows {
+                println!("{}", row(mode, fields));
+            }
+        }
+    }
+}
+
+/// Print the detail in the mode. In tsv and csv the todo is followed by the rows of its subtree.
+pub fn print_detail(mode: OutputMode, detail: &TodoDetail) {
+    match mode {
+        OutputMode::Text => (),
+        OutputMode::Json => println!("{}", serde_json::to_string_pretty(detail).unwrap()),
+        OutputMode::Jsonl => println!("{}", serde_json::to_string(detail).unwrap()),
+        OutputMode::Tsv | OutputMode::Csv => {
+            println!("{}", row(mode, &TodoRecord::HEADER.map(String::from)));
+            println!("{}", row(mode, &detail.todo.fields()));
+            for record in &detail.subtree {
+                println!("{}", row(mode, &record.fields()));
+            }
+        }
+    }
+}
+
+fn tsv_escape(string: &str) -> String {
+    string
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Quote the field if it has a comma, a quote or a line break, as in RFC 4180.
+fn csv_escape(string: &str) -> String {
+    if string.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", string.replace('"', "\"\""))
+    } else {
+        string.to_string()
+    }
+}
+
+/// Join the fields into a line of tsv or csv.
+fn row(mode: OutputMode, fields: &[String]) -> String {
+    match mode {
+        OutputMode::Csv => fields
+            .iter()
+            .map(|field| csv_escape(field))
+            .collect::<Vec<_>>()
+            .join(","),
+        _ => fields
+            .iter()
+            .map(|field| tsv_escape(field))
+            .collect::<Vec<_>>()
+            .join("\t"),
+    }
+}
+
+/// Print the records in the mode. Nothing is printed in text mode.
+pub fn print_records(mode: OutputMode, records: &[TodoRecord]) {
+    match mode {
+        OutputMode::Text => (),
+        OutputMode::Json => println!("{}", serde_json::to_string_pretty(records).unwrap()),
+        OutputMode::Jsonl => {
+            for record in records {
+                println!("{}", serde_json::to_string(record).unwrap());
+            }
+        }
+        OutputMode::Tsv | OutputMode::Csv => {
+            println!("{}", row(mode, &TodoRecord::HEADER.map(String::from)));
+            for record in records {
+                println!("{}", row(mode, &record.fields()));
+            }
+        }
+    }
+}
+
+/// Print the todos with the ids as records, in the mode.
+pub fn report(instance: &TodoInstance, ids: &[u64], mode: OutputMode) {
+    if mode.is_text() {
+        return;
+    }
+
+    let short_ids = instance.short_ids();
+    let records: Vec<TodoRecord> = ids
+        .iter()
+        .filter_map(|id| TodoRecord::of(instance, &short_ids, *id, 0))
+        .collect();
+    print_records(mode, &records);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tuffous_core::Todo;
+
+    fn record() -> TodoRecord {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let mut todo = Todo::create(String::from("Write, \"report\""));
+        todo.metadata
+            .set_details(String::from("line\tone\nline two"));
+        todo.tags = vec![String::from("work"), String::from("q2")];
+        todo.weight = 3;
+        todo.estimate = Some(90);
+        let id = todo.id();
+        instance.insert(todo);
+        TodoRecord::of(&instance, &instance.short_ids(), id, 2).unwrap()
+    }
+
+    #[test]
+    fn json_schema() {
+        let record = record();
+        let json = serde_json::to_value(&record).unwrap();
+        let mut keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str())
+            .collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "cancelled",
+                "checklist",
+                "children",
+                "completed",
+                "completion_date",
+                "creation_date",
+                "date",
+                "deadline",
+                "depth",
+                "details",
+                "estimate",
+                "id",
+                "name",
+                "parents",
+                "progress",
+                "schema",
+                "short_id",
+                "tags",
+                "weight",
+            ]
+        );
+        assert_eq!(json["schema"], SCHEMA_VERSION);
+        // Ids are strings, as they don't fit in JSON numbers
+        assert_eq!(json["id"], record.id);
+        assert_eq!(json["estimate"], 90);
+        assert_eq!(json["deadline"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn tsv_and_csv_rows() {
+        let record = record();
+        let fields = record.fields();
+        assert_eq!(fields.len(), TodoRecord::HEADER.len());
+        let field = |name: &str| {
+            let index = TodoRecord::HEADER.iter().position(|h| *h == name).unwrap();
+            fields[index].as_str()
+        };
+        assert_eq!(field("tags"), "work,q2");
+        assert_eq!(field("depth"), "2");
+        assert_eq!(field("estimate"), "90");
+        assert_eq!(field("deadline"), "");
+        assert_eq!(field("cancelled"), "false");
+
+        let tsv = row(OutputMode::Tsv, &fields);
+        assert_eq!(tsv.split('\t').count(), TodoRecord::HEADER.len());
+        assert!(tsv.contains("\tline\\tone\\nline two\t"));
+        assert!(!tsv.contains('\n'));
+
+        let csv = row(OutputMode::Csv, &fields);
+        assert!(csv.contains(",\"Write, \"\"report\"\"\","));
+        assert!(csv.contains(",\"work,q2\","));
+        assert!(csv.contains(",\"line\tone\nline two\","));
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(tsv_escape("a\\b\r"), "a\\\\b\\r");
+    }
+
+    #[test]
+    fn modes() {
+        assert!("JSON"
+            .parse::<OutputMode>()
+            .is_ok_and(|mode| mode == OutputMode::Json));
+        assert!("text".parse::<OutputMode>().unwrap().is_text());
+        assert!("xml".parse::<OutputMode>().is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use std::{env, io::IsTerminal, str::FromStr};
+use tuffous_core::{
+    icons::IconSet,
+    palette::{Palette, Rgb},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    /// Whether to color the standard output. `auto` colors a terminal unless
+    /// `NO_COLOR` is set or the terminal is dumb.
+    pub fn enabled(&self) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                env::var("NO_COLOR").map_or(true, |v| v.is_empty())
+                    && env::var("TERM").map_or(true, |term| term != "dumb")
+                    && std::io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!("Unknown color mode: {s}")),
+        }
+    }
+}
+
+/// How todos are drawn in the terminal.
+pub struct Style {
+    pub icons: IconSet,
+    pub color: bool,
+    pub palette: Palette,
+}
+
+impl Style {
+    /// Color the text, or leave it as is if colors are disabled.
+    pub fn paint(&self, text: &str, color: Option<Rgb>) -> String {
+        match color {
+            Some(Rgb(r, g, b)) if self.color && !text.is_empty() => {
+                format!("\x1b[38;2;{r};{g};{b}m{text}\x1b[0m")
+            }
+            _ => text.to_string(),
+        }
+    }
+
+    pub fn dim(&self, text: &str) -> String {
+        if self.color && !text.is_empty() {
+            format!("\x1b[2m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! Format templates for list lines, like `git log --format`.
+//!
+//! - `{field}` is replaced by the value of the field.
+//! - `{field:MOD}` pads or truncates it, where `MOD` is `[<|>][width][.max]`,
+//!   e.g. `{name:20}`, `{weight:>3}` or `{details:.30}`.
+//! - `{?field|...}` is only rendered when the field isn't empty,
+//!   `{!field|...}` only when it is. Sections can contain placeholders.
+//! - `{{` and `}}` are literal braces.
+
+use chrono::Local;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+use tuffous_core::TodoInstance;
+
+pub const FIELDS: [&str; 17] = [
+    "id",
+    "short",
+    "name",
+    "details",
+    "tags",
+    "date",
+    "deadline",
+    "weight",
+    "estimate",
+    "progress",
+    "done",
+    "total",
+    "percent",
+    "depth",
+    "indent",
+    "status",
+    "checklist",
+];
+
+enum Node {
+    Text(String),
+    Field {
+        name: String,
+        right: bool,
+        width: Option<usize>,
+        max: Option<usize>,
+    },
+    Section {
+        name: String,
+        negate: bool,
+        body: Vec<Node>,
+    },
+}
+
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(string: &str) -> Result<Template, String> {
+        let mut chars = string.chars().peekable();
+        let nodes = parse_nodes(&mut chars, false)?;
+        Ok(Template { nodes })
+    }
+
+    /// Render the template with the values of the fields.
+    pub fn render(&self, value: &dyn Fn(&str) -> String) -> String {
+        render_nodes(&self.nodes, value)
+    }
+}
+
+fn parse_nodes(chars: &mut Peekable<Chars>, nested: bool) -> Result<Vec<Node>, String> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '}' if nested => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text(text));
+                }
+                return Ok(nodes);
+            }
+            '}' => return Err(String::from("Unmatched '}' in format")),
+            '{' => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text(std::mem::take(&mut text)));
+                }
+                nodes.push(parse_placeholder(chars)?);
+            }
+            _ => text.push(c),
+        }
+    }
+
+    if nested {
+        return Err(String::from("Unclosed section in format"));
+    }
+
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+    Ok(nodes)
+}
+
+fn parse_placeholder(chars: &mut Peekable<Chars>) -> Result<Node, String> {
+    let section = match chars.peek() {
+        Some('?') => Some(false),
+        Some('!') => Some(true),
+        _ => None,
+    };
+    if section.is_some() {
+        chars.next();
+    }
+
+    let mut name = String::new();
+    while let Some(c) = chars.peek() {
+        if c.is_ascii_alphanumeric() || *c == '_' {
+            name.push(*c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+
+    if !FIELDS.contains(&name.as_str()) {
+        return Err(format!("Unknown format field: {name}"));
+    }
+
+    if let Some(negate) = section {
+        if chars.next() != Some('|') {
+            return Err(format!("Expected '|' after section field {name}"));
+        }
+        let body = parse_nodes(chars, true)?;
+        return Ok(Node::Section { name, negate, body });
+    }
+
+    let mut modifier = String::new();
+    match chars.next() {
+        Some('}') => (),
+        Some(':') => loop {
+            match chars.next() {
+                Some('}') => break,
+                Some(c) => modifier.push(c),
+                None => return Err(format!("Unclosed field {name} in format")),
+            }
+        },
+        _ => return Err(format!("Unclosed field {name} in format")),
+    }
+
+    let right = modifier.starts_with('>');
+    let modifier = modifier.trim_start_matches(['<', '>']);
+    let (width, max) = modifier.split_once('.').unwrap_or((modifier, ""));
+    let parse = |n: &str| -> Result<Option<usize>, String> {
+        if n.is_empty() {
+            Ok(None)
+        } else {
+            n.parse::<usize>()
+                .map(Some)
+                .map_err(|_| format!("Invalid width in field {name}: {n}"))
+        }
+    };
+
+    Ok(Node::Field {
+        right,
+        width: parse(width)?,
+        max: parse(max)?,
+        name,
+    })
+}
+
+fn render_nodes(nodes: &[Node], value: &dyn Fn(&str) -> String) -> String {
+    let mut string = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => string.push_str(text),
+            Node::Field {
+                name,
+                right,
+                width,
+                max,
+            } => {
+                let mut v = value(name);
+                if let Some(max) = max {
+                    v = truncate(&v, *max);
+                }
+                if let Some(width) = width {
+                    let len = v.chars().count();
+                    if len < *width {
+                        let padding = " ".repeat(width - len);
+                        v = if *right {
+                            format!("{padding}{v}")
+                        } else {
+                            format!("{v}{padding}")
+                        };
+                    }
+                }
+                string.push_str(&v);
+            }
+            Node::Section { name, negate, body } => {
+                if value(name).is_empty() == *negate {
+                    string.push_str(&render_nodes(body, value));
+                }
+            }
+        }
+    }
+    string
+}
+
+fn truncate(string: &str, max: usize) -> String {
+    if string.chars().count() <= max {
+        string.to_string()
+    } else if max == 0 {
+        String::new()
+    } else {
+        let mut s: String = string.chars().take(max - 1).collect();
+        s.push('…');
+        s
+    }
+}
+
+/// The value of a field for the todo shown at the depth of a tree.
+pub fn todo_value(
+    instance: &TodoInstance,
+    short_ids: &HashMap<u64, String>,
+    id: u64,
+    depth: usize,
+    field: &str,
+) -> String {
+    let Some(todo) = instance.get(id) else {
+        return String::new();
+    };
+    let progress = instance.progress(id).filter(|_| instance.has_progress(id));
+
+    match field {
+        "id" => id.to_string(),
+        "short" => short_ids.get(&id).cloned().unwrap_or_default(),
+        "name" => todo.metadata.name.clone(),
+        "details" => todo.metadata.details().to_owned(),
+        "tags" => todo.tags.join(","),
+        "date" => todo.time.map(|d| d.to_string()).unwrap_or_default(),
+        "deadline" => todo
+            .deadline
+            .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default(),
+        "weight" => todo.weight.to_string(),
+        "estimate" => todo.estimate.map(format_minutes).unwrap_or_default(),
+        "progress" => progress
+            .map(|p| format!("{}/{}", p.done, p.total))
+            .unwrap_or_default(),
+        "done" => progress.map(|p| p.done.to_string()).unwrap_or_default(),
+        "total" => progress.map(|p| p.total.to_string()).unwrap_or_default(),
+        "percent" => progress.map(|p| p.percent.to_string()).unwrap_or_default(),
+        "depth" => depth.to_string(),
+        "indent" => "   ".repeat(depth),
+        "status" => {
+            if todo.completed {
+                String::from("done")
+            } else if todo.cancelled {
+                String::from("cancelled")
+            } else if todo
+                .deadline
+                .is_some_and(|ddl| ddl <= Local::now().naive_local())
+            {
+                String::from("overdue")
+            } else if todo.time == Some(Local::now().date_naive())
+                || todo
+                    .deadline
+                    .is_some_and(|ddl| ddl.date() == Local::now().date_naive())
+            {
+                String::from("today")
+            } else {
+                String::from("open")
+            }
+        }
+        "checklist" if !todo.metadata.checklist.is_empty() => format!(
+            "{}/{}",
+            todo.metadata
+                .checklist
+                .iter()
+                .filter(|item| item.checked)
+                .count(),
+            todo.metadata.checklist.len()
+        ),
+        _ => String::new(),
+    }
+}
+
+/// Minutes like `45m`, `2h` or `1h30m`.
+pub fn format_minutes(minutes: u32) -> String {
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{m}m"),
+        (h, 0) => format!("{h}h"),
+        (h, m) => format!("{h}h{m}m"),
+    }
+}
+
+/// Minutes from a duration like `45m`, `2h`, `1h30m` or `1.5h`. A bare number is minutes.
+pub fn parse_minutes(string: &str) -> Option<u32> {
+    let string = string.trim().to_lowercase();
+    if let Ok(minutes) = string.parse::<u32>() {
+        return Some(minutes);
+    }
+
+    let (hours, minutes) = match string.split_once('h') {
+        Some((h, m)) => (h.parse::<f32>().ok()?, m),
+        None => (0.0, string.as_str()),
+    };
+    let minutes = match minutes.strip_suffix('m') {
+        Some(m) => m.parse::<u32>().ok()?,
+        None if minutes.is_empty() => 0,
+        None => return None,
+    };
+    Some((hours * 60.0).round() as u32 + minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, fields: &[(&str, &str)]) -> String {
+        let fields: HashMap<&str, &str> = fields.iter().copied().collect();
+        Template::parse(template)
+            .unwrap()
+            .render(&|name| fields.get(name).unwrap_or(&"").to_string())
+    }
+
+    #[test]
+    fn padding() {
+        let fields = [("name", "Write report"), ("weight", "3")];
+        assert_eq!(render("[{name:15}]", &fields), "[Write report   ]");
+        assert_eq!(render("[{weight:>3}]", &fields), "[  3]");
+        assert_eq!(render("[{weight:<3}]", &fields), "[3  ]");
+        assert_eq!(render("[{name:.6}]", &fields), "[Write…]");
+        assert_eq!(render("[{name:8.6}]", &fields), "[Write…  ]");
+        assert_eq!(render("[{name:4}]", &fields), "[Write report]");
+        assert_eq!(render("[{name:.0}]", &fields), "[]");
+        // Widths count chars, not bytes
+        assert_eq!(render("[{name:6}]", &[("name", "été")]), "[été   ]");
+        assert_eq!(render("{{{name}}}", &fields), "{Write report}");
+    }
+
+    #[test]
+    fn conditionals() {
+        let template = "{name}{?deadline| due {deadline}}{!tags| (untagged)}";
+        assert_eq!(
+            render(template, &[("name", "Pay"), ("deadline", "2024-05-01")]),
+            "Pay due 2024-05-01 (untagged)"
+        );
+        assert_eq!(
+            render(template, &[("name", "Pay"), ("tags", "home")]),
+            "Pay"
+        );
+        // Sections nest
+        assert_eq!(
+            render(
+                "{?tags|[{?weight|{weight}}]}",
+                &[("tags", "home"), ("weight", "2")]
+            ),
+            "[2]"
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(Template::parse("{color}").is_err());
+        assert!(Template::parse("{name").is_err());
+        assert!(Template::parse("{name:x}").is_err());
+        assert!(Template::parse("name}").is_err());
+        assert!(Template::parse("{?tags name}").is_err());
+        assert!(Template::parse("{?tags|name").is_err());
+    }
+
+    #[test]
+    fn minutes() {
+        assert_eq!(format_minutes(45), "45m");
+        assert_eq!(format_minutes(120), "2h");
+        assert_eq!(format_minutes(90), "1h30m");
+        assert_eq!(parse_minutes("1h30m"), Some(90));
+        assert_eq!(parse_minutes("1.5h"), Some(90));
+        assert_eq!(parse_minutes("45"), Some(45));
+        assert_eq!(parse_minutes("2H"), Some(120));
+        assert_eq!(parse_minutes("soon"), None);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{Todo, TodoInstance};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+/// Whether the todo shows in the Today view: dated today, or undated with a
+/// deadline up to today.
+pub fn is_today(todo: &Todo, today: NaiveDate) -> bool {
+    if let Some(date) = todo.time {
+        date == today
+    } else if let Some(ddl) = todo.deadline {
+        ddl.date() <= today
+    } else {
+        false
+    }
+}
+
+/// Whether the todo shows in the Upcoming view: dated another day, or
+/// undated with a deadline after today.
+pub fn is_upcoming(todo: &Todo, today: NaiveDate) -> bool {
+    if let Some(date) = todo.time {
+        date != today
+    } else if let Some(ddl) = todo.deadline {
+        today < ddl.date()
+    } else {
+        false
+    }
+}
+
+/// Whether the todo shows in the Anytime view: without a date or deadline.
+pub fn is_anytime(todo: &Todo) -> bool {
+    todo.time.is_none() && todo.deadline.is_none()
+}
+
+/// Why a todo is listed on a day of the agenda.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgendaEntry {
+    Date,
+    Deadline(NaiveDateTime),
+}
+
+#[derive(Debug, Default)]
+pub struct Agenda {
+    /// Todos past their deadline or their date.
+    pub overdue: Vec<u64>,
+    /// Every day from today on, with the todos dated or due on it.
+    pub days: Vec<(NaiveDate, Vec<(u64, AgendaEntry)>)>,
+    /// Todos without a date or deadline.
+    pub undated: Vec<u64>,
+}
+
+impl TodoInstance {
+    /// The open todos among the ids, by the days they're dated or due on.
+    ///
+    /// A todo dated on one day and due on another is listed on both.
+    pub fn agenda(&self, ids: &[u64], now: NaiveDateTime, days: u32) -> Agenda {
+        let today = now.date();
+        let mut agenda = Agenda {
+            days: (0..days)
+                .map(|day| (today + Duration::days(day as i64), Vec::new()))
+                .collect(),
+            ..Default::default()
+        };
+
+        for id in ids {
+            let Some(todo) = self
+                .get(*id)
+                .filter(|todo| !todo.completed && !todo.cancelled)
+            else {
+                continue;
+            };
+
+            if todo.deadline.is_some_and(|ddl| ddl < now) || todo.time.is_some_and(|d| d < today) {
+                agenda.overdue.push(*id);
+                continue;
+            }
+            if is_anytime(todo) {
+                agenda.undated.push(*id);
+                continue;
+            }
+
+            for (day, entries) in &mut agenda.days {
+                if todo.time == Some(*day) {
+                    entries.push((*id, AgendaEntry::Date));
+                }
+                if let Some(ddl) = todo.deadline.filter(|ddl| ddl.date() == *day) {
+                    entries.push((*id, AgendaEntry::Deadline(ddl)));
+                }
+            }
+        }
+
+        // Timed deadlines of a day go first, earliest first
+        for (_, entries) in &mut agenda.days {
+            entries.sort_by_key(|(_, entry)| match entry {
+                AgendaEntry::Deadline(ddl) => (0, Some(*ddl)),
+                AgendaEntry::Date => (1, None),
+            });
+        }
+        agenda
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: NaiveDate, hour: u32) -> NaiveDateTime {
+        date.and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    fn todo(
+        instance: &mut TodoInstance,
+        name: &str,
+        time: Option<NaiveDate>,
+        deadline: Option<NaiveDateTime>,
+    ) -> u64 {
+        let mut todo = Todo::create(String::from(name));
+        todo.time = time;
+        todo.deadline = deadline;
+        let id = todo.id();
+        instance.insert(todo);
+        id
+    }
+
+    #[test]
+    fn views() {
+        let today = NaiveDate::from_ymd_opt(2024, 5, 10).unwrap();
+        let mut todo = Todo::create(String::from("Pay rent"));
+        assert!(is_anytime(&todo) && !is_today(&todo, today) && !is_upcoming(&todo, today));
+
+        todo.deadline = Some(at(today - Duration::days(2), 9));
+        assert!(is_today(&todo, today) && !is_upcoming(&todo, today));
+        todo.deadline = Some(at(today + Duration::days(2), 9));
+        assert!(!is_today(&todo, today) && is_upcoming(&todo, today));
+
+        // The date wins over the deadline
+        todo.time = Some(today);
+        assert!(is_today(&todo, today) && !is_upcoming(&todo, today));
+        todo.time = Some(today - Duration::days(1));
+        assert!(!is_today(&todo, today) && is_upcoming(&todo, today));
+        assert!(!is_anytime(&todo));
+    }
+
+    #[test]
+    fn agenda_days() {
+        let today = NaiveDate::from_ymd_opt(2024, 5, 10).unwrap();
+        let tomorrow = today + Duration::days(1);
+        let now = at(today, 12);
+        let mut instance = TodoInstance::create("/nonexistent");
+        let late = todo(&mut instance, "Late", None, Some(at(today, 9)));
+        let stale = todo(
+            &mut instance,
+            "Stale",
+            Some(today - Duration::days(1)),
+            None,
+        );
+        let dated = todo(&mut instance, "Dated", Some(today), None);
+        let due = todo(&mut instance, "Due", None, Some(at(today, 18)));
+        let both = todo(&mut instance, "Both", Some(today), Some(at(tomorrow, 8)));
+        let far = todo(&mut instance, "Far", Some(today + Duration::days(9)), None);
+        let undated = todo(&mut instance, "Undated", None, None);
+        let done = todo(&mut instance, "Done", Some(today), None);
+        instance.get_mut(done).unwrap().set_completed(true);
+        let cancelled = todo(&mut instance, "Cancelled", Some(today), None);
+        instance.get_mut(cancelled).unwrap().cancelled = true;
+
+        let agenda = instance.agenda(&instance.todos(), now, 2);
+        assert_eq!(agenda.overdue, [late, stale]);
+        assert_eq!(agenda.undated, [undated]);
+        assert_eq!(
+            agenda.days,
+            [
+                (
+                    today,
+                    vec![
+                        (due, AgendaEntry::Deadline(at(today, 18))),
+                        (dated, AgendaEntry::Date),
+                        (both, AgendaEntry::Date),
+                    ]
+                ),
+                (
+                    tomorrow,
+                    vec![(both, AgendaEntry::Deadline(at(tomorrow, 8)))]
+                ),
+            ]
+        );
+        assert!(!agenda
+            .days
+            .iter()
+            .any(|(_, entries)| entries.iter().any(|(id, _)| *id == far)));
+    }
+}
@@ -0,0 +1,225 @@
+//! The archive keeps old completed todos out of the working set.
+//!
+//! Archived todos are stored like the others, in `.tuffous/archive`, and keep
+//! their links. They are only loaded by [`TodoInstance::read_archive`].
+
+use crate::{config::RepoConfig, index::Stamp, Todo, TodoInstance};
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::{collections::HashSet, fs};
+
+impl TodoInstance {
+    /// The completed todos whose whole subtree was completed before the time.
+    ///
+    /// Todos completed before completion dates were recorded count from their creation.
+    pub fn archivable(&self, before: NaiveDateTime) -> Vec<u64> {
+        let mut ids: HashSet<u64> = self
+            .todos
+            .iter()
+            .filter(|todo| {
+                todo.completed
+                    && *todo.completion_date().unwrap_or(todo.creation_date()) < before
+                    && !self.is_archived(todo)
+            })
+            .map(|todo| todo.id())
+            .collect();
+
+        // Drop the todos with a child left out, until none is
+        loop {
+            let kept: HashSet<u64> = ids
+                .iter()
+                .copied()
+                .filter(|id| {
+                    self.children_once(*id)
+                        .iter()
+                        .all(|child| ids.contains(child))
+                })
+                .collect();
+            if kept.len() == ids.len() {
+                break;
+            }
+            ids = kept;
+        }
+
+        self.todos()
+            .into_iter()
+            .filter(|id| ids.contains(id))
+            .collect()
+    }
+
+    /// Move the todos to the archive. Todos that can't be moved are left where
+    /// they are, failing with the first error once the others are moved.
+    pub fn archive(&mut self, ids: &[u64]) -> Result<(), String> {
+        fs::create_dir_all(self.archive_dir()).map_err(|err| err.to_string())?;
+        let mut moved = Vec::new();
+        let mut error = None;
+        for id in ids {
+            let written = match self.get(*id) {
+                Some(todo) => todo.write_to_dir(&self.archive_dir()),
+                None => continue,
+            };
+            match written.and_then(|_| self.unstore(&[*id])) {
+                Ok(()) => moved.push(*id),
+                Err(err) => {
+                    let _ = fs::remove_file(format!("{}/{id}.json", self.archive_dir()));
+                    error.get_or_insert(err);
+                }
+            }
+        }
+        let moved: HashSet<u64> = moved.into_iter().collect();
+        self.todos.retain(|todo| !moved.contains(&todo.id()));
+        self.stamps.retain(|id, _| !moved.contains(id));
+        self.cache.get_mut().reset();
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Archive the todos completed longer ago than the `archive_after_days` of the repo.
+    pub fn archive_by_policy(&mut self) -> Result<Vec<u64>, String> {
+        let Some(days) = RepoConfig::get(&self.path).archive_after_days else {
+            return Ok(Vec::new());
+        };
+        let ids = self.archivable(Utc::now().naive_utc() - Duration::days(days as i64));
+        self.archive(&ids)?;
+        Ok(ids)
+    }
+
+    /// Load the archived todos along with the others.
+    pub fn read_archive(&mut self) {
+        let Ok(dir) = fs::read_dir(self.archive_dir()) else {
+            return;
+        };
+        let mut ids: HashSet<u64> = self.todos.iter().map(|todo| todo.id()).collect();
+        for file in dir.flatten() {
+            if let Some(todo) = Todo::read_from_file(file.path()) {
+                if ids.insert(todo.id()) {
+                    if let Some(stamp) = file.metadata().ok().and_then(|m| Stamp::of(&m)) {
+                        self.stamps.insert(todo.id(), stamp);
+                    }
+                    self.archived.insert(todo.id());
+                    self.unchecked.insert(todo.id());
+                    self.todos.push(todo);
+                }
+            }
+        }
+        self.todos.sort_by_key(|todo| (todo.creation_date, todo.id));
+        self.cache.get_mut().reset();
+    }
+
+    /// Drop the loaded archived todos, except the ones reopened since.
+    pub fn unload_archive(&mut self) {
+        let archived: HashSet<u64> = self
+            .todos
+            .iter()
+            .filter(|todo| self.is_archived(todo))
+            .map(|todo| todo.id())
+            .collect();
+        self.todos.retain(|todo| !archived.contains(&todo.id()));
+        self.stamps.retain(|id, _| !archived.contains(id));
+        self.dirty.retain(|id| !archived.contains(id));
+        self.cache.get_mut().reset();
+        self.archived.clear();
+    }
+
+    /// Whether the todo was loaded from the archive and is still completed.
+    /// Reopening an archived todo brings it back to the working set when written.
+    pub fn is_archived(&self, todo: &Todo) -> bool {
+        todo.completed && self.archived.contains(&todo.id())
+    }
+
+    /// The ids of every archived todo, loaded or not.
+    pub fn archived_ids(&self) -> Vec<u64> {
+        let Ok(dir) = fs::read_dir(self.archive_dir()) else {
+            return Vec::new();
+        };
+        dir.flatten()
+            .filter_map(|file| {
+                file.path()
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+            })
+            .collect()
+    }
+
+    pub(crate) fn archive_dir(&self) -> String {
+        format!("{}/.tuffous/archive", self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_repo;
+
+    fn completed(name: &str, days_ago: i64, father: Option<u64>) -> Todo {
+        let mut todo = Todo::create(String::from(name));
+        todo.completed = true;
+        todo.completion_date = Some(Utc::now().naive_utc() - Duration::days(days_ago));
+        todo.dependents.extend(father);
+        todo
+    }
+
+    #[test]
+    fn archivable_subtrees() {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let old = completed("Move", 30, None);
+        let old_id = old.id();
+        let packed = completed("Pack", 20, Some(old_id));
+        let recent = completed("Clean", 1, Some(old_id));
+        let lone = completed("Call", 20, None);
+        let mut open = Todo::create(String::from("Sell"));
+        open.creation_date -= Duration::days(60);
+        let (packed_id, recent_id, lone_id) = (packed.id(), recent.id(), lone.id());
+        for todo in [old, packed, recent, lone, open] {
+            instance.insert(todo);
+        }
+
+        // A father stays while one of its children is too recent
+        let before = Utc::now().naive_utc() - Duration::days(7);
+        assert_eq!(instance.archivable(before), [packed_id, lone_id]);
+        instance.get_mut(recent_id).unwrap().completion_date = Some(before - Duration::days(1));
+        assert_eq!(
+            instance.archivable(before),
+            [old_id, packed_id, recent_id, lone_id]
+        );
+    }
+
+    #[test]
+    fn archive_and_reopen() {
+        let path = temp_repo("archive");
+        let mut instance = TodoInstance::create(&path);
+        let father = completed("Move", 30, None);
+        let father_id = father.id();
+        let child = completed("Pack", 30, Some(father_id));
+        let child_id = child.id();
+        instance.insert(father);
+        instance.insert(child);
+        instance.write_all();
+
+        instance.archive(&[father_id, child_id]).unwrap();
+        assert!(instance.todos.is_empty());
+        let mut archived = instance.archived_ids();
+        archived.sort();
+        let mut ids = vec![father_id, child_id];
+        ids.sort();
+        assert_eq!(archived, ids);
+
+        let mut instance = TodoInstance::create(&path);
+        instance.read_all();
+        assert!(instance.todos.is_empty());
+        instance.read_archive();
+        assert_eq!(instance.todos.len(), 2);
+        assert!(instance.is_archived(instance.get(child_id).unwrap()));
+        // Archived todos keep their links
+        assert_eq!(instance.get(child_id).unwrap().dependents, [father_id]);
+
+        // Reopening a todo brings it back
+        instance.get_mut(child_id).unwrap().set_completed(false);
+        instance.write_all();
+        instance.unload_archive();
+        assert_eq!(instance.todos(), [child_id]);
+        assert_eq!(instance.archived_ids(), [father_id]);
+        let mut instance = TodoInstance::create(&path);
+        instance.read_all();
+        assert_eq!(instance.todos(), [child_id]);
+    }
+}
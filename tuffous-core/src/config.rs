@@ -0,0 +1,73 @@
+use crate::{order::SortKey, palette::Rgb, progress::Strategy, storage::Storage};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// Settings of a todo repo shared by all the frontends.
+#[derive(Deserialize, Serialize, Default)]
+pub struct RepoConfig {
+    #[serde(default)]
+    pub secondary_sort: Vec<SortKey>,
+    #[serde(default)]
+    pub perspectives: Vec<Perspective>,
+    /// Completed todos are archived this many days after their completion.
+    #[serde(default)]
+    pub archive_after_days: Option<u32>,
+    /// Trashed todos are deleted this many days after their removal.
+    #[serde(default)]
+    pub trash_purge_days: Option<u32>,
+    /// How the progress of todos is measured, unless set on them.
+    #[serde(default)]
+    pub progress: Strategy,
+    /// How the todos are stored, changed with [`crate::storage::convert`].
+    #[serde(default)]
+    pub storage: Storage,
+}
+
+/// A saved query shown as a view, like "Waiting on others".
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct Perspective {
+    pub name: String,
+    /// See [`crate::query`] for the syntax.
+    pub query: String,
+    /// The glyph shown in the GUI sidebar.
+    #[serde(default)]
+    pub icon: Option<char>,
+    #[serde(default)]
+    pub color: Option<Rgb>,
+    /// Whether completed todos are shown too.
+    #[serde(default)]
+    pub completed: bool,
+}
+
+impl RepoConfig {
+    pub fn get(path: &str) -> Self {
+        Self::read_from_file(Self::path(path)).unwrap_or_default()
+    }
+
+    /// The perspective with the name, case-insensitively.
+    pub fn perspective(&self, name: &str) -> Option<&Perspective> {
+        self.perspectives
+            .iter()
+            .find(|perspective| perspective.name.to_lowercase() == name.to_lowercase())
+    }
+
+    pub fn read_from_file<P: AsRef<Path>>(path: P) -> Option<Self> {
+        if let Ok(s) = fs::read_to_string(path) {
+            if let Ok(x) = serde_json::from_str::<Self>(&s) {
+                return Some(x);
+            }
+        };
+        None
+    }
+
+    /// Write the config through a temporary file, so it's never left half written.
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        let temp = format!("{}.tmp", Self::path(path));
+        fs::write(&temp, serde_json::to_string(&self).unwrap())?;
+        fs::rename(temp, Self::path(path))
+    }
+
+    fn path(path: &str) -> String {
+        format!("{path}/.tuffous/config.json")
+    }
+}
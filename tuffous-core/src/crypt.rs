@@ -0,0 +1,698 @@
+//! Encrypted repos keep their todos sealed with XChaCha20-Poly1305.
+//!
+//! The data keys are random and kept in `.tuffous/key.json`, each sealed with
+//! a key derived from the passphrase by Argon2id. Every sealed text names the
+//! key it was sealed with, like `$tfs1$2$<hex>`, so rotating to a new key
+//! can be interrupted without losing anything: the old keys are only dropped
+//! once every file is sealed with the new one. Texts are bound to the file
+//! they are in, relative to the repo, so they can't be moved to another file
+//! or todo. Texts that aren't sealed are refused, except by fsck.
+//!
+//! The keys of unlocked repos are kept for the whole process by the path of
+//! the repo, since todos are read and written by path. Writing to a locked
+//! repo fails rather than leaving todos in plaintext. The config files are
+//! left in plaintext.
+
+use crate::storage::{self, LOG_FILE};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, Generate, KeyInit, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+const KEY_FILE: &str = ".tuffous/key.json";
+const PREFIX: &str = "$tfs1$";
+const NONCE_LEN: usize = 24;
+
+/// The variable holding the passphrase.
+pub const PASSPHRASE_VAR: &str = "TUFFOUS_PASSPHRASE";
+/// The variable naming a file that holds the passphrase.
+pub const KEYFILE_VAR: &str = "TUFFOUS_KEYFILE";
+
+/// The directories and files that hold todos, relative to the repo.
+const SEALED_DIRS: [&str; 4] = [
+    ".tuffous/todos",
+    ".tuffous/archive",
+    ".tuffous/trash",
+    LOST_DIR,
+];
+const LOST_DIR: &str = ".tuffous/lost";
+const SEALED_FILES: [&str; 2] = [".tuffous/index.json", LOG_FILE];
+
+/// The keys of the unlocked repos, by the canonical path of the repo.
+static KEYRINGS: RwLock<BTreeMap<PathBuf, Keyring>> = RwLock::new(BTreeMap::new());
+
+#[derive(Clone)]
+struct Keyring {
+    keys: BTreeMap<u32, Key>,
+    current: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    /// Hex of the salt of the passphrase.
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    /// The data keys sealed with the key of the passphrase, by id.
+    keys: BTreeMap<u32, String>,
+    /// The key new texts are sealed with.
+    current: u32,
+}
+
+impl KeyFile {
+    fn read(path: &str) -> Result<KeyFile, String> {
+        let s = fs::read_to_string(key_path(path)).map_err(|err| err.to_string())?;
+        serde_json::from_str(&s).map_err(|err| format!("{KEY_FILE} is invalid: {err}"))
+    }
+
+    fn write(&self, path: &str) -> Result<(), String> {
+        let temp = format!("{}.tmp", key_path(path));
+        fs::write(&temp, serde_json::to_string(self).unwrap()).map_err(|err| err.to_string())?;
+        fs::rename(temp, key_path(path)).map_err(|err| err.to_string())
+    }
+
+    /// A key file sealing the keys of the keyring with the passphrase.
+    fn seal(keyring: &Keyring, passphrase: &str) -> Result<KeyFile, String> {
+        let mut salt = [0; 16];
+        getrandom::fill(&mut salt).map_err(|err| err.to_string())?;
+        let params = Params::default();
+        let mut file = KeyFile {
+            salt: to_hex(&salt),
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+            keys: BTreeMap::new(),
+            current: keyring.current,
+        };
+        let master = file.derive(passphrase)?;
+        for (id, key) in &keyring.keys {
+            file.keys
+                .insert(*id, to_hex(&encrypt(&master, &key_aad(*id), key)));
+        }
+        Ok(file)
+    }
+
+    fn derive(&self, passphrase: &str) -> Result<Key, String> {
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|err| err.to_string())?;
+        let salt = from_hex(&self.salt).ok_or("The salt of the key file is invalid")?;
+        let mut key = [0; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|err| err.to_string())?;
+        Ok(Key::from(key))
+    }
+
+    fn open(&self, passphrase: &str) -> Result<Keyring, String> {
+        let master = self.derive(passphrase)?;
+        let mut keys = BTreeMap::new();
+        for (id, sealed) in &self.keys {
+            let key = from_hex(sealed)
+                .and_then(|sealed| decrypt(&master, &key_aad(*id), &sealed))
+                .ok_or("Wrong passphrase")?;
+            keys.insert(
+                *id,
+                Key::try_from(key.as_slice()).map_err(|_| "A key of the key file is invalid")?,
+            );
+        }
+        Ok(Keyring {
+            keys,
+            current: self.current,
+        })
+    }
+}
+
+/// Whether the repo at the path is encrypted.
+pub fn is_encrypted(path: &str) -> bool {
+    Path::new(&key_path(path)).exists()
+}
+
+/// Whether the keys of the repo at the path were unlocked.
+pub fn is_unlocked(path: &str) -> bool {
+    repo_of(Path::new(path)).is_some_and(|repo| KEYRINGS.read().unwrap().contains_key(&repo))
+}
+
+/// The passphrase of the `TUFFOUS_PASSPHRASE` variable, or else of the file
+/// named by `TUFFOUS_KEYFILE`.
+pub fn passphrase_from_env() -> Result<Option<String>, String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(Some(passphrase));
+    }
+    match std::env::var(KEYFILE_VAR) {
+        Ok(file) => read_keyfile(&file).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The passphrase in the file, without the line break it may end with.
+pub fn read_keyfile(file: &str) -> Result<String, String> {
+    let s = fs::read_to_string(file).map_err(|err| format!("Can't read {file}: {err}"))?;
+    Ok(s.trim_end_matches(['\n', '\r']).to_owned())
+}
+
+/// Unlock the repo at the path, to read and write its todos.
+pub fn unlock(path: &str, passphrase: &str) -> Result<(), String> {
+    let keyring = KeyFile::read(path)?.open(passphrase)?;
+    set_keyring(path, Some(keyring))
+}
+
+/// Forget the keys of the repo at the path.
+pub fn lock(path: &str) {
+    let _ = set_keyring(path, None);
+}
+
+/// Encrypt the repo at the path with the passphrase.
+/// Returns how many files were sealed.
+pub fn enable(path: &str, passphrase: &str) -> Result<usize, String> {
+    if is_encrypted(path) {
+        return Err(String::from("The repo is already encrypted"));
+    }
+    let keyring = Keyring {
+        keys: BTreeMap::from([(1, Key::generate())]),
+        current: 1,
+    };
+    // Files left in plaintext if this is interrupted aren't read until
+    // fsck seals them
+    KeyFile::seal(&keyring, passphrase)?.write(path)?;
+    set_keyring(path, Some(keyring))?;
+    reseal(path)
+}
+
+/// Decrypt the unlocked repo at the path for good.
+/// Returns how many files were opened.
+pub fn disable(path: &str) -> Result<usize, String> {
+    let keyring = unlocked(path)?;
+    // Nothing is written until the key file is removed
+    set_keyring(path, None)?;
+    let count = rewrite(path, files(path), |file, text| {
+        open_with(&keyring, file, text)
+    });
+    match count {
+        Ok(count) => {
+            fs::remove_file(key_path(path)).map_err(|err| err.to_string())?;
+            Ok(count)
+        }
+        Err(err) => {
+            set_keyring(path, Some(keyring))?;
+            Err(err)
+        }
+    }
+}
+
+/// Seal the unlocked repo at the path with a new key and passphrase.
+/// Returns how many files were sealed again.
+pub fn rotate(path: &str, passphrase: &str) -> Result<usize, String> {
+    let mut keyring = unlocked(path)?;
+    let id = keyring.keys.keys().max().unwrap_or(&0) + 1;
+    keyring.keys.insert(id, Key::generate());
+    keyring.current = id;
+    KeyFile::seal(&keyring, passphrase)?.write(path)?;
+    set_keyring(path, Some(keyring.clone()))?;
+
+    let count = reseal(path)?;
+    keyring.keys.retain(|key, _| *key == id);
+    KeyFile::seal(&keyring, passphrase)?.write(path)?;
+    set_keyring(path, Some(keyring))?;
+    Ok(count)
+}
+
+/// The files of the repo at the path that aren't sealed with the current key.
+/// Files with text sealed with a key the repo doesn't have are left out, since
+/// they can't be sealed again.
+pub fn unsealed(path: &str) -> Vec<PathBuf> {
+    let Ok(Some(keyring)) = keyring(Path::new(path)) else {
+        return Vec::new();
+    };
+    let current = format!("{PREFIX}{}$", keyring.current);
+    files(path)
+        .into_iter()
+        .filter(|file| {
+            let full = Path::new(path).join(file);
+            fs::read_to_string(&full).is_ok_and(|s| {
+                let mut stale = s
+                    .lines()
+                    .filter(|line| !line.is_empty() && !line.starts_with(&current))
+                    .peekable();
+                stale.peek().is_some() && stale.all(|line| has_key(&full, line))
+            })
+        })
+        .collect()
+}
+
+/// Seal the files of the repo at the path with the current key.
+pub fn reseal(path: &str) -> Result<usize, String> {
+    let keyring = unlocked(path)?;
+    rewrite(path, files(path), |file, text| {
+        open_with(&keyring, file, text).map(|text| seal_with(&keyring, file, &text))
+    })
+}
+
+/// Seal the file of the repo at the path with the current key.
+pub fn reseal_file(path: &str, file: &Path) -> Result<(), String> {
+    let keyring = unlocked(path)?;
+    rewrite(path, vec![file.to_path_buf()], |file, text| {
+        open_with(&keyring, file, text).map(|text| seal_with(&keyring, file, &text))
+    })
+    .map(|_| ())
+}
+
+/// Seal the text of the file with the current key of the repo holding it,
+/// if it's encrypted. Fails if the repo is locked.
+pub(crate) fn seal<P: AsRef<Path>>(file: P, text: String) -> Result<String, String> {
+    Ok(match keyring(file.as_ref())? {
+        Some(keyring) => seal_with(&keyring, &name_in_repo(file.as_ref())?, &text),
+        None => text,
+    })
+}
+
+/// Open the sealed text of the file. Texts of repos that aren't encrypted
+/// are returned as they are, and texts that aren't sealed in encrypted repos
+/// are refused.
+pub(crate) fn open<P: AsRef<Path>>(file: P, text: &str) -> Result<String, String> {
+    match keyring(file.as_ref())? {
+        Some(_) if !text.starts_with(PREFIX) => Err(String::from("The text isn't encrypted")),
+        _ => open_unsealed(file, text),
+    }
+}
+
+/// Open the text of the file like [`open`], but return the texts that aren't
+/// sealed as they are even in encrypted repos, for fsck to find them.
+pub(crate) fn open_unsealed<P: AsRef<Path>>(file: P, text: &str) -> Result<String, String> {
+    if !text.starts_with(PREFIX) {
+        return Ok(text.to_owned());
+    }
+    match keyring(file.as_ref())? {
+        Some(keyring) => open_with(&keyring, &name_in_repo(file.as_ref())?, text),
+        None => Err(String::from("The key file of the repo is missing")),
+    }
+}
+
+/// Whether the text is plaintext or sealed with a key of the unlocked repo
+/// holding the file, so failing to open it means it was altered rather than
+/// locked.
+pub(crate) fn has_key<P: AsRef<Path>>(file: P, text: &str) -> bool {
+    let Some(rest) = text.strip_prefix(PREFIX) else {
+        return true;
+    };
+    let id = rest
+        .split_once('$')
+        .and_then(|(id, _)| id.parse::<u32>().ok());
+    match (keyring(file.as_ref()), id) {
+        (Ok(Some(keyring)), Some(id)) => keyring.keys.contains_key(&id),
+        // Not a key id, so the text is invalid whatever the keys
+        (Ok(Some(_)), None) => true,
+        _ => false,
+    }
+}
+
+/// Read the file and open it.
+pub(crate) fn read<P: AsRef<Path>>(file: P) -> Option<String> {
+    open(&file, &fs::read_to_string(&file).ok()?).ok()
+}
+
+/// Seal the text and write it to the file.
+pub(crate) fn write<P: AsRef<Path>>(file: P, text: String) -> Result<(), String> {
+    fs::write(&file, seal(&file, text)?).map_err(|err| err.to_string())
+}
+
+/// The keys of the repo holding the path, `None` if it isn't encrypted.
+/// Fails if it's locked.
+fn keyring(at: &Path) -> Result<Option<Keyring>, String> {
+    let Some(repo) = repo_of(at) else {
+        return Ok(None);
+    };
+    if let Some(keyring) = KEYRINGS.read().unwrap().get(&repo) {
+        return Ok(Some(keyring.clone()));
+    }
+    if repo.join(KEY_FILE).exists() {
+        Err(String::from("The repo is locked"))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The keys of the unlocked repo at the path.
+fn unlocked(path: &str) -> Result<Keyring, String> {
+    keyring(Path::new(path))?.ok_or_else(|| String::from("The repo isn't encrypted"))
+}
+
+fn set_keyring(path: &str, keyring: Option<Keyring>) -> Result<(), String> {
+    let repo = repo_of(Path::new(path)).ok_or("Not a todo repo")?;
+    let mut keyrings = KEYRINGS.write().unwrap();
+    match keyring {
+        Some(keyring) => keyrings.insert(repo, keyring),
+        None => keyrings.remove(&repo),
+    };
+    Ok(())
+}
+
+/// The repo holding the path, the first of its ancestors with a `.tuffous`
+/// directory. Files that don't exist yet are held by the repo of their directory.
+fn repo_of(at: &Path) -> Option<PathBuf> {
+    locate(at).map(|(repo, _)| repo)
+}
+
+/// The repo holding the path and the path relative to it.
+fn locate(at: &Path) -> Option<(PathBuf, PathBuf)> {
+    let at = fs::canonicalize(at)
+        .ok()
+        .or_else(|| Some(fs::canonicalize(at.parent()?).ok()?.join(at.file_name()?)))?;
+    let repo = at.ancestors().find(|dir| dir.join(".tuffous").is_dir())?;
+    Some((
+        repo.to_path_buf(),
+        at.strip_prefix(repo).ok()?.to_path_buf(),
+    ))
+}
+
+/// The name of the file in its repo, like `.tuffous/todos/42.json`, which
+/// texts are bound to.
+fn name_in_repo(file: &Path) -> Result<String, String> {
+    locate(file)
+        .map(|(_, file)| name(&file))
+        .ok_or_else(|| format!("{} isn't in a todo repo", file.display()))
+}
+
+/// The path relative to the repo with `/` between its components, the same
+/// on every platform.
+fn name(file: &Path) -> String {
+    file.components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn seal_with(keyring: &Keyring, file: &str, text: &str) -> String {
+    let key = &keyring.keys[&keyring.current];
+    format!(
+        "{PREFIX}{}${}",
+        keyring.current,
+        to_hex(&encrypt(key, file.as_bytes(), text.as_bytes()))
+    )
+}
+
+/// Open the text sealed in the file, returning plaintext as it is.
+fn open_with(keyring: &Keyring, file: &str, text: &str) -> Result<String, String> {
+    let Some(rest) = text.strip_prefix(PREFIX) else {
+        return Ok(text.to_owned());
+    };
+    let (id, hex) = rest.split_once('$').ok_or("The sealed text is invalid")?;
+    let key = id
+        .parse::<u32>()
+        .ok()
+        .and_then(|id| keyring.keys.get(&id))
+        .ok_or("The key of the sealed text is missing")?;
+    let sealed = from_hex(hex.trim_end()).ok_or("The sealed text is invalid")?;
+    let plain = decrypt(key, file.as_bytes(), &sealed)
+        .ok_or("The sealed text was altered or moved from another file")?;
+    String::from_utf8(plain).map_err(|err| err.to_string())
+}
+
+/// Rewrite every line of the files with the function, given the name of the
+/// file in the repo and the line.
+fn rewrite(
+    path: &str,
+    files: Vec<PathBuf>,
+    f: impl Fn(&str, &str) -> Result<String, String>,
+) -> Result<usize, String> {
+    let mut count = 0;
+    for file in files {
+        let full = Path::new(path).join(&file);
+        // Lines appended to the log meanwhile would be lost
+        let _lock = match file == Path::new(LOG_FILE) {
+            true => Some(storage::lock(path)?),
+            false => None,
+        };
+        let Ok(text) = fs::read_to_string(&full) else {
+            continue;
+        };
+        let name = name(&file);
+        let lines: Result<Vec<String>, String> = text
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| f(&name, line))
+            .collect();
+        let lines = match lines {
+            Ok(lines) => lines,
+            // Lost files may be anything, they are left as they are
+            Err(_) if file.starts_with(LOST_DIR) => continue,
+            Err(err) => return Err(format!("{}: {err}", file.display())),
+        };
+        let mut text = lines.join("\n");
+        if file.extension().is_some_and(|ext| ext == "jsonl") && !text.is_empty() {
+            text.push('\n');
+        }
+        let temp = full.with_extension("tmp");
+        fs::write(&temp, text).map_err(|err| err.to_string())?;
+        fs::rename(temp, full).map_err(|err| err.to_string())?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// The files holding todos, relative to the repo.
+fn files(path: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = SEALED_FILES
+        .iter()
+        .map(PathBuf::from)
+        .filter(|file| Path::new(path).join(file).exists())
+        .collect();
+    for dir in SEALED_DIRS {
+        if let Ok(entries) = fs::read_dir(Path::new(path).join(dir)) {
+            files.extend(
+                entries
+                    .flatten()
+                    .map(|entry| Path::new(dir).join(entry.file_name())),
+            );
+        }
+    }
+    files
+}
+
+/// The data bound to a key sealed in the key file, so keys can't be swapped.
+fn key_aad(id: u32) -> Vec<u8> {
+    format!("key {id}").into_bytes()
+}
+
+fn encrypt(key: &Key, aad: &[u8], msg: &[u8]) -> Vec<u8> {
+    let nonce = XNonce::generate();
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        XChaCha20Poly1305::new(key)
+            .encrypt(&nonce, Payload { msg, aad })
+            .unwrap(),
+    );
+    sealed
+}
+
+fn decrypt(key: &Key, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, cipher) = sealed.split_at(NONCE_LEN);
+    let nonce = XNonce::try_from(nonce).ok()?;
+    XChaCha20Poly1305::new(key)
+        .decrypt(&nonce, Payload { msg: cipher, aad })
+        .ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn key_path(path: &str) -> String {
+    format!("{path}/{KEY_FILE}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{temp_repo, Todo, TodoInstance};
+
+    /// A repo with a todo written, and the id of the todo.
+    fn repo(name: &str) -> (String, u64) {
+        let path = temp_repo(name);
+        let mut todo = Todo::create(String::from("Call the client"));
+        todo.metadata.set_details(String::from("ACME Corp"));
+        let id = todo.id();
+        let mut instance = TodoInstance::create(&path);
+        instance.insert(todo);
+        instance.write_all();
+        (path, id)
+    }
+
+    fn details(path: &str, id: u64) -> Option<String> {
+        let mut instance = TodoInstance::create(path);
+        instance.read_all();
+        instance
+            .get(id)
+            .map(|todo| todo.metadata.details().to_owned())
+    }
+
+    #[test]
+    fn enable_and_disable() {
+        let (path, id) = repo("crypt-enable");
+        let file = format!("{path}/.tuffous/todos/{id}.json");
+        assert_eq!(enable(&path, "passphrase"), Ok(1));
+        assert!(enable(&path, "passphrase").is_err());
+        let sealed = fs::read_to_string(&file).unwrap();
+        assert!(sealed.starts_with("$tfs1$1$"));
+        assert!(!sealed.contains("ACME"));
+        assert_eq!(details(&path, id).as_deref(), Some("ACME Corp"));
+
+        lock(&path);
+        assert!(!is_unlocked(&path));
+        assert_eq!(details(&path, id), None);
+        assert_eq!(
+            unlock(&path, "wrong"),
+            Err(String::from("Wrong passphrase"))
+        );
+        unlock(&path, "passphrase").unwrap();
+        assert_eq!(details(&path, id).as_deref(), Some("ACME Corp"));
+
+        assert!(disable(&path).is_ok());
+        assert!(!is_encrypted(&path));
+        assert!(fs::read_to_string(&file).unwrap().contains("ACME Corp"));
+        assert_eq!(details(&path, id).as_deref(), Some("ACME Corp"));
+    }
+
+    #[test]
+    fn locked_repos_are_not_written() {
+        let (path, _) = repo("crypt-locked");
+        enable(&path, "passphrase").unwrap();
+        lock(&path);
+        assert_eq!(
+            seal(&path, String::from("secret")),
+            Err(String::from("The repo is locked"))
+        );
+
+        let todo = Todo::create(String::from("secret"));
+        let id = todo.id();
+        let mut instance = TodoInstance::create(&path);
+        instance.insert(todo);
+        instance.write_all();
+        assert!(!Path::new(&format!("{path}/.tuffous/todos/{id}.json")).exists());
+
+        // Kept to be written once the repo is unlocked
+        unlock(&path, "passphrase").unwrap();
+        instance.write_all();
+        let sealed = fs::read_to_string(format!("{path}/.tuffous/todos/{id}.json")).unwrap();
+        assert!(sealed.starts_with(PREFIX));
+    }
+
+    #[test]
+    fn open_rejects_other_keys() {
+        let (a, _) = repo("crypt-key-a");
+        let (b, _) = repo("crypt-key-b");
+        enable(&a, "passphrase").unwrap();
+        enable(&b, "passphrase").unwrap();
+        let sealed = seal(&a, String::from("secret")).unwrap();
+        assert_eq!(open(&a, &sealed), Ok(String::from("secret")));
+        // Sealed with the key 1 of another repo
+        assert!(open(&b, &sealed).is_err());
+        assert!(has_key(&b, &sealed));
+
+        let mut altered = sealed.clone();
+        let last = if altered.ends_with('0') { "1" } else { "0" };
+        altered.replace_range(altered.len() - 1.., last);
+        assert!(open(&a, &altered).is_err());
+        assert!(!has_key(&a, "$tfs1$9$00"));
+        // Plaintext is only taken by fsck
+        assert!(open(&a, "plain").is_err());
+        assert_eq!(open_unsealed(&a, "plain"), Ok(String::from("plain")));
+        assert_eq!(
+            open(temp_repo("crypt-key-c"), "plain"),
+            Ok(String::from("plain"))
+        );
+    }
+
+    #[test]
+    fn texts_are_bound_to_their_file() {
+        let path = temp_repo("crypt-bound");
+        let mut instance = TodoInstance::create(&path);
+        let first = Todo::create(String::from("First"));
+        let second = Todo::create(String::from("Second"));
+        let (first, second) = (first.id(), {
+            let id = second.id();
+            instance.insert(first);
+            instance.insert(second);
+            id
+        });
+        instance.write_all();
+        enable(&path, "passphrase").unwrap();
+
+        // Swapping the sealed files of two todos doesn't swap the todos
+        let file = |id: u64| format!("{path}/.tuffous/todos/{id}.json");
+        let text = fs::read_to_string(file(first)).unwrap();
+        fs::write(file(second), &text).unwrap();
+        assert!(open(file(second), &text).is_err());
+        assert!(Todo::read_from_file(file(second)).is_none());
+        assert_eq!(
+            Todo::read_from_file(file(first)).map(|todo| todo.metadata.name),
+            Some(String::from("First"))
+        );
+
+        // and neither does writing plaintext to them
+        let mut forged = Todo::read_from_file(file(first)).unwrap();
+        forged.metadata.name = String::from("Forged");
+        fs::write(file(first), serde_json::to_string(&forged).unwrap()).unwrap();
+        assert!(Todo::read_from_file(file(first)).is_none());
+    }
+
+    #[test]
+    fn rotate() {
+        let (path, id) = repo("crypt-rotate");
+        enable(&path, "old passphrase").unwrap();
+        assert_eq!(super::rotate(&path, "new passphrase"), Ok(1));
+        let sealed = fs::read_to_string(format!("{path}/.tuffous/todos/{id}.json")).unwrap();
+        assert!(sealed.starts_with("$tfs1$2$"));
+
+        lock(&path);
+        assert!(unlock(&path, "old passphrase").is_err());
+        unlock(&path, "new passphrase").unwrap();
+        assert_eq!(details(&path, id).as_deref(), Some("ACME Corp"));
+        assert!(unsealed(&path).is_empty());
+    }
+
+    #[test]
+    fn interrupted_rotation() {
+        let (path, id) = repo("crypt-interrupted");
+        enable(&path, "passphrase").unwrap();
+        // The new key was saved, but nothing was sealed with it yet
+        let mut keyring = unlocked(&path).unwrap();
+        keyring.keys.insert(2, Key::generate());
+        keyring.current = 2;
+        KeyFile::seal(&keyring, "passphrase")
+            .unwrap()
+            .write(&path)
+            .unwrap();
+        lock(&path);
+        unlock(&path, "passphrase").unwrap();
+
+        assert_eq!(details(&path, id).as_deref(), Some("ACME Corp"));
+        let file = PathBuf::from(format!(".tuffous/todos/{id}.json"));
+        assert_eq!(unsealed(&path), std::slice::from_ref(&file));
+        reseal_file(&path, &file).unwrap();
+        assert!(unsealed(&path).is_empty());
+        let sealed = fs::read_to_string(Path::new(&path).join(&file)).unwrap();
+        assert!(sealed.starts_with("$tfs1$2$"));
+    }
+}
@@ -0,0 +1,659 @@
+//! Checks of the files of a repo, for what reading it skips or fixes silently.
+
+use crate::{
+    crypt,
+    storage::{self, Replay, LOG_FILE},
+    Todo,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Where files that can't be repaired are moved, to be looked at by hand.
+pub(crate) const LOST_DIR: &str = ".tuffous/lost";
+
+/// The directories todo files are kept in.
+const TODO_DIRS: [&str; 2] = [".tuffous/todos", ".tuffous/archive"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// A file that isn't a todo.
+    Unparsable {
+        file: PathBuf,
+        error: String,
+    },
+    /// A todo in a file not named after its id.
+    WrongFileName {
+        file: PathBuf,
+        id: u64,
+    },
+    /// Todos with the same id in several files.
+    DuplicateId {
+        id: u64,
+        files: Vec<PathBuf>,
+    },
+    /// A todo with a father that doesn't exist.
+    DanglingFather {
+        file: PathBuf,
+        id: u64,
+        father: u64,
+    },
+    /// Todos that are their own ancestors, each a child of the next and the
+    /// last a child of the first.
+    Cycle {
+        files: Vec<PathBuf>,
+        ids: Vec<u64>,
+    },
+    EmptyName {
+        file: PathBuf,
+        id: u64,
+    },
+    /// The repo is encrypted and wasn't unlocked.
+    Locked,
+    /// A file of an encrypted repo that isn't sealed with its current key.
+    Unsealed {
+        file: PathBuf,
+    },
+    /// A file, or a line of the log, encrypted with a key the unlocked repo
+    /// doesn't have.
+    MissingKey {
+        file: PathBuf,
+        line: Option<usize>,
+    },
+    /// A line of the log that is neither a todo nor a tombstone.
+    InvalidLine {
+        line: usize,
+        error: String,
+    },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Unparsable { file, error } => write!(
+                f,
+                "{} isn't a valid todo ({error}), so it's skipped when the repo is read",
+                file.display()
+            ),
+            Problem::WrongFileName { file, id } => write!(
+                f,
+                "{} holds the todo {id}, so saving it writes a second file",
+                file.display()
+            ),
+            Problem::DuplicateId { id, files } => write!(
+                f,
+                "the todo {id} is in {} files, so only one of them is used: {}",
+                files.len(),
+                files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Problem::DanglingFather { file, id, father } => write!(
+                f,
+                "the todo {id} in {} is a child of {father}, which doesn't exist, \
+                 so the link is dropped the next time it's saved",
+                file.display()
+            ),
+            Problem::Cycle { ids, .. } => write!(
+                f,
+                "the todos {} are their own ancestors, so their trees never end",
+                ids.iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            ),
+            Problem::EmptyName { file, id } => write!(
+                f,
+                "the todo {id} in {} has no name, so it can't be found by name",
+                file.display()
+            ),
+            Problem::Locked => write!(f, "the repo is encrypted, so it can't be checked locked"),
+            Problem::Unsealed { file } => write!(
+                f,
+                "{} isn't encrypted with the current key, as if encrypting the repo \
+                 or rotating its key was interrupted",
+                file.display()
+            ),
+            Problem::MissingKey { file, line } => write!(
+                f,
+                "{}{} is encrypted with a key the repo doesn't have, \
+                 so it's skipped when the repo is read",
+                match line {
+                    Some(line) => format!("the line {line} of "),
+                    None => String::new(),
+                },
+                file.display()
+            ),
+            Problem::InvalidLine { line, error } => write!(
+                f,
+                "the line {line} of {LOG_FILE} isn't a todo ({error}), \
+                 so it's skipped when the repo is read"
+            ),
+        }
+    }
+}
+
+impl Problem {
+    /// What [`Problem::fix`] does.
+    pub fn remedy(&self) -> String {
+        match self {
+            Problem::Unparsable { .. } => format!("move the file to {LOST_DIR}"),
+            Problem::WrongFileName { id, .. } => format!("rename the file to {id}.json"),
+            Problem::DuplicateId { .. } => format!(
+                "keep the file named after the id, or else the last modified, \
+                 and move the others to {LOST_DIR}"
+            ),
+            Problem::DanglingFather { .. } => String::from("unlink the todo from the father"),
+            Problem::Cycle { ids, .. } => {
+                format!("unlink {} from {}", ids[0], ids.get(1).unwrap_or(&ids[0]))
+            }
+            Problem::EmptyName { .. } => String::from("name the todo \"untitled todo\""),
+            Problem::Locked => String::from("unlock the repo first"),
+            Problem::Unsealed { .. } => String::from("encrypt the file with the current key"),
+            Problem::MissingKey { .. } => {
+                String::from("none, restore the key file it was encrypted with")
+            }
+            Problem::InvalidLine { .. } => {
+                format!("compact the log, moving the line to {LOST_DIR}/todos.jsonl")
+            }
+        }
+    }
+
+    /// Repair the files of the repo at the path.
+    pub fn fix(&self, path: &str) -> Result<(), String> {
+        match self {
+            Problem::Unparsable { file, .. } => move_to_lost(path, file),
+            Problem::WrongFileName { file, id } => {
+                let target = file.with_file_name(format!("{id}.json"));
+                if Path::new(path).join(&target).exists() {
+                    return Err(format!("{} exists", target.display()));
+                }
+                // Sealed again, since sealed texts are bound to their file
+                let from = Path::new(path).join(file);
+                let text = fs::read_to_string(&from).map_err(|err| err.to_string())?;
+                crypt::write(
+                    Path::new(path).join(target),
+                    crypt::open_unsealed(&from, &text)?,
+                )?;
+                fs::remove_file(from).map_err(|err| err.to_string())
+            }
+            Problem::DuplicateId { id, files } => {
+                let named = files
+                    .iter()
+                    .find(|file| file.file_name() == Some(format!("{id}.json").as_ref()));
+                let newest = files.iter().max_by_key(|file| {
+                    fs::metadata(Path::new(path).join(file))
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                });
+                // The log is never moved, it holds the other todos too
+                let log = files.iter().find(|file| is_log(file));
+                let kept = log.or(named).or(newest);
+                for file in files {
+                    if Some(file) != kept {
+                        move_to_lost(path, file)?;
+                    }
+                }
+                Ok(())
+            }
+            Problem::DanglingFather { file, id, father } => edit(path, file, *id, |todo| {
+                todo.dependents.retain(|dep| dep != father)
+            }),
+            Problem::Cycle { files, ids } => {
+                let father = *ids.get(1).unwrap_or(&ids[0]);
+                edit(path, &files[0], ids[0], |todo| {
+                    todo.dependents.retain(|dep| *dep != father)
+                })
+            }
+            Problem::EmptyName { file, id } => edit(path, file, *id, |todo| {
+                todo.metadata.name = String::from("untitled todo")
+            }),
+            Problem::Locked => Err(String::from("the repo is locked")),
+            Problem::Unsealed { file } => crypt::reseal_file(path, file),
+            Problem::MissingKey { .. } => Err(String::from("the key is missing")),
+            Problem::InvalidLine { .. } => storage::drop_invalid(path).map(|_| ()),
+        }
+    }
+
+    /// Whether fixing the problem changes which files hold todos, so the
+    /// other problems have to be checked again.
+    fn moves_files(&self) -> bool {
+        matches!(
+            self,
+            Problem::Unparsable { .. }
+                | Problem::WrongFileName { .. }
+                | Problem::DuplicateId { .. }
+                | Problem::InvalidLine { .. }
+        )
+    }
+}
+
+/// Check the todo files and the log of the repo at the path.
+///
+/// Problems with the files come first, since the others are found in the
+/// todos that would be read.
+pub fn check(path: &str) -> Vec<Problem> {
+    if crypt::is_encrypted(path) && !crypt::is_unlocked(path) {
+        return vec![Problem::Locked];
+    }
+    let mut problems: Vec<Problem> = crypt::unsealed(path)
+        .into_iter()
+        .map(|file| Problem::Unsealed { file })
+        .collect();
+    let mut todos: Vec<(PathBuf, Todo)> = Vec::new();
+
+    for dir in TODO_DIRS {
+        let Ok(entries) = fs::read_dir(Path::new(path).join(dir)) else {
+            continue;
+        };
+        let mut files: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| Path::new(dir).join(entry.file_name()))
+            .collect();
+        files.sort();
+
+        for file in files {
+            let full = Path::new(path).join(&file);
+            let text = fs::read_to_string(&full);
+            if text.as_deref().is_ok_and(|s| !crypt::has_key(&full, s)) {
+                problems.push(Problem::MissingKey { file, line: None });
+                continue;
+            }
+            let parsed = text
+                .map_err(|err| err.to_string())
+                .and_then(|s| crypt::open_unsealed(&full, &s))
+                .and_then(|s| serde_json::from_str::<Todo>(&s).map_err(|err| err.to_string()));
+            match parsed {
+                Ok(todo) => todos.push((file, todo)),
+                Err(error) => problems.push(Problem::Unparsable { file, error }),
+            }
+        }
+    }
+    if let Ok(replay) = Replay::of_unsealed(path) {
+        for line in &replay.invalid {
+            problems.push(if line.sealed {
+                Problem::MissingKey {
+                    file: PathBuf::from(LOG_FILE),
+                    line: Some(line.number),
+                }
+            } else {
+                Problem::InvalidLine {
+                    line: line.number,
+                    error: line.error.to_owned(),
+                }
+            });
+        }
+        for todo in replay.left() {
+            todos.push((PathBuf::from(LOG_FILE), todo));
+        }
+    }
+
+    let mut by_id: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    for (file, todo) in &todos {
+        by_id.entry(todo.id()).or_default().push(file.clone());
+    }
+    for (id, files) in &by_id {
+        if files.len() > 1 {
+            problems.push(Problem::DuplicateId {
+                id: *id,
+                files: files.clone(),
+            });
+        }
+    }
+    for (file, todo) in &todos {
+        if by_id[&todo.id()].len() == 1
+            && !is_log(file)
+            && file.file_name() != Some(format!("{}.json", todo.id()).as_ref())
+        {
+            problems.push(Problem::WrongFileName {
+                file: file.clone(),
+                id: todo.id(),
+            });
+        }
+    }
+
+    for (file, todo) in &todos {
+        for father in &todo.dependents {
+            if !by_id.contains_key(father) {
+                problems.push(Problem::DanglingFather {
+                    file: file.clone(),
+                    id: todo.id(),
+                    father: *father,
+                });
+            }
+        }
+        if todo.metadata.name.trim().is_empty() {
+            problems.push(Problem::EmptyName {
+                file: file.clone(),
+                id: todo.id(),
+            });
+        }
+    }
+
+    let files: HashMap<u64, &PathBuf> = todos.iter().map(|(file, t)| (t.id(), file)).collect();
+    let fathers: HashMap<u64, &Vec<u64>> =
+        todos.iter().map(|(_, t)| (t.id(), &t.dependents)).collect();
+    for ids in cycles(&fathers) {
+        problems.push(Problem::Cycle {
+            files: ids.iter().map(|id| files[id].clone()).collect(),
+            ids,
+        });
+    }
+
+    problems
+}
+
+/// Fix every problem of the repo at the path, checking again after the files
+/// are moved. Returns the problems with the result of their fix.
+pub fn repair(path: &str) -> Vec<(Problem, Result<(), String>)> {
+    let mut results: Vec<(Problem, Result<(), String>)> = Vec::new();
+    loop {
+        let problems: Vec<Problem> = check(path)
+            .into_iter()
+            .filter(|problem| results.iter().all(|(p, _)| p != problem))
+            .collect();
+        if problems.is_empty() {
+            return results;
+        }
+
+        for problem in problems {
+            let result = problem.fix(path);
+            let moved = result.is_ok() && problem.moves_files();
+            results.push((problem, result));
+            if moved {
+                break;
+            }
+        }
+    }
+}
+
+/// Every cycle of fathers once, starting from its smallest id.
+fn cycles(fathers: &HashMap<u64, &Vec<u64>>) -> Vec<Vec<u64>> {
+    fn visit(
+        id: u64,
+        fathers: &HashMap<u64, &Vec<u64>>,
+        stack: &mut Vec<u64>,
+        done: &mut HashSet<u64>,
+        found: &mut Vec<Vec<u64>>,
+    ) {
+        if let Some(start) = stack.iter().position(|i| *i == id) {
+            let mut cycle = stack[start..].to_vec();
+            let min = cycle.iter().enumerate().min_by_key(|(_, i)| **i).unwrap().0;
+            cycle.rotate_left(min);
+            if !found.contains(&cycle) {
+                found.push(cycle);
+            }
+            return;
+        }
+        if done.contains(&id) {
+            return;
+        }
+
+        stack.push(id);
+        for father in fathers.get(&id).map(|f| f.as_slice()).unwrap_or_default() {
+            visit(*father, fathers, stack, done, found);
+        }
+        stack.pop();
+        done.insert(id);
+    }
+
+    let mut ids: Vec<u64> = fathers.keys().copied().collect();
+    ids.sort();
+    let mut done = HashSet::new();
+    let mut found = Vec::new();
+    for id in ids {
+        visit(id, fathers, &mut Vec::new(), &mut done, &mut found);
+    }
+    found
+}
+
+fn is_log(file: &Path) -> bool {
+    file == Path::new(LOG_FILE)
+}
+
+/// Change the todo in the file, or add a line with the changed todo to the log.
+fn edit(path: &str, file: &Path, id: u64, f: impl FnOnce(&mut Todo)) -> Result<(), String> {
+    if is_log(file) {
+        let mut todo = Replay::of(path)?
+            .todos
+            .remove(&id)
+            .flatten()
+            .ok_or("the todo isn't in the log")?;
+        f(&mut todo);
+        return storage::append_todo(path, &todo);
+    }
+
+    let full = Path::new(path).join(file);
+    let mut todo = Todo::read_from_file(&full).ok_or("the todo can't be read")?;
+    f(&mut todo);
+    crypt::write(full, serde_json::to_string(&todo).unwrap())
+}
+
+fn move_to_lost(path: &str, file: &Path) -> Result<(), String> {
+    let lost = Path::new(path).join(LOST_DIR);
+    fs::create_dir_all(&lost).map_err(|err| err.to_string())?;
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    let mut target = lost.join(name.as_ref());
+    let mut n = 1;
+    while target.exists() {
+        target = lost.join(format!("{name}.{n}"));
+        n += 1;
+    }
+    fs::rename(Path::new(path).join(file), target).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::RepoConfig, storage::Storage, temp_repo};
+
+    fn write_todo(path: &str, file: &str, todo: &Todo) {
+        let file = Path::new(path).join(file);
+        let text = crypt::seal(&file, serde_json::to_string(todo).unwrap()).unwrap();
+        fs::write(file, text).unwrap();
+    }
+
+    fn todo_file(todo: &Todo) -> PathBuf {
+        PathBuf::from(format!(".tuffous/todos/{}.json", todo.id()))
+    }
+
+    /// Fix the only problem of the repo, which is then gone.
+    fn fix(path: &str, problem: Problem) {
+        assert_eq!(check(path), std::slice::from_ref(&problem));
+        assert_eq!(problem.fix(path), Ok(()));
+        assert_eq!(check(path), []);
+    }
+
+    #[test]
+    fn unparsable() {
+        let path = temp_repo("fsck-unparsable");
+        fs::write(format!("{path}/.tuffous/todos/junk.json"), "not a todo").unwrap();
+        let Some(Problem::Unparsable { file, .. }) = check(&path).pop() else {
+            panic!("no problem found");
+        };
+        assert_eq!(file, Path::new(".tuffous/todos/junk.json"));
+
+        assert_eq!(
+            Problem::Unparsable {
+                file,
+                error: String::new()
+            }
+            .fix(&path),
+            Ok(())
+        );
+        assert_eq!(check(&path), []);
+        let lost = fs::read_to_string(format!("{path}/{LOST_DIR}/junk.json")).unwrap();
+        assert_eq!(lost, "not a todo");
+    }
+
+    #[test]
+    fn wrong_file_name() {
+        let path = temp_repo("fsck-name");
+        let todo = Todo::create(String::from("Misnamed"));
+        write_todo(&path, ".tuffous/todos/copy.json", &todo);
+        fix(
+            &path,
+            Problem::WrongFileName {
+                file: PathBuf::from(".tuffous/todos/copy.json"),
+                id: todo.id(),
+            },
+        );
+        assert!(Path::new(&path).join(todo_file(&todo)).exists());
+
+        // The file named after the id in the repo isn't overwritten
+        let other = Todo::create(String::from("Other"));
+        write_todo(&path, ".tuffous/todos/other.json", &other);
+        let problem = Problem::WrongFileName {
+            file: PathBuf::from(".tuffous/todos/other.json"),
+            id: todo.id(),
+        };
+        assert!(problem.fix(&path).is_err());
+        let kept = Todo::read_from_file(Path::new(&path).join(todo_file(&todo))).unwrap();
+        assert_eq!(kept.metadata.name, "Misnamed");
+    }
+
+    #[test]
+    fn duplicate_id() {
+        let path = temp_repo("fsck-duplicate");
+        let todo = Todo::create(String::from("Twice"));
+        write_todo(&path, ".tuffous/todos/copy.json", &todo);
+        todo.write_to_file(&path).unwrap();
+        fix(
+            &path,
+            Problem::DuplicateId {
+                id: todo.id(),
+                files: vec![todo_file(&todo), PathBuf::from(".tuffous/todos/copy.json")],
+            },
+        );
+        assert!(Path::new(&path).join(todo_file(&todo)).exists());
+        assert!(Path::new(&format!("{path}/{LOST_DIR}/copy.json")).exists());
+    }
+
+    #[test]
+    fn dangling_father() {
+        let path = temp_repo("fsck-dangling");
+        let mut todo = Todo::create(String::from("Orphan"));
+        todo.dependents.push(42);
+        todo.write_to_file(&path).unwrap();
+        fix(
+            &path,
+            Problem::DanglingFather {
+                file: todo_file(&todo),
+                id: todo.id(),
+                father: 42,
+            },
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        let path = temp_repo("fsck-cycle");
+        let mut a = Todo::create(String::from("Chicken"));
+        let mut b = Todo::create(String::from("Egg"));
+        a.dependents.push(b.id());
+        b.dependents.push(a.id());
+        a.write_to_file(&path).unwrap();
+        b.write_to_file(&path).unwrap();
+        let (first, second) = if a.id() < b.id() { (&a, &b) } else { (&b, &a) };
+        fix(
+            &path,
+            Problem::Cycle {
+                files: vec![todo_file(first), todo_file(second)],
+                ids: vec![first.id(), second.id()],
+            },
+        );
+    }
+
+    #[test]
+    fn empty_name() {
+        let path = temp_repo("fsck-empty");
+        let todo = Todo::create(String::from(" "));
+        todo.write_to_file(&path).unwrap();
+        fix(
+            &path,
+            Problem::EmptyName {
+                file: todo_file(&todo),
+                id: todo.id(),
+            },
+        );
+    }
+
+    #[test]
+    fn invalid_line() {
+        let path = temp_repo("fsck-line");
+        let mut config = RepoConfig::get(&path);
+        config.storage = Storage::Jsonl;
+        config.write(&path).unwrap();
+        let todo = Todo::create(String::from("Logged"));
+        storage::append_todo(&path, &todo).unwrap();
+        fs::write(
+            format!("{path}/{LOG_FILE}"),
+            fs::read_to_string(format!("{path}/{LOG_FILE}")).unwrap() + "{\"trunc\n",
+        )
+        .unwrap();
+        let Some(Problem::InvalidLine { line, error }) = check(&path).pop() else {
+            panic!("no problem found");
+        };
+        assert_eq!(line, 2);
+        fix(&path, Problem::InvalidLine { line, error });
+        assert_eq!(Replay::of(&path).unwrap().left()[0].id(), todo.id());
+    }
+
+    #[test]
+    fn encrypted() {
+        let path = temp_repo("fsck-encrypted");
+        crypt::enable(&path, "passphrase").unwrap();
+        let todo = Todo::create(String::from("Plain"));
+        fs::write(
+            Path::new(&path).join(todo_file(&todo)),
+            serde_json::to_string(&todo).unwrap(),
+        )
+        .unwrap();
+        fix(
+            &path,
+            Problem::Unsealed {
+                file: todo_file(&todo),
+            },
+        );
+
+        // Sealed with a key of another repo this one doesn't have
+        let other = temp_repo("fsck-encrypted-other");
+        crypt::enable(&other, "passphrase").unwrap();
+        crypt::rotate(&other, "passphrase").unwrap();
+        let foreign = Todo::create(String::from("Foreign"));
+        write_todo(&other, &todo_file(&foreign).to_string_lossy(), &foreign);
+        fs::copy(
+            Path::new(&other).join(todo_file(&foreign)),
+            Path::new(&path).join(todo_file(&foreign)),
+        )
+        .unwrap();
+        let problem = Problem::MissingKey {
+            file: todo_file(&foreign),
+            line: None,
+        };
+        assert_eq!(check(&path), std::slice::from_ref(&problem));
+        assert!(problem.fix(&path).is_err());
+        assert!(Path::new(&path).join(todo_file(&foreign)).exists());
+        fs::remove_file(Path::new(&path).join(todo_file(&foreign))).unwrap();
+
+        // Renamed files are sealed again for their new name
+        let misnamed = Todo::create(String::from("Misnamed"));
+        write_todo(&path, ".tuffous/todos/copy.json", &misnamed);
+        fix(
+            &path,
+            Problem::WrongFileName {
+                file: PathBuf::from(".tuffous/todos/copy.json"),
+                id: misnamed.id(),
+            },
+        );
+        assert!(Todo::read_from_file(Path::new(&path).join(todo_file(&misnamed))).is_some());
+
+        crypt::lock(&path);
+        assert_eq!(check(&path), [Problem::Locked]);
+    }
+}
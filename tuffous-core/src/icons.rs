@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use std::{env, fmt::Display, str::FromStr};
+
+/// The glyphs frontends draw todos with.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IconSet {
+    /// Private-use glyphs of nerd-font patched fonts.
+    #[default]
+    Nerd,
+    /// Unicode emoji and symbols any modern terminal can show.
+    Emoji,
+    /// Plain ASCII, for dumb terminals and logs.
+    Ascii,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Icon {
+    Today,
+    Completed,
+    Cancelled,
+    Overdue,
+    DueToday,
+    Date,
+    Deadline,
+    /// Connector of a child that has siblings after it.
+    Branch,
+    /// Connector of the last child.
+    LastBranch,
+    /// Continues the connector of a father past its children.
+    Pipe,
+}
+
+impl IconSet {
+    /// Guess the icon set from `TERM` and the locale.
+    ///
+    /// Nerd fonts can't be detected, so they're assumed unless the terminal is
+    /// dumb or the locale isn't UTF-8, in which case ASCII is used.
+    pub fn detect() -> Self {
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .filter_map(|key| env::var(key).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        Self::detect_from(&env::var("TERM").unwrap_or_default(), &locale)
+    }
+
+    /// The icon set for the terminal and the locale that applies.
+    fn detect_from(term: &str, locale: &str) -> Self {
+        if term.is_empty() || term == "dumb" || term == "linux" || term.starts_with("vt") {
+            return IconSet::Ascii;
+        }
+
+        let locale = locale.to_lowercase();
+        if locale.contains("utf-8") || locale.contains("utf8") {
+            IconSet::Nerd
+        } else {
+            IconSet::Ascii
+        }
+    }
+
+    pub fn icon(&self, icon: Icon) -> &'static str {
+        match self {
+            IconSet::Nerd => match icon {
+                Icon::Today => "",
+                Icon::Completed => "󰄲",
+                Icon::Cancelled => "󰜺",
+                Icon::Overdue => "󱂴",
+                Icon::DueToday => "󰈽",
+                Icon::Date => "󰃭",
+                Icon::Deadline => "󰈻",
+                Icon::Branch => "├─",
+                Icon::LastBranch => "└─",
+                Icon::Pipe => "│",
+            },
+            IconSet::Emoji => match icon {
+                Icon::Today => "⭐",
+                Icon::Completed => "✅",
+                Icon::Cancelled => "🚫",
+                Icon::Overdue => "⏰",
+                Icon::DueToday => "⏳",
+                Icon::Date => "📅",
+                Icon::Deadline => "🏁",
+                Icon::Branch => "├─",
+                Icon::LastBranch => "└─",
+                Icon::Pipe => "│",
+            },
+            IconSet::Ascii => match icon {
+                Icon::Today => "*",
+                Icon::Completed => "[x]",
+                Icon::Cancelled => "[-]",
+                Icon::Overdue => "!!",
+                Icon::DueToday => "!",
+                Icon::Date => "@",
+                Icon::Deadline => "due",
+                Icon::Branch => "|-",
+                Icon::LastBranch => "`-",
+                Icon::Pipe => "|",
+            },
+        }
+    }
+
+    /// The glyph of a progress in percent.
+    pub fn progress(&self, percent: u32) -> &'static str {
+        match self {
+            IconSet::Nerd => match percent {
+                0 => "󰝦",
+                1..=13 => "󰪞",
+                14..=25 => "󰪟",
+                26..=38 => "󰪠",
+                39..=50 => "󰪡",
+                51..=63 => "󰪢",
+                64..=75 => "󰪣",
+                76..=88 => "󰪤",
+                _ => "󰪥",
+            },
+            IconSet::Emoji => match percent {
+                0 => "○",
+                1..=25 => "◔",
+                26..=50 => "◑",
+                51..=75 => "◕",
+                _ => "●",
+            },
+            IconSet::Ascii => match percent {
+                0 => "[    ]",
+                1..=25 => "[=   ]",
+                26..=50 => "[==  ]",
+                51..=75 => "[=== ]",
+                _ => "[====]",
+            },
+        }
+    }
+}
+
+impl FromStr for IconSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nerd" => Ok(IconSet::Nerd),
+            "emoji" | "unicode" => Ok(IconSet::Emoji),
+            "ascii" => Ok(IconSet::Ascii),
+            _ => Err(format!("Unknown icon set: {s}")),
+        }
+    }
+}
+
+impl Display for IconSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                IconSet::Nerd => "nerd",
+                IconSet::Emoji => "emoji",
+                IconSet::Ascii => "ascii",
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_dumb_terminals() {
+        for term in ["", "dumb", "linux", "vt100"] {
+            assert_eq!(IconSet::detect_from(term, "en_US.UTF-8"), IconSet::Ascii);
+        }
+    }
+
+    #[test]
+    fn detect_locale() {
+        assert_eq!(
+            IconSet::detect_from("xterm-256color", "en_US.UTF-8"),
+            IconSet::Nerd
+        );
+        assert_eq!(
+            IconSet::detect_from("xterm-256color", "C.utf8"),
+            IconSet::Nerd
+        );
+        assert_eq!(IconSet::detect_from("xterm-256color", "C"), IconSet::Ascii);
+        assert_eq!(IconSet::detect_from("xterm-256color", ""), IconSet::Ascii);
+    }
+
+    #[test]
+    fn ascii_is_ascii() {
+        let icons = [
+            Icon::Today,
+            Icon::Completed,
+            Icon::Cancelled,
+            Icon::Overdue,
+            Icon::DueToday,
+            Icon::Date,
+            Icon::Deadline,
+            Icon::Branch,
+            Icon::LastBranch,
+            Icon::Pipe,
+        ];
+        for icon in icons {
+            assert!(IconSet::Ascii.icon(icon).is_ascii());
+        }
+        for percent in [0, 10, 50, 100] {
+            assert!(IconSet::Ascii.progress(percent).is_ascii());
+        }
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for set in [IconSet::Nerd, IconSet::Emoji, IconSet::Ascii] {
+            assert_eq!(set.to_string().parse::<IconSet>(), Ok(set));
+        }
+        assert_eq!("unicode".parse::<IconSet>(), Ok(IconSet::Emoji));
+        assert!("fancy".parse::<IconSet>().is_err());
+    }
+}
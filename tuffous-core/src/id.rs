@@ -0,0 +1,122 @@
+use crate::TodoInstance;
+use std::collections::HashMap;
+
+/// Short ids are never shorter than this, even when a shorter prefix is unique.
+pub const MIN_SHORT_ID_LEN: usize = 3;
+
+/// The id of a todo written as 16 hex digits, which id prefixes refer to.
+pub fn hex_id(id: u64) -> String {
+    format!("{id:016x}")
+}
+
+impl TodoInstance {
+    /// Find the todos whose hex id starts with the prefix.
+    pub fn find_by_prefix(&self, prefix: &str) -> Vec<u64> {
+        let prefix = prefix.to_lowercase();
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+
+        self.todos
+            .iter()
+            .filter(|todo| hex_id(todo.id()).starts_with(&prefix))
+            .map(|todo| todo.id())
+            .collect()
+    }
+
+    /// The shortest unique prefix of the hex id of every todo.
+    pub fn short_ids(&self) -> HashMap<u64, String> {
+        let mut hexes: Vec<(String, u64)> = self
+            .todos
+            .iter()
+            .map(|todo| (hex_id(todo.id()), todo.id()))
+            .collect();
+        hexes.sort();
+
+        let mut map = HashMap::new();
+        for (index, (hex, id)) in hexes.iter().enumerate() {
+            let mut len = MIN_SHORT_ID_LEN;
+            if index > 0 {
+                len = len.max(common_prefix_len(hex, &hexes[index - 1].0) + 1);
+            }
+            if let Some((next, _)) = hexes.get(index + 1) {
+                len = len.max(common_prefix_len(hex, next) + 1);
+            }
+            map.insert(*id, hex[..len.min(hex.len())].to_string());
+        }
+        map
+    }
+
+    /// The shortest unique prefix of the hex id of the todo.
+    pub fn short_id(&self, id: u64) -> String {
+        let hex = hex_id(id);
+        let mut len = MIN_SHORT_ID_LEN;
+        for todo in &self.todos {
+            if todo.id() != id {
+                len = len.max(common_prefix_len(&hex, &hex_id(todo.id())) + 1);
+            }
+        }
+        hex[..len.min(hex.len())].to_string()
+    }
+}
+
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.chars().zip(b.chars()).take_while(|(x, y)| x == y).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Todo;
+
+    fn instance(ids: &[u64]) -> TodoInstance {
+        let mut instance = TodoInstance::create("/nonexistent");
+        for id in ids {
+            let mut todo = Todo::create(hex_id(*id));
+            todo.id = *id;
+            instance.insert(todo);
+        }
+        instance
+    }
+
+    #[test]
+    fn shortest_unique_prefix() {
+        let ids = [
+            0x1234_0000_0000_0000,
+            0x1235_0000_0000_0000,
+            0xabcd_0000_0000_0000,
+            0x1234_5000_0000_0000,
+        ];
+        let instance = instance(&ids);
+        let short_ids = instance.short_ids();
+        assert_eq!(short_ids[&ids[0]], "12340");
+        assert_eq!(short_ids[&ids[1]], "1235");
+        assert_eq!(short_ids[&ids[2]], "abc");
+        assert_eq!(short_ids[&ids[3]], "12345");
+        for id in ids {
+            assert_eq!(instance.short_id(id), short_ids[&id]);
+            assert_eq!(instance.find_by_prefix(&short_ids[&id]), [id]);
+        }
+    }
+
+    #[test]
+    fn prefixes() {
+        let ids = [0xab00_0000_0000_0000, 0xab10_0000_0000_0000];
+        let instance = instance(&ids);
+        assert_eq!(instance.find_by_prefix("AB"), ids);
+        assert_eq!(instance.find_by_prefix("ab1"), [ids[1]]);
+        assert!(instance.find_by_prefix("").is_empty());
+        assert!(instance.find_by_prefix("ff").is_empty());
+        assert_eq!(hex_id(1), "0000000000000001");
+        // Even a lone todo keeps the minimum length
+        assert_eq!(self::instance(&[1]).short_id(1), "000");
+    }
+
+    #[test]
+    fn identical_prefixes_use_the_whole_id() {
+        let ids = [0x0123_4567_89ab_cdef, 0x0123_4567_89ab_cdee];
+        let short_ids = instance(&ids).short_ids();
+        assert_eq!(short_ids[&ids[0]], "0123456789abcdef");
+        assert_eq!(short_ids[&ids[1]], "0123456789abcdee");
+    }
+}
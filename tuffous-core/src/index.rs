@@ -0,0 +1,166 @@
+//! The index of a repo, `.tuffous/index.json`, keeps the todos without their
+//! details by the modification time of their files, so reading a repo only
+//! parses the files changed since it was last read.
+
+use crate::{crypt, Details, Todo, TodoInstance};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, Metadata},
+    path::Path,
+    time::SystemTime,
+};
+
+/// When a file was last modified and how long it was then.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    modified: SystemTime,
+    pub(crate) len: u64,
+}
+
+impl Stamp {
+    pub fn of(metadata: &Metadata) -> Option<Stamp> {
+        Some(Stamp {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Option<Stamp> {
+        Self::of(&fs::metadata(path).ok()?)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Entry {
+    pub stamp: Stamp,
+    pub todo: Todo,
+}
+
+/// The indexed todos by the name of their file.
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct Index {
+    pub entries: HashMap<String, Entry>,
+}
+
+impl Index {
+    pub fn read(path: &str) -> Index {
+        crypt::read(Self::path(path))
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, path: &str) {
+        let _ = crypt::write(Self::path(path), serde_json::to_string(self).unwrap());
+    }
+
+    /// The todo of the file, from the index if the file is unchanged since
+    /// it was indexed, else parsed and indexed. Returns whether the index
+    /// changed too.
+    pub fn load(&mut self, file: &Path, stamp: Stamp) -> Option<(Todo, bool)> {
+        let name = file.file_name()?.to_string_lossy().into_owned();
+        if let Some(entry) = self.entries.get(&name).filter(|e| e.stamp == stamp) {
+            let mut todo = entry.todo.clone();
+            todo.metadata.details = Details::lazy(file.to_path_buf());
+            return Some((todo, false));
+        }
+
+        let todo = Todo::read_from_file(file)?;
+        self.entries.insert(
+            name,
+            Entry {
+                stamp,
+                todo: todo.summary(),
+            },
+        );
+        Some((todo, true))
+    }
+
+    /// Drop the entries of the files not in the names. Returns whether any was.
+    pub fn retain(&mut self, names: &HashSet<String>) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|name, _| names.contains(name));
+        len != self.entries.len()
+    }
+
+    pub fn path(path: &str) -> String {
+        format!("{path}/.tuffous/index.json")
+    }
+}
+
+impl TodoInstance {
+    /// The todos of the files changed since they were last read or written,
+    /// and the ids of the ones whose files were removed.
+    pub(crate) fn read_files(&self) -> (Vec<(Todo, Stamp)>, HashSet<u64>) {
+        let mut index: Option<Index> = None;
+        let mut index_changed = false;
+        let mut names = HashSet::new();
+        let mut found = HashSet::new();
+        let mut read = Vec::new();
+
+        for file in fs::read_dir(format!("{}/.tuffous/todos", self.path))
+            .unwrap()
+            .flatten()
+        {
+            let Some(stamp) = file.metadata().ok().and_then(|m| Stamp::of(&m)) else {
+                continue;
+            };
+            let name = file.file_name().to_string_lossy().into_owned();
+            let id = name
+                .strip_suffix(".json")
+                .and_then(|id| id.parse::<u64>().ok());
+            names.insert(name);
+            if let Some(id) = id.filter(|id| self.stamps.get(id) == Some(&stamp)) {
+                found.insert(id);
+                continue;
+            }
+
+            let index = index.get_or_insert_with(|| Index::read(&self.path));
+            if let Some((todo, changed)) = index.load(&file.path(), stamp) {
+                index_changed |= changed;
+                found.insert(todo.id());
+                read.push((todo, stamp));
+            }
+        }
+        if let Some(mut index) = index {
+            if index.retain(&names) || index_changed {
+                index.write(&self.path);
+            }
+        }
+
+        let removed = self
+            .stamps
+            .keys()
+            .filter(|id| !found.contains(id))
+            .copied()
+            .collect();
+        (read, removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{temp_repo, Todo, TodoInstance};
+
+    #[test]
+    fn lazy_details_are_written() {
+        let path = temp_repo("lazy-details");
+        let mut todo = Todo::create(String::from("Alpha"));
+        todo.metadata.set_details(String::from("secret details"));
+        let id = todo.id();
+        let mut instance = TodoInstance::create(&path);
+        instance.insert(todo);
+        instance.write_all();
+
+        // Indexed by the first read, loaded from the index by the second
+        TodoInstance::create(&path).read_all();
+        let mut instance = TodoInstance::create(&path);
+        instance.read_all();
+        let json = serde_json::to_string(instance.get(id).unwrap()).unwrap();
+        assert!(json.contains("secret details"));
+        assert_eq!(
+            instance.get(id).unwrap().metadata.details(),
+            "secret details"
+        );
+    }
+}
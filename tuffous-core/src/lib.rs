@@ -1,13 +1,31 @@
 use chrono::{Local, NaiveDate, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::hash_map::DefaultHasher,
+    cell::{OnceCell, RefCell, RefMut},
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
     fs::{self, File},
     hash::{Hash, Hasher},
     io::{Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
+pub mod agenda;
+pub mod archive;
+pub mod config;
+pub mod crypt;
+pub mod fsck;
+pub mod icons;
+pub mod id;
+pub mod index;
+pub mod order;
+pub mod palette;
+pub mod path;
+pub mod progress;
+pub mod query;
+pub mod search;
+pub mod stats;
+pub mod storage;
+pub mod trash;
 pub mod util;
 
 pub fn version() -> String {
@@ -33,18 +51,107 @@ pub struct Todo {
     id: u64,
     pub completed: bool,
     creation_date: NaiveDateTime,
+    #[serde(default)]
+    completion_date: Option<NaiveDateTime>,
     pub deadline: Option<NaiveDateTime>,
     pub time: Option<NaiveDate>,
     pub dependents: Vec<u64>,
     pub tags: Vec<String>,
     pub weight: u32,
     pub metadata: TodoMetaData,
+    /// Manual sort keys of the todo, by the key of each [`order::OrderScope`].
+    #[serde(default)]
+    pub order: BTreeMap<String, i64>,
+    /// Dropped without being done.
+    #[serde(default)]
+    pub cancelled: bool,
+    /// The minutes the todo is expected to take.
+    #[serde(default)]
+    pub estimate: Option<u32>,
+    /// How the progress of the todo and its subtree is measured, instead of
+    /// the strategy of its ancestors or of the repo.
+    #[serde(default)]
+    pub progress: Option<progress::Strategy>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct TodoMetaData {
-    pub details: String,
+    /// Left out of the index, see [`Details`].
+    #[serde(default)]
+    details: Details,
     pub name: String,
+    #[serde(default)]
+    pub checklist: Vec<CheckItem>,
+}
+
+/// The details of a todo. Todos loaded from the index read them from their
+/// file when they are first needed, or serialized.
+#[derive(Default, Clone)]
+pub struct Details {
+    text: OnceCell<String>,
+    file: Option<PathBuf>,
+}
+
+impl Details {
+    fn lazy(file: PathBuf) -> Details {
+        Details {
+            text: OnceCell::new(),
+            file: Some(file),
+        }
+    }
+
+    fn get(&self) -> &str {
+        self.text.get_or_init(|| {
+            self.file
+                .as_ref()
+                .and_then(Todo::read_from_file)
+                .and_then(|todo| todo.metadata.details.text.into_inner())
+                .unwrap_or_default()
+        })
+    }
+}
+
+impl Serialize for Details {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.get())
+    }
+}
+
+impl<'de> Deserialize<'de> for Details {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Details {
+            text: OnceCell::from(String::deserialize(deserializer)?),
+            file: None,
+        })
+    }
+}
+
+impl TodoMetaData {
+    pub fn details(&self) -> &str {
+        self.details.get()
+    }
+
+    pub fn set_details(&mut self, details: String) {
+        self.details = Details {
+            text: OnceCell::from(details),
+            file: None,
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct CheckItem {
+    pub name: String,
+    pub checked: bool,
+}
+
+impl CheckItem {
+    pub fn new(name: String) -> CheckItem {
+        CheckItem {
+            name,
+            checked: false,
+        }
+    }
 }
 
 impl Todo {
@@ -62,6 +169,7 @@ impl Todo {
             id: Self::create_id(&name, &time),
             completed: false,
             creation_date: time,
+            completion_date: None,
             deadline: None,
             time: None,
             dependents: Vec::new(),
@@ -69,8 +177,13 @@ impl Todo {
             weight: 1,
             metadata: TodoMetaData {
                 name,
-                details: String::new(),
+                details: Details::default(),
+                checklist: Vec::new(),
             },
+            order: BTreeMap::new(),
+            cancelled: false,
+            estimate: None,
+            progress: None,
         }
     }
 
@@ -78,18 +191,37 @@ impl Todo {
         &self.creation_date
     }
 
-    pub fn write_to_file(&self, path: &str) {
-        let p = format!("{path}/.tuffous/todos/{}.json", self.id());
+    pub fn completion_date(&self) -> Option<&NaiveDateTime> {
+        self.completion_date.as_ref()
+    }
+
+    /// Complete or uncomplete the todo, keeping track of when it was completed.
+    pub fn set_completed(&mut self, completed: bool) {
+        if completed && !self.completed {
+            self.completion_date = Some(Utc::now().naive_utc());
+        } else if !completed {
+            self.completion_date = None;
+        }
+        self.completed = completed;
+    }
+
+    pub fn write_to_file(&self, path: &str) -> Result<(), String> {
+        self.write_to_dir(&format!("{path}/.tuffous/todos"))
+    }
+
+    fn write_to_dir(&self, dir: &str) -> Result<(), String> {
+        let p = format!("{dir}/{}.json", self.id());
+        // Sealed before the file the details may come from is truncated
+        let text = crypt::seal(&p, serde_json::to_string(self).unwrap())?;
 
-        let Ok(mut file) = File::create(p) else { return };
-        let _ = file
-            .write(serde_json::to_string(self).unwrap().as_bytes())
-            .unwrap();
+        let mut file = File::create(p).map_err(|err| err.to_string())?;
+        file.write_all(text.as_bytes())
+            .map_err(|err| err.to_string())
     }
 
     pub fn read_from_file<P: AsRef<Path>>(path: P) -> Option<Self> {
         let mut file;
-        if let Ok(f) = File::open(path) {
+        if let Ok(f) = File::open(&path) {
             file = f
         } else {
             return None;
@@ -100,11 +232,14 @@ impl Todo {
             return None;
         };
 
-        if let Ok(x) = serde_json::from_str::<Todo>(&str) {
-            Some(x)
-        } else {
-            None
-        }
+        serde_json::from_str::<Todo>(&crypt::open(path, &str).ok()?).ok()
+    }
+
+    /// The todo without its details, as kept in the index.
+    fn summary(&self) -> Todo {
+        let mut todo = self.clone();
+        todo.metadata.details = Details::default();
+        todo
     }
 }
 
@@ -120,12 +255,17 @@ impl Clone for Todo {
             id: self.id,
             completed: self.completed,
             creation_date: self.creation_date,
+            completion_date: self.completion_date,
             deadline: self.deadline,
             time: self.time,
             dependents: self.dependents.clone(),
             tags: self.tags.clone(),
             weight: self.weight,
             metadata: self.metadata.clone(),
+            order: self.order.clone(),
+            cancelled: self.cancelled,
+            estimate: self.estimate,
+            progress: self.progress,
         }
     }
 }
@@ -135,6 +275,7 @@ impl Clone for TodoMetaData {
         Self {
             details: self.details.clone(),
             name: self.name.clone(),
+            checklist: self.checklist.clone(),
         }
     }
 }
@@ -148,42 +289,236 @@ fn calculate_hash<T: Hash>(t: &T) -> u64 {
 pub struct TodoInstance {
     pub todos: Vec<Todo>,
     path: String,
+    /// The ids of the todos loaded from the archive.
+    archived: HashSet<u64>,
+    /// The progress strategy of the repo.
+    default_strategy: progress::Strategy,
+    storage: storage::Storage,
+    log: storage::Log,
+    /// The files of the loaded todos, as they were last read or written.
+    stamps: HashMap<u64, index::Stamp>,
+    /// The todos changed since they were last read or written.
+    dirty: HashSet<u64>,
+    /// The todos read or changed since [`TodoInstance::refresh`] checked them.
+    unchecked: HashSet<u64>,
+    /// Whether todos were dropped since the last refresh, maybe leaving links to them.
+    dropped: bool,
+    /// The day of the last refresh, after which every date is checked again.
+    refreshed_on: Option<NaiveDate>,
+    cache: RefCell<Cache>,
+}
+
+/// Lookups derived from the todos. Each remembers how many todos there were
+/// when it was built, so the todos pushed since are noticed.
+#[derive(Default)]
+struct Cache {
+    positions: HashMap<u64, usize>,
+    positions_len: Option<usize>,
+    children: HashMap<u64, Vec<u64>>,
+    children_len: Option<usize>,
+    /// Progress by todo and strategy.
+    progress: HashMap<(u64, progress::Strategy), progress::Progress>,
+    /// The ids of every archived todo, loaded or not.
+    archived_ids: Option<HashSet<u64>>,
+}
+
+impl Cache {
+    /// Forget what depends on the fields of the todos.
+    fn clear(&mut self) {
+        self.children_len = None;
+        self.progress.clear();
+    }
+
+    /// Forget everything, after todos are moved or removed.
+    fn reset(&mut self) {
+        self.positions_len = None;
+        self.archived_ids = None;
+        self.clear();
+    }
 }
 
 impl TodoInstance {
     pub fn create(path: &str) -> TodoInstance {
+        let config = config::RepoConfig::get(path);
         TodoInstance {
             todos: Vec::new(),
             path: path.to_string(),
+            archived: HashSet::new(),
+            default_strategy: config.progress,
+            storage: config.storage,
+            log: storage::Log::default(),
+            stamps: HashMap::new(),
+            dirty: HashSet::new(),
+            unchecked: HashSet::new(),
+            dropped: false,
+            refreshed_on: None,
+            cache: RefCell::new(Cache::default()),
         }
     }
 
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Read the todos of the repo, or only the ones changed on disk since
+    /// they were last read or written. Todos changed and not written yet are kept.
     pub fn read_all(&mut self) {
-        for f in fs::read_dir(format!("{}/.tuffous/todos", self.path)).unwrap() {
-            if let Ok(fo) = f {
-                if let Some(x) = Todo::read_from_file(fo.path()) {
-                    self.todos.push(x)
-                } else {
-                    continue;
-                }
-            } else {
+        let (read, mut removed) = match self.storage {
+            storage::Storage::Files => self.read_files(),
+            storage::Storage::Jsonl => self.read_log(),
+        };
+
+        removed.retain(|id| !self.archived.contains(id) && !self.dirty.contains(id));
+        let len = self.todos.len();
+        self.todos.retain(|todo| !removed.contains(&todo.id));
+        self.dropped |= len != self.todos.len();
+        self.stamps.retain(|id, _| !removed.contains(id));
+
+        let positions: HashMap<u64, usize> = self
+            .todos
+            .iter()
+            .enumerate()
+            .map(|(i, todo)| (todo.id, i))
+            .collect();
+        for (todo, stamp) in read {
+            if self.dirty.contains(&todo.id) {
                 continue;
             }
+            self.stamps.insert(todo.id, stamp);
+            self.unchecked.insert(todo.id);
+            match positions.get(&todo.id) {
+                Some(i) => self.todos[*i] = todo,
+                None => self.todos.push(todo),
+            }
         }
+
+        // Keep the order independent of the file system
+        self.todos.sort_by_key(|todo| (todo.creation_date, todo.id));
+        self.cache.get_mut().reset();
     }
 
-    pub fn write_all(&self) {
+    /// Write the todos changed since they were read, and the new ones.
+    ///
+    /// Todos that can't be written, like in a locked repo, are kept to be
+    /// written the next time.
+    pub fn write_all(&mut self) {
+        let mut stamps = Vec::new();
+        let mut lines = Vec::new();
+        let mut reopened = Vec::new();
+        let mut failed = HashSet::new();
         for todo in &self.todos {
-            todo.write_to_file(&self.path);
+            if !self.dirty.contains(&todo.id) && self.stamps.contains_key(&todo.id) {
+                continue;
+            }
+            if self.is_archived(todo) {
+                if todo.write_to_dir(&self.archive_dir()).is_err() {
+                    failed.insert(todo.id);
+                    continue;
+                }
+                let file = format!("{}/{}.json", self.archive_dir(), todo.id);
+                stamps.extend(index::Stamp::read(file).map(|stamp| (todo.id, stamp)));
+                continue;
+            }
+            if self.archived.contains(&todo.id()) {
+                reopened.push(todo.id);
+            }
+            match self.storage {
+                storage::Storage::Files => {
+                    if todo.write_to_file(&self.path).is_err() {
+                        failed.insert(todo.id);
+                        continue;
+                    }
+                    let file = format!("{}/.tuffous/todos/{}.json", self.path, todo.id);
+                    stamps.extend(index::Stamp::read(file).map(|stamp| (todo.id, stamp)));
+                }
+                storage::Storage::Jsonl => {
+                    match crypt::seal(
+                        storage::log_path(&self.path),
+                        serde_json::to_string(todo).unwrap(),
+                    ) {
+                        Ok(line) => lines.push((todo.id, line)),
+                        Err(_) => {
+                            failed.insert(todo.id);
+                        }
+                    }
+                }
+            }
         }
+        if !lines.is_empty() {
+            let (ids, lines): (Vec<u64>, Vec<String>) = lines.into_iter().unzip();
+            match self.append(&lines) {
+                Some(stamp) => stamps.extend(ids.into_iter().map(|id| (id, stamp))),
+                None => failed.extend(ids),
+            }
+        }
+        // Reopened todos leave the archive once they are written elsewhere
+        for id in reopened.iter().filter(|id| !failed.contains(id)) {
+            let _ = fs::remove_file(format!("{}/{id}.json", self.archive_dir()));
+        }
+        self.stamps.extend(stamps);
+        self.dirty = failed;
+        self.compact_if_needed();
+    }
+
+    /// Add the todo, to be written with the others.
+    pub fn insert(&mut self, todo: Todo) {
+        self.dirty.insert(todo.id);
+        self.unchecked.insert(todo.id);
+        self.todos.push(todo);
+        self.cache.get_mut().reset();
     }
 
     pub fn get(&self, id: u64) -> Option<&Todo> {
-        self.todos.iter().find(|&todo| todo.id() == id)
+        self.todos.get(self.position(id)?)
     }
 
+    /// The todo is written by [`TodoInstance::write_all`] from now on.
     pub fn get_mut(&mut self, id: u64) -> Option<&mut Todo> {
-        self.todos.iter_mut().find(|todo| todo.id() == id)
+        let i = self.position(id)?;
+        self.cache.get_mut().clear();
+        self.dirty.insert(id);
+        self.unchecked.insert(id);
+        self.todos.get_mut(i)
+    }
+
+    fn position(&self, id: u64) -> Option<usize> {
+        let i = *self.cache().positions.get(&id)?;
+        if self.todos.get(i).is_some_and(|todo| todo.id == id) {
+            return Some(i);
+        }
+        // Moved since the positions were built
+        self.cache.borrow_mut().positions_len = None;
+        self.cache().positions.get(&id).copied()
+    }
+
+    /// The lookups, rebuilding the stale ones.
+    fn cache(&self) -> RefMut<'_, Cache> {
+        let mut cache = self.cache.borrow_mut();
+        let len = self.todos.len();
+        if cache.positions_len != Some(len) {
+            cache.positions = self
+                .todos
+                .iter()
+                .enumerate()
+                .map(|(i, todo)| (todo.id, i))
+                .collect();
+            cache.positions_len = Some(len);
+        }
+        if cache.children_len != Some(len) {
+            let mut children: HashMap<u64, Vec<u64>> = HashMap::new();
+            for todo in &self.todos {
+                for father in &todo.dependents {
+                    let list = children.entry(*father).or_default();
+                    if list.last() != Some(&todo.id) {
+                        list.push(todo.id);
+                    }
+                }
+            }
+            cache.children = children;
+            cache.children_len = Some(len);
+            cache.progress.clear();
+        }
+        cache
     }
 
     pub fn todos(&self) -> Vec<u64> {
@@ -195,23 +530,20 @@ impl TodoInstance {
     }
 
     pub fn children(&self, id: u64) -> Vec<u64> {
-        let mut vec = Vec::new();
-        for todo in &self.todos {
-            if self.all_deps(todo.id).contains(&id) {
-                vec.push(todo.id());
+        let mut found = HashSet::new();
+        let mut stack = self.children_once(id);
+        while let Some(child) = stack.pop() {
+            if found.insert(child) {
+                stack.append(&mut self.children_once(child));
             }
         }
+        let mut vec: Vec<u64> = found.into_iter().collect();
+        vec.sort_by_key(|child| self.position(*child));
         vec
     }
 
     pub fn children_once(&self, id: u64) -> Vec<u64> {
-        let mut vec = Vec::new();
-        for todo in &self.todos {
-            if todo.dependents.contains(&id) {
-                vec.push(todo.id());
-            }
-        }
-        vec
+        self.cache().children.get(&id).cloned().unwrap_or_default()
     }
 
     pub fn is_child_able(&self, father: u64, child: u64) -> bool {
@@ -222,16 +554,10 @@ impl TodoInstance {
     }
 
     pub fn replace(&mut self, replacement: Todo) -> bool {
-        if !&self.todos.contains(&replacement) {
+        let Some(todo) = self.get_mut(replacement.id) else {
             return false;
-        }
-        for t in self.todos.iter().enumerate() {
-            if t.1.eq(&replacement) {
-                self.todos.remove(t.0);
-                self.todos.push(replacement);
-                break;
-            }
-        }
+        };
+        *todo = replacement;
         true
     }
 
@@ -246,6 +572,16 @@ impl TodoInstance {
         }
     }
 
+    pub fn unchild(&mut self, father: u64, child: u64) -> bool {
+        if let Some(target) = self.get_mut(child) {
+            if target.dependents.contains(&father) {
+                util::remove_from_vec(&mut target.dependents, &father);
+                return true;
+            }
+        }
+        false
+    }
+
     pub fn all_deps(&self, id: u64) -> Vec<u64> {
         let mut vec = Vec::new();
         if let Some(target) = self.get(id) {
@@ -263,70 +599,221 @@ impl TodoInstance {
         }
     }
 
+    /// Drop the links to todos that don't exist and move past dates to today.
+    ///
+    /// Only the todos read or changed since the last refresh are checked, or
+    /// every todo once some were dropped or the day changed. Only the todos
+    /// changed are written again.
     pub fn refresh(&mut self) {
-        let todos = self.todos();
-        for todo_id in &todos {
-            if let Some(todo) = self.get_mut(*todo_id) {
-                // Remove broken deps
-                loop {
-                    let mut rm = 0;
-                    let mut remove = false;
-                    for dep in todo.dependents.iter().enumerate() {
-                        if !todos.contains(dep.1) {
-                            rm = dep.0;
-                            remove = true;
-                            break;
-                        }
-                    }
+        let today = Local::now().date_naive();
+        let unchecked: Vec<u64> = if self.dropped || self.refreshed_on != Some(today) {
+            self.todos()
+        } else {
+            self.unchecked.iter().copied().collect()
+        };
+        self.unchecked.clear();
+        self.dropped = false;
+        self.refreshed_on = Some(today);
 
-                    if remove {
-                        todo.dependents.remove(rm);
-                    } else {
-                        break;
-                    }
-                }
+        let mut changed = false;
+        for id in unchecked {
+            let Some(todo) = self.get(id) else {
+                continue;
+            };
+            // Links to archived todos aren't broken
+            let broken: Vec<u64> = todo
+                .dependents
+                .iter()
+                .copied()
+                .filter(|dep| self.get(*dep).is_none() && !self.is_archived_id(*dep))
+                .collect();
+            let overdue = !todo.completed && todo.time.is_some_and(|date| date < today);
+            if broken.is_empty() && !overdue {
+                continue;
+            }
 
-                // Correct time
-                if !todo.completed {
-                    if let Some(date) = &todo.time {
-                        if date < &Local::now().date_naive() {
-                            todo.time = Some(Local::now().date_naive());
-                        }
-                    }
-                }
+            let i = self.position(id).unwrap();
+            let todo = &mut self.todos[i];
+            todo.dependents.retain(|dep| !broken.contains(dep));
+            if overdue {
+                todo.time = Some(today);
             }
+            self.dirty.insert(id);
+            changed = true;
+        }
+        if changed {
+            self.cache.get_mut().clear();
         }
     }
 
-    pub fn remove(&mut self, id: u64) {
-        let mut rm = 0;
-        let mut remove = false;
-
-        for todo in self.todos.iter().enumerate() {
-            if todo.1.id() == id {
-                rm = todo.0;
-                remove = true;
-            }
+    /// Whether the todo is archived, from the archived ids read once until
+    /// todos are moved.
+    fn is_archived_id(&self, id: u64) -> bool {
+        if let Some(ids) = &self.cache.borrow().archived_ids {
+            return ids.contains(&id);
         }
-        if remove {
-            self.todos.remove(rm);
+        let ids: HashSet<u64> = self.archived_ids().into_iter().collect();
+        let archived = ids.contains(&id);
+        self.cache.borrow_mut().archived_ids = Some(ids);
+        archived
+    }
+
+    /// Move the todo to the trash. Its children are unlinked until it's restored.
+    /// Fails if it can't be trashed or dropped from the repo, keeping it.
+    pub fn remove(&mut self, id: u64) -> Result<(), String> {
+        self.trash(id)?;
+
+        let dropped = if self.get(id).is_some_and(|todo| self.is_archived(todo)) {
+            fs::remove_file(format!("{}/{id}.json", self.archive_dir()))
+                .map_err(|err| err.to_string())
+        } else {
+            self.unstore(&[id])
+        };
+        if let Err(err) = dropped {
+            self.untrash(id);
+            return Err(err);
         }
+        self.todos.retain(|todo| todo.id != id);
+        self.dropped = true;
+        self.stamps.remove(&id);
+        self.dirty.remove(&id);
+        self.cache.get_mut().reset();
         self.refresh();
-        fs::remove_file(format!("{}/.tuffous/todos/{}.json", self.path, id)).unwrap();
+        Ok(())
     }
 
+    /// The weight of the todo and its subtree, or the part of it that is done
+    /// if `completed`. Checklist items weigh one each, and a completed todo
+    /// counts as done whatever its children and checklist say.
     pub fn weight(&self, id: u64, completed: bool) -> u32 {
-        let mut base = 0;
-        if self.children_once(id).is_empty() && (self.get(id).unwrap().completed || !completed) {
-            base += self.get(id).unwrap().weight
+        let Some(todo) = self.get(id) else {
+            return 0;
+        };
+        if completed && todo.completed {
+            return self.weight(id, false);
+        }
+
+        // Each checklist item counts as one unit of progress
+        let mut base = todo
+            .metadata
+            .checklist
+            .iter()
+            .filter(|item| item.checked || !completed)
+            .count() as u32;
+
+        if self.children_once(id).is_empty() && !completed {
+            base += todo.weight
         }
 
         for child in self.children_once(id) {
-            if self.get(child).unwrap().completed || !completed {
-                base += self.weight(child, completed);
-            }
+            base += self.weight(child, completed);
         }
 
         base
     }
+
+    pub fn has_progress(&self, id: u64) -> bool {
+        !self.children_once(id).is_empty()
+            || self
+                .get(id)
+                .is_some_and(|todo| !todo.metadata.checklist.is_empty())
+    }
+}
+
+/// A new empty repo in the temporary directory.
+#[cfg(test)]
+pub(crate) fn temp_repo(name: &str) -> String {
+    let path = std::env::temp_dir()
+        .join(format!("tuffous-{name}-{}", std::process::id()))
+        .to_string_lossy()
+        .into_owned();
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    init_repo(&path);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn todo(name: &str, weight: u32, checklist: &[bool]) -> Todo {
+        let mut todo = Todo::create(String::from(name));
+        todo.weight = weight;
+        todo.metadata.checklist = checklist
+            .iter()
+            .map(|checked| CheckItem {
+                name: String::from("step"),
+                checked: *checked,
+            })
+            .collect();
+        todo
+    }
+
+    #[test]
+    fn weight() {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let father = todo("Move", 5, &[true, false]);
+        let mut done = todo("Pack", 2, &[]);
+        done.set_completed(true);
+        let mut open = todo("Clean", 3, &[true]);
+        let id = father.id();
+        done.dependents.push(id);
+        open.dependents.push(id);
+        let (done_id, open_id) = (done.id(), open.id());
+        instance.insert(father);
+        instance.insert(done);
+        instance.insert(open);
+
+        // A father weighs its children, not itself, plus its checklist
+        assert_eq!(instance.weight(id, false), 2 + 2 + 3 + 1);
+        assert_eq!(instance.weight(id, true), 1 + 2 + 1);
+        assert_eq!(instance.weight(done_id, true), 2);
+        assert_eq!(instance.weight(open_id, true), 1);
+        assert!(instance.has_progress(id));
+        assert!(!instance.has_progress(done_id));
+
+        instance.get_mut(id).unwrap().set_completed(true);
+        assert_eq!(instance.weight(id, true), instance.weight(id, false));
+        assert_eq!(instance.weight(0, false), 0);
+    }
+
+    #[test]
+    fn refresh() {
+        let path = temp_repo("refresh");
+        let mut instance = TodoInstance::create(&path);
+        let father = todo("Move", 1, &[]);
+        let mut archived = todo("Pack", 1, &[]);
+        archived.set_completed(true);
+        let mut child = todo("Clean", 1, &[]);
+        child.dependents = vec![father.id(), archived.id()];
+        let [father, archived, child] = [father, archived, child].map(|todo| {
+            let id = todo.id();
+            instance.insert(todo);
+            id
+        });
+        instance.write_all();
+        instance.archive(&[archived]).unwrap();
+        let today = Local::now().date_naive();
+        let yesterday = today - chrono::Duration::days(1);
+
+        // Links to archived todos aren't broken
+        instance.refresh();
+        assert_eq!(instance.get(child).unwrap().dependents, [father, archived]);
+
+        instance.get_mut(child).unwrap().time = Some(yesterday);
+        instance.refresh();
+        assert_eq!(instance.get(child).unwrap().time, Some(today));
+
+        // Todos unchanged since the last refresh aren't checked again,
+        let i = instance.position(child).unwrap();
+        instance.todos[i].time = Some(yesterday);
+        instance.refresh();
+        assert_eq!(instance.get(child).unwrap().time, Some(yesterday));
+
+        // until a todo is dropped
+        instance.remove(father).unwrap();
+        assert_eq!(instance.get(child).unwrap().dependents, [archived]);
+        assert_eq!(instance.get(child).unwrap().time, Some(today));
+    }
 }
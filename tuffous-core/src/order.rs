@@ -0,0 +1,299 @@
+use crate::{Todo, TodoInstance};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, fmt::Display, str::FromStr};
+
+/// Built-in views a todo can be manually ordered in.
+pub const VIEWS: [&str; 5] = ["today", "upcoming", "anytime", "logbook", "all"];
+
+/// The list a sort key of a todo belongs to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderScope {
+    /// Among the children of a father todo.
+    Parent(u64),
+    /// Among the top-level todos of a view.
+    View(String),
+}
+
+impl OrderScope {
+    pub fn key(&self) -> String {
+        match self {
+            OrderScope::Parent(id) => format!("parent:{id}"),
+            OrderScope::View(view) => format!("view:{view}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    Deadline,
+    Date,
+    Weight,
+    Created,
+    Name,
+    Completed,
+}
+
+impl SortKey {
+    pub const ALL: [SortKey; 6] = [
+        SortKey::Deadline,
+        SortKey::Date,
+        SortKey::Weight,
+        SortKey::Created,
+        SortKey::Name,
+        SortKey::Completed,
+    ];
+
+    /// Compare two todos by this key. Todos without a value always sort last.
+    pub fn compare(&self, a: &Todo, b: &Todo, descending: bool) -> Ordering {
+        match self {
+            SortKey::Deadline => compare_options(a.deadline, b.deadline, descending),
+            SortKey::Date => compare_options(a.time, b.time, descending),
+            SortKey::Weight => compare_options(Some(a.weight), Some(b.weight), descending),
+            SortKey::Created => {
+                compare_options(Some(a.creation_date()), Some(b.creation_date()), descending)
+            }
+            SortKey::Name => compare_options(
+                Some(a.metadata.name.to_lowercase()),
+                Some(b.metadata.name.to_lowercase()),
+                descending,
+            ),
+            SortKey::Completed => {
+                compare_options(a.completion_date(), b.completion_date(), descending)
+            }
+        }
+    }
+
+    /// Whether the key reads naturally from high to low, like weights.
+    pub fn descending_by_default(&self) -> bool {
+        matches!(self, SortKey::Weight)
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "deadline" | "ddl" => Ok(SortKey::Deadline),
+            "date" => Ok(SortKey::Date),
+            "weight" => Ok(SortKey::Weight),
+            "created" | "creation" => Ok(SortKey::Created),
+            "name" => Ok(SortKey::Name),
+            "completed" | "completion" => Ok(SortKey::Completed),
+            _ => Err(format!("Unknown sort key: {s}")),
+        }
+    }
+}
+
+impl Display for SortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SortKey::Deadline => "deadline",
+            SortKey::Date => "date",
+            SortKey::Weight => "weight",
+            SortKey::Created => "created",
+            SortKey::Name => "name",
+            SortKey::Completed => "completed",
+        })
+    }
+}
+
+/// A sort key with an explicit direction, written as `key[:asc|desc]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl FromStr for Sort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, direction) = s.split_once(':').unwrap_or((s, ""));
+        let key = key.parse::<SortKey>()?;
+        let descending = match direction.to_lowercase().as_str() {
+            "" => key.descending_by_default(),
+            "asc" => false,
+            "desc" => true,
+            _ => return Err(format!("Unknown sort order: {direction}")),
+        };
+        Ok(Sort { key, descending })
+    }
+}
+
+pub(crate) fn compare_options<T: Ord>(a: Option<T>, b: Option<T>, descending: bool) -> Ordering {
+    match (a, b) {
+        (Some(x), Some(y)) => {
+            if descending {
+                y.cmp(&x)
+            } else {
+                x.cmp(&y)
+            }
+        }
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+impl TodoInstance {
+    /// Sort todos by their manual order in the scope. Todos never moved in the scope
+    /// come after the ordered ones, sorted by the secondary keys.
+    pub fn sort(&self, ids: &mut [u64], scope: &OrderScope, secondary: &[SortKey]) {
+        let key = scope.key();
+        ids.sort_by(|a, b| {
+            let (Some(ta), Some(tb)) = (self.get(*a), self.get(*b)) else {
+                return Ordering::Equal;
+            };
+
+            let mut ordering = compare_options(
+                ta.order.get(&key).copied(),
+                tb.order.get(&key).copied(),
+                false,
+            );
+
+            for sort_key in secondary {
+                ordering = ordering
+                    .then_with(|| sort_key.compare(ta, tb, sort_key.descending_by_default()));
+            }
+
+            ordering.then_with(|| SortKey::Created.compare(ta, tb, false))
+        });
+    }
+
+    /// Sort todos by the keys only, ignoring their manual order.
+    pub fn sort_by_keys(&self, ids: &mut [u64], sorts: &[Sort]) {
+        ids.sort_by(|a, b| {
+            let (Some(ta), Some(tb)) = (self.get(*a), self.get(*b)) else {
+                return Ordering::Equal;
+            };
+
+            let mut ordering = Ordering::Equal;
+            for sort in sorts {
+                ordering = ordering.then_with(|| sort.key.compare(ta, tb, sort.descending));
+            }
+            ordering
+        });
+    }
+
+    /// Move a todo right before or after the target among its siblings in the scope,
+    /// then persist the resulting order of all the siblings.
+    pub fn reorder(
+        &mut self,
+        siblings: &[u64],
+        id: u64,
+        target: u64,
+        after: bool,
+        scope: &OrderScope,
+        secondary: &[SortKey],
+    ) -> bool {
+        if id == target || !siblings.contains(&id) || !siblings.contains(&target) {
+            return false;
+        }
+
+        let mut ordered = siblings.to_vec();
+        self.sort(&mut ordered, scope, secondary);
+        ordered.retain(|sibling| *sibling != id);
+
+        let position = ordered
+            .iter()
+            .position(|sibling| *sibling == target)
+            .unwrap();
+        ordered.insert(if after { position + 1 } else { position }, id);
+
+        let key = scope.key();
+        for (index, sibling) in ordered.iter().enumerate() {
+            if let Some(todo) = self.get_mut(*sibling) {
+                todo.order.insert(key.clone(), index as i64);
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An instance with the todos named, created a minute apart in that order.
+    fn instance(names: &[&str]) -> (TodoInstance, Vec<u64>) {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let mut ids = Vec::new();
+        for (minutes, name) in names.iter().enumerate() {
+            let mut todo = Todo::create(name.to_string());
+            todo.creation_date += chrono::Duration::minutes(minutes as i64);
+            ids.push(todo.id());
+            instance.insert(todo);
+        }
+        (instance, ids)
+    }
+
+    fn sorted(instance: &TodoInstance, ids: &[u64], scope: &OrderScope) -> Vec<String> {
+        let mut ids = ids.to_vec();
+        instance.sort(&mut ids, scope, &[]);
+        ids.iter()
+            .map(|id| instance.get(*id).unwrap().metadata.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn reorder_is_stable() {
+        let (mut instance, ids) = instance(&["a", "b", "c", "d"]);
+        let scope = OrderScope::View(String::from("all"));
+        assert_eq!(sorted(&instance, &ids, &scope), ["a", "b", "c", "d"]);
+
+        assert!(instance.reorder(&ids, ids[3], ids[0], false, &scope, &[]));
+        assert_eq!(sorted(&instance, &ids, &scope), ["d", "a", "b", "c"]);
+        assert!(instance.reorder(&ids, ids[0], ids[2], true, &scope, &[]));
+        assert_eq!(sorted(&instance, &ids, &scope), ["d", "b", "c", "a"]);
+
+        // Moving a todo where it already is keeps the order
+        assert!(instance.reorder(&ids, ids[1], ids[2], false, &scope, &[]));
+        assert_eq!(sorted(&instance, &ids, &scope), ["d", "b", "c", "a"]);
+
+        // Other scopes keep their own order
+        let other = OrderScope::Parent(42);
+        assert_eq!(sorted(&instance, &ids, &other), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn reorder_refuses_strangers() {
+        let (mut instance, ids) = instance(&["a", "b", "c"]);
+        let scope = OrderScope::View(String::from("all"));
+        assert!(!instance.reorder(&ids[..2], ids[2], ids[0], false, &scope, &[]));
+        assert!(!instance.reorder(&ids, ids[1], ids[1], true, &scope, &[]));
+        assert!(ids
+            .iter()
+            .all(|id| instance.get(*id).unwrap().order.is_empty()));
+    }
+
+    #[test]
+    fn unordered_todos_come_last() {
+        let (mut instance, ids) = instance(&["c", "b", "a"]);
+        let scope = OrderScope::View(String::from("all"));
+        instance.reorder(&ids[1..], ids[2], ids[1], false, &scope, &[]);
+        let mut all = ids.clone();
+        instance.sort(&mut all, &scope, &[SortKey::Name]);
+        assert_eq!(all, [ids[2], ids[1], ids[0]]);
+
+        // Secondary keys only apply among the unordered todos
+        let mut all = ids.clone();
+        instance.get_mut(ids[0]).unwrap().weight = 9;
+        instance.get_mut(ids[1]).unwrap().order.clear();
+        instance.get_mut(ids[2]).unwrap().order.clear();
+        instance.sort(&mut all, &scope, &[SortKey::Weight, SortKey::Name]);
+        assert_eq!(all, [ids[0], ids[2], ids[1]]);
+    }
+
+    #[test]
+    fn parse_sorts() {
+        let sort = "weight".parse::<Sort>().unwrap();
+        assert_eq!(sort.key, SortKey::Weight);
+        assert!(sort.descending);
+        assert!(!"ddl:asc".parse::<Sort>().unwrap().descending);
+        assert!("name:sideways".parse::<Sort>().is_err());
+        assert!("color".parse::<SortKey>().is_err());
+    }
+}
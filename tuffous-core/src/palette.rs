@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+
+/// A color written as `#rrggbb`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches('#');
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+                .ok_or_else(|| format!("Invalid color: {s}"))
+        };
+        if hex.len() != 6 {
+            return Err(format!("Invalid color: {s}"));
+        }
+        Ok(Rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Rgb> for String {
+    fn from(value: Rgb) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for Rgb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+/// Colors shared by the frontends. Missing keys of a configured palette
+/// fall back to the dark one.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Palette {
+    /// Deadlines and overdue todos.
+    pub flag: Rgb,
+    /// Todos for today and due today.
+    pub star: Rgb,
+    pub checkbox: Rgb,
+    pub gray: Rgb,
+    pub green: Rgb,
+    pub blue_green: Rgb,
+    /// Tags pick one of these by their name.
+    pub tags: Vec<Rgb>,
+    /// Weights from low to high.
+    pub heat: Vec<Rgb>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Palette {
+    pub fn dark() -> Self {
+        Palette {
+            flag: Rgb(215, 64, 68),
+            star: Rgb(255, 215, 0),
+            checkbox: Rgb(94, 124, 226),
+            gray: Rgb(128, 128, 128),
+            green: Rgb(50, 205, 50),
+            blue_green: Rgb(0, 139, 139),
+            tags: Self::tag_colors(),
+            heat: Self::heat_colors(),
+        }
+    }
+
+    pub fn light() -> Self {
+        Palette {
+            flag: Rgb(220, 20, 60),
+            star: Rgb(255, 215, 0),
+            checkbox: Rgb(18, 59, 151),
+            gray: Rgb(128, 128, 128),
+            green: Rgb(22, 152, 43),
+            blue_green: Rgb(0, 128, 128),
+            tags: Self::tag_colors(),
+            heat: Self::heat_colors(),
+        }
+    }
+
+    fn tag_colors() -> Vec<Rgb> {
+        vec![
+            Rgb(0, 139, 139),
+            Rgb(93, 124, 215),
+            Rgb(186, 85, 211),
+            Rgb(50, 205, 50),
+            Rgb(255, 140, 0),
+            Rgb(219, 112, 147),
+        ]
+    }
+
+    fn heat_colors() -> Vec<Rgb> {
+        vec![Rgb(255, 215, 0), Rgb(255, 140, 0), Rgb(215, 64, 68)]
+    }
+
+    /// The color of a tag, which stays the same across runs.
+    pub fn tag(&self, tag: &str) -> Option<Rgb> {
+        if self.tags.is_empty() {
+            return None;
+        }
+        let hash = tag.bytes().fold(0usize, |hash, b| {
+            hash.wrapping_mul(31).wrapping_add(b as usize)
+        });
+        Some(self.tags[hash % self.tags.len()])
+    }
+
+    /// The color of a weight above 1, getting hotter up to the last heat color.
+    pub fn heat(&self, weight: u32) -> Option<Rgb> {
+        if weight <= 1 {
+            return None;
+        }
+        let index = (weight as usize - 2).min(self.heat.len().checked_sub(1)?);
+        self.heat.get(index).copied()
+    }
+}
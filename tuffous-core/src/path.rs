@@ -0,0 +1,278 @@
+use crate::{id::MIN_SHORT_ID_LEN, TodoInstance};
+
+#[derive(Debug)]
+pub enum PathError {
+    /// No todo matches the segment of the path.
+    NotFound(String),
+    /// Several todos match the whole path.
+    Ambiguous(Vec<u64>),
+}
+
+/// Split a path of names on `/`, where `\/` is a slash inside a name.
+pub fn split_path(path: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut segment = String::new();
+    let mut chars = path.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    segment.push(next);
+                }
+            }
+            '/' => segments.push(std::mem::take(&mut segment)),
+            _ => segment.push(c),
+        }
+    }
+    segments.push(segment);
+
+    segments
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Names matching at this level or better are exact names or prefixes.
+const PREFIX: u8 = 1;
+
+/// Every name that matches at all matches at this level or better.
+const FUZZY: u8 = 3;
+
+/// How well a name matches a segment, from exact to fuzzy. `None` if it doesn't match.
+fn match_level(name: &str, segment: &str) -> Option<u8> {
+    let name = name.to_lowercase();
+    let segment = segment.to_lowercase();
+
+    if name == segment {
+        Some(0)
+    } else if name.starts_with(&segment) {
+        Some(1)
+    } else if name.contains(&segment) {
+        Some(2)
+    } else {
+        // Every char of the segment appears in order
+        let mut chars = name.chars();
+        if segment.chars().all(|c| chars.any(|n| n == c)) {
+            Some(3)
+        } else {
+            None
+        }
+    }
+}
+
+impl TodoInstance {
+    /// The names of the todo and its fathers, following the first father of each.
+    pub fn todo_path(&self, id: u64) -> String {
+        let mut names = Vec::new();
+        let mut current = self.get(id);
+        while let Some(todo) = current {
+            names.push(todo.metadata.name.replace('/', "\\/"));
+            if names.len() > self.todos.len() {
+                break;
+            }
+            current = todo.dependents.first().and_then(|dep| self.get(*dep));
+        }
+        names.reverse();
+        names.join("/")
+    }
+
+    /// Find a todo by the names of its ancestry, like `Website/Launch/Write copy`.
+    ///
+    /// Each segment matches case-insensitively, preferring exact names, then prefixes,
+    /// then substrings and finally fuzzy matches. The first segment matches top-level
+    /// todos, or any todo if no top-level todo matches.
+    pub fn resolve_path(&self, path: &str) -> Result<u64, PathError> {
+        self.resolve_path_within(path, FUZZY)
+    }
+
+    /// Find a todo by its full id, a unique prefix of its hex id or the path of its names.
+    ///
+    /// An id prefix wins over names, exact names and prefixes over substrings and
+    /// fuzzy matches, so a short id stays usable whatever the names look like.
+    pub fn find(&self, reference: &str) -> Result<u64, PathError> {
+        if let Ok(id) = reference.parse::<u64>() {
+            if self.get(id).is_some() {
+                return Ok(id);
+            }
+        }
+
+        let mut ids = Vec::new();
+        if reference.len() >= MIN_SHORT_ID_LEN && reference.chars().all(|c| c.is_ascii_hexdigit()) {
+            ids = self.find_by_prefix(reference);
+            if let [id] = ids[..] {
+                return Ok(id);
+            }
+        }
+
+        match self.resolve_path_within(reference, PREFIX) {
+            Err(PathError::NotFound(_)) if !ids.is_empty() => Err(PathError::Ambiguous(ids)),
+            Err(PathError::NotFound(_)) => self.resolve_path(reference),
+            result => result,
+        }
+    }
+
+    /// Resolve the path with names matching each segment at `max_level` or better.
+    fn resolve_path_within(&self, path: &str, max_level: u8) -> Result<u64, PathError> {
+        let segments = split_path(path);
+        let Some(first) = segments.first() else {
+            return Err(PathError::NotFound(path.to_string()));
+        };
+
+        let roots: Vec<u64> = self
+            .todos
+            .iter()
+            .filter(|todo| todo.dependents.is_empty())
+            .map(|todo| todo.id())
+            .collect();
+        let mut candidates = self.best_matches(&roots, first, max_level);
+        if candidates.is_empty() {
+            candidates = self.best_matches(&self.todos(), first, max_level);
+        }
+
+        for segment in segments.iter().skip(1) {
+            if candidates.is_empty() {
+                break;
+            }
+
+            let mut children = Vec::new();
+            for candidate in &candidates {
+                for child in self.children_once(*candidate) {
+                    if !children.contains(&child) {
+                        children.push(child);
+                    }
+                }
+            }
+            candidates = self.best_matches(&children, segment, max_level);
+            if candidates.is_empty() {
+                return Err(PathError::NotFound(segment.to_string()));
+            }
+        }
+
+        match candidates.len() {
+            0 => Err(PathError::NotFound(first.to_string())),
+            1 => Ok(candidates[0]),
+            _ => Err(PathError::Ambiguous(candidates)),
+        }
+    }
+
+    fn best_matches(&self, ids: &[u64], segment: &str, max_level: u8) -> Vec<u64> {
+        let levels: Vec<(u64, u8)> = ids
+            .iter()
+            .filter_map(|id| {
+                let todo = self.get(*id)?;
+                match_level(&todo.metadata.name, segment)
+                    .filter(|level| *level <= max_level)
+                    .map(|level| (*id, level))
+            })
+            .collect();
+
+        let Some(best) = levels.iter().map(|(_, level)| *level).min() else {
+            return Vec::new();
+        };
+        levels
+            .into_iter()
+            .filter(|(_, level)| *level == best)
+            .map(|(id, _)| id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{id::hex_id, Todo};
+
+    fn todo(instance: &mut TodoInstance, id: u64, name: &str, father: Option<u64>) -> u64 {
+        let mut todo = Todo::create(String::from(name));
+        todo.id = id;
+        todo.dependents.extend(father);
+        instance.insert(todo);
+        id
+    }
+
+    #[test]
+    fn short_ids_win_over_names() {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let bad = todo(&mut instance, 0xbad0_0000_0000_0001, "Call the bank", None);
+        let bread = todo(&mut instance, 0x1000_0000_0000_0000, "Buy bread", None);
+        let named = todo(&mut instance, 0x2000_0000_0000_0000, "cafe", None);
+        todo(&mut instance, 0xcafe_0000_0000_0001, "Lunch", None);
+        todo(&mut instance, 0xcafe_0000_0000_0002, "Dinner", None);
+
+        // `bad` fuzzily matches "Buy bread", but is the short id of a todo
+        assert_eq!(instance.short_id(bad), "bad");
+        assert_eq!(instance.find("bad").ok(), Some(bad));
+        assert_eq!(instance.find("BAD").ok(), Some(bad));
+        assert_eq!(instance.find(&hex_id(bad)).ok(), Some(bad));
+        assert_eq!(instance.find(&bad.to_string()).ok(), Some(bad));
+        // An ambiguous prefix gives way to an exact name
+        assert_eq!(instance.find("cafe").ok(), Some(named));
+        assert!(matches!(
+            instance.find("cafe0"),
+            Err(PathError::Ambiguous(ids)) if ids.len() == 2
+        ));
+        // Fuzzy names are still found when nothing else matches
+        assert_eq!(instance.find("bbrd").ok(), Some(bread));
+        assert_eq!(instance.find("bread").ok(), Some(bread));
+        assert!(matches!(instance.find("fed"), Err(PathError::NotFound(_))));
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(
+            split_path("Website/ Launch /Write copy"),
+            ["Website", "Launch", "Write copy"]
+        );
+        assert_eq!(split_path("A\\/B/C"), ["A/B", "C"]);
+        assert_eq!(split_path("//A//"), ["A"]);
+        assert!(split_path("").is_empty());
+    }
+
+    #[test]
+    fn ambiguous_paths() {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let website = todo(&mut instance, 1, "Website", None);
+        let shop = todo(&mut instance, 2, "Shop", None);
+        let site_launch = todo(&mut instance, 3, "Launch", Some(website));
+        let shop_launch = todo(&mut instance, 4, "Launch", Some(shop));
+        let copy = todo(&mut instance, 5, "Write copy", Some(site_launch));
+        let launched = todo(&mut instance, 6, "Launch party", Some(shop));
+
+        // Exact names win over the prefix, but two remain
+        assert!(matches!(
+            instance.resolve_path("Launch"),
+            Err(PathError::Ambiguous(ids)) if ids == [site_launch, shop_launch]
+        ));
+        assert_eq!(
+            instance.resolve_path("website/launch").ok(),
+            Some(site_launch)
+        );
+        assert_eq!(instance.resolve_path("Shop/Launch").ok(), Some(shop_launch));
+        assert_eq!(instance.resolve_path("Shop/party").ok(), Some(launched));
+        // The first segment falls back to todos that aren't top-level
+        assert_eq!(instance.resolve_path("Launch/Write").ok(), Some(copy));
+        assert!(matches!(
+            instance.resolve_path("Website/Party"),
+            Err(PathError::NotFound(segment)) if segment == "Party"
+        ));
+        assert!(matches!(
+            instance.resolve_path(""),
+            Err(PathError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn paths_of_todos() {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let io = todo(&mut instance, 1, "I/O", None);
+        let read = todo(&mut instance, 2, "Read", Some(io));
+        assert_eq!(instance.todo_path(read), "I\\/O/Read");
+        assert_eq!(
+            instance.resolve_path(&instance.todo_path(read)).ok(),
+            Some(read)
+        );
+        assert_eq!(instance.todo_path(7), "");
+    }
+}
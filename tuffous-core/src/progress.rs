@@ -0,0 +1,165 @@
+//! How much of a todo and its subtree is done.
+//!
+//! A todo counts the progress of its children, or its own units if it has
+//! none, plus its checklist. Completed todos are done as a whole.
+
+use crate::TodoInstance;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+/// What a unit of progress is.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Measure {
+    /// Every todo without children and every checklist item is one unit.
+    Count,
+    /// Todos without children count their weight, checklist items one unit.
+    #[default]
+    Weight,
+    /// Todos without children count their estimate in minutes, done in the
+    /// share of their checklist that is checked.
+    Estimate,
+}
+
+/// How cancelled todos count.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Cancelled {
+    /// Left out of the total.
+    #[default]
+    Exclude,
+    /// Counted as done.
+    Done,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Strategy {
+    #[serde(default)]
+    pub measure: Measure,
+    #[serde(default)]
+    pub cancelled: Cancelled,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Progress {
+    pub done: u32,
+    pub total: u32,
+    pub percent: u32,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    /// Parse a strategy like `count`, `estimate:done` or `weight:exclude`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (measure, cancelled) = s.split_once(':').unwrap_or((s, "exclude"));
+        Ok(Strategy {
+            measure: match measure.to_lowercase().as_str() {
+                "count" => Measure::Count,
+                "weight" => Measure::Weight,
+                "estimate" => Measure::Estimate,
+                _ => return Err(format!("Unknown progress measure: {measure}")),
+            },
+            cancelled: match cancelled.to_lowercase().as_str() {
+                "exclude" => Cancelled::Exclude,
+                "done" => Cancelled::Done,
+                _ => return Err(format!("Unknown way to count cancelled todos: {cancelled}")),
+            },
+        })
+    }
+}
+
+impl Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let measure = match self.measure {
+            Measure::Count => "count",
+            Measure::Weight => "weight",
+            Measure::Estimate => "estimate",
+        };
+        match self.cancelled {
+            Cancelled::Exclude => write!(f, "{measure}"),
+            Cancelled::Done => write!(f, "{measure}:done"),
+        }
+    }
+}
+
+impl TodoInstance {
+    /// The strategy of the todo, set on it or its nearest ancestor, or else
+    /// the one of the repo.
+    pub fn strategy(&self, id: u64) -> Strategy {
+        std::iter::once(id)
+            .chain(self.all_deps(id))
+            .find_map(|id| self.get(id).and_then(|todo| todo.progress))
+            .unwrap_or(self.default_strategy)
+    }
+
+    /// The progress of the todo with its strategy, `None` if it doesn't exist.
+    pub fn progress(&self, id: u64) -> Option<Progress> {
+        self.progress_with(id, self.strategy(id))
+    }
+
+    pub fn progress_with(&self, id: u64, strategy: Strategy) -> Option<Progress> {
+        self.get(id)?;
+        if let Some(progress) = self.cache().progress.get(&(id, strategy)) {
+            return Some(*progress);
+        }
+
+        let (done, total) = self.measure(id, strategy, &mut Vec::new());
+        let progress = Progress {
+            done: done as u32,
+            total: total as u32,
+            percent: (done * 100).checked_div(total).unwrap_or(0) as u32,
+        };
+        self.cache().progress.insert((id, strategy), progress);
+        Some(progress)
+    }
+
+    /// The done and total units of the todo. Todos already on the path are
+    /// skipped, in case of a cycle.
+    fn measure(&self, id: u64, strategy: Strategy, path: &mut Vec<u64>) -> (u64, u64) {
+        let Some(todo) = self.get(id) else {
+            return (0, 0);
+        };
+        if path.contains(&id) || todo.cancelled && strategy.cancelled == Cancelled::Exclude {
+            return (0, 0);
+        }
+
+        let checklist = &todo.metadata.checklist;
+        let checked = checklist.iter().filter(|item| item.checked).count() as u64;
+        let children = self.children_once(id);
+        let mut done = 0;
+        let mut total = 0;
+
+        if strategy.measure != Measure::Estimate {
+            done += checked;
+            total += checklist.len() as u64;
+        }
+        if children.is_empty() {
+            let units = match strategy.measure {
+                Measure::Count => 1,
+                Measure::Weight => todo.weight as u64,
+                Measure::Estimate => todo.estimate.unwrap_or(0) as u64,
+            };
+            if strategy.measure == Measure::Estimate && !checklist.is_empty() {
+                done += units * checked / checklist.len() as u64;
+            }
+            total += units;
+        }
+
+        path.push(id);
+        for child in children {
+            let (d, t) = self.measure(child, strategy, path);
+            done += d;
+            total += t;
+        }
+        path.pop();
+
+        if todo.completed || todo.cancelled {
+            done = total;
+        }
+        (done, total)
+    }
+}
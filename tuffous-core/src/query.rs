@@ -0,0 +1,661 @@
+//! Filter expressions shared by the frontends, like
+//! `tag:work and (deadline<=today or weight>=3) and not status:done`.
+//!
+//! - Terms next to each other are joined with AND. `and`, `or`, `not`, `&&`, `||`,
+//!   `-` and `!` combine them, and parentheses group them.
+//! - A bare word matches the name, details or tags, case-insensitively.
+//! - `name:`, `details:` and `tag:` match the field, `text:` any of them.
+//! - `date`, `deadline` (`ddl`), `created` and `completed` compare dates with `:`, `=`,
+//!   `<`, `<=`, `>` or `>=`, like `deadline<2024-05-01`. `none` matches a missing date,
+//!   and `+7` or `-7` are days from today.
+//! - `weight` compares numbers the same way. Both accept ranges like `weight:2..4`.
+//! - `status:` is one of `open`, `done`, `cancelled` (`canceled`), `overdue` or `today`.
+//!   Cancelled todos are neither open, overdue nor due today.
+//! - `has:` is one of `date`, `deadline`, `children`, `checklist`, `tags` or `details`.
+//! - `under:` matches todos under the project with the name or path.
+//! - Values with spaces are quoted, like `tag:"to read"`.
+
+use crate::{path::PathError, util::parse_date, TodoInstance};
+use chrono::{Duration, Local, NaiveDate};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// Matches every todo, the query of an empty expression.
+    All,
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Term(Term),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Text(String),
+    Name(String),
+    Details(String),
+    Tag(String),
+    Date(DateField, Op, NaiveDate),
+    Weight(Op, u32),
+    Status(Status),
+    Has(Has),
+    Under(String),
+    /// Matches todos under one of the projects, an `under:` term resolved by
+    /// [`Query::resolve`].
+    Within(Vec<u64>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateField {
+    Date,
+    Deadline,
+    Created,
+    Completed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Open,
+    Done,
+    Cancelled,
+    Overdue,
+    Today,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Has {
+    Date,
+    Deadline,
+    Children,
+    Checklist,
+    Tags,
+    Details,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word(String),
+}
+
+impl Op {
+    fn test<T: PartialOrd>(&self, a: T, b: T) -> bool {
+        match self {
+            Op::Eq => a == b,
+            Op::Lt => a < b,
+            Op::Le => a <= b,
+            Op::Gt => a > b,
+            Op::Ge => a >= b,
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(string: &str) -> Result<Query, String> {
+        let tokens = tokenize(string)?;
+        if tokens.is_empty() {
+            return Ok(Query::All);
+        }
+
+        let mut parser = Parser { tokens, pos: 0 };
+        let query = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(query),
+            Some(Token::Close) => Err(String::from("Unmatched ')' in query")),
+            Some(_) => Err(String::from("Unexpected token in query")),
+        }
+    }
+
+    /// A query matching todos that contain all of the words, for input that
+    /// doesn't parse as an expression.
+    pub fn words(string: &str) -> Query {
+        string
+            .split_whitespace()
+            .map(|word| Query::Term(Term::Text(word.to_lowercase())))
+            .reduce(|a, b| Query::And(Box::new(a), Box::new(b)))
+            .unwrap_or(Query::All)
+    }
+
+    /// The query with its `under:` projects looked up, to match many todos
+    /// without resolving the paths for each of them.
+    pub fn resolve(self, instance: &TodoInstance) -> Query {
+        match self {
+            Query::And(a, b) => {
+                Query::And(Box::new(a.resolve(instance)), Box::new(b.resolve(instance)))
+            }
+            Query::Or(a, b) => {
+                Query::Or(Box::new(a.resolve(instance)), Box::new(b.resolve(instance)))
+            }
+            Query::Not(a) => Query::Not(Box::new(a.resolve(instance))),
+            Query::Term(Term::Under(path)) => Query::Term(Term::Within(projects(instance, &path))),
+            query => query,
+        }
+    }
+
+    /// The text terms the query looks for, leaving out negated ones.
+    pub fn texts(&self) -> Vec<String> {
+        match self {
+            Query::And(a, b) | Query::Or(a, b) => {
+                let mut texts = a.texts();
+                texts.append(&mut b.texts());
+                texts
+            }
+            Query::Term(Term::Text(text) | Term::Name(text)) => vec![text.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn matches(&self, instance: &TodoInstance, id: u64) -> bool {
+        match self {
+            Query::All => true,
+            Query::And(a, b) => a.matches(instance, id) && b.matches(instance, id),
+            Query::Or(a, b) => a.matches(instance, id) || b.matches(instance, id),
+            Query::Not(a) => !a.matches(instance, id),
+            Query::Term(term) => term.matches(instance, id),
+        }
+    }
+}
+
+impl Term {
+    pub fn matches(&self, instance: &TodoInstance, id: u64) -> bool {
+        let Some(todo) = instance.get(id) else {
+            return false;
+        };
+        let contains = |field: &str, text: &str| field.to_lowercase().contains(text);
+        let now = Local::now().naive_local();
+
+        match self {
+            Term::Text(text) => {
+                contains(&todo.metadata.name, text)
+                    || contains(todo.metadata.details(), text)
+                    || todo.tags.iter().any(|tag| contains(tag, text))
+            }
+            Term::Name(text) => contains(&todo.metadata.name, text),
+            Term::Details(text) => contains(todo.metadata.details(), text),
+            Term::Tag(tag) => todo.tags.iter().any(|t| t.to_lowercase() == *tag),
+            Term::Date(field, op, date) => {
+                let value = match field {
+                    DateField::Date => todo.time,
+                    DateField::Deadline => todo.deadline.map(|ddl| ddl.date()),
+                    DateField::Created => Some(todo.creation_date().date()),
+                    DateField::Completed => todo.completion_date().map(|d| d.date()),
+                };
+                value.is_some_and(|value| op.test(value, *date))
+            }
+            Term::Weight(op, weight) => op.test(todo.weight, *weight),
+            Term::Status(status) => match status {
+                Status::Open => !todo.completed && !todo.cancelled,
+                Status::Done => todo.completed,
+                Status::Cancelled => todo.cancelled,
+                Status::Overdue => {
+                    !todo.completed
+                        && !todo.cancelled
+                        && todo.deadline.is_some_and(|ddl| ddl <= now)
+                }
+                Status::Today => {
+                    !todo.completed
+                        && !todo.cancelled
+                        && (todo.time == Some(now.date())
+                            || todo.deadline.is_some_and(|ddl| ddl.date() == now.date()))
+                }
+            },
+            Term::Has(has) => match has {
+                Has::Date => todo.time.is_some(),
+                Has::Deadline => todo.deadline.is_some(),
+                Has::Children => !instance.children_once(id).is_empty(),
+                Has::Checklist => !todo.metadata.checklist.is_empty(),
+                Has::Tags => !todo.tags.is_empty(),
+                Has::Details => !todo.metadata.details().is_empty(),
+            },
+            Term::Under(path) => Term::Within(projects(instance, path)).matches(instance, id),
+            Term::Within(projects) => instance
+                .all_deps(id)
+                .iter()
+                .any(|dep| projects.contains(dep)),
+        }
+    }
+}
+
+/// The projects with the name or path, every one of them if it's ambiguous.
+fn projects(instance: &TodoInstance, path: &str) -> Vec<u64> {
+    match instance.resolve_path(path) {
+        Ok(project) => vec![project],
+        Err(PathError::Ambiguous(projects)) => projects,
+        Err(PathError::NotFound(_)) => Vec::new(),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn or(&mut self) -> Result<Query, String> {
+        let mut query = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, String> {
+        let mut query = self.not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                Some(Token::Open | Token::Not | Token::Word(_)) => (),
+                _ => break,
+            }
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+        Ok(query)
+    }
+
+    fn not(&mut self) -> Result<Query, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Query, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Open) => {
+                let query = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(String::from("Unclosed '(' in query"));
+                }
+                self.pos += 1;
+                Ok(query)
+            }
+            Some(Token::Word(word)) => parse_term(&word),
+            Some(Token::Close) => Err(String::from("Unexpected ')' in query")),
+            Some(_) => Err(String::from("Expected a term after an operator in query")),
+            None => Err(String::from("Unexpected end of query")),
+        }
+    }
+}
+
+fn tokenize(string: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = string.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '-' | '!' if chars.peek().is_some_and(|n| !n.is_whitespace()) => {
+                tokens.push(Token::Not)
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                let mut was_quoted = false;
+                let mut c = c;
+                loop {
+                    if c == '"' {
+                        quoted = !quoted;
+                        was_quoted = true;
+                    } else {
+                        word.push(c);
+                    }
+
+                    match chars.peek() {
+                        Some(&n) if quoted || !(n.is_whitespace() || n == '(' || n == ')') => {
+                            c = n;
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+                if quoted {
+                    return Err(String::from("Unclosed '\"' in query"));
+                }
+
+                // Keywords are only operators when they aren't quoted
+                tokens.push(match word.to_lowercase().as_str() {
+                    "and" | "&&" if !was_quoted => Token::And,
+                    "or" | "||" if !was_quoted => Token::Or,
+                    "not" if !was_quoted => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_term(word: &str) -> Result<Query, String> {
+    let Some(index) = word.find([':', '=', '<', '>']) else {
+        return Ok(Query::Term(Term::Text(word.to_lowercase())));
+    };
+    let field = word[..index].to_lowercase();
+    let mut rest = &word[index..];
+
+    // `:` may be followed by a comparison, like `date:<today`
+    if let Some(r) = rest.strip_prefix(':') {
+        rest = r;
+    }
+    let (op, value) = if let Some(v) = rest.strip_prefix("<=") {
+        (Op::Le, v)
+    } else if let Some(v) = rest.strip_prefix(">=") {
+        (Op::Ge, v)
+    } else if let Some(v) = rest.strip_prefix('<') {
+        (Op::Lt, v)
+    } else if let Some(v) = rest.strip_prefix('>') {
+        (Op::Gt, v)
+    } else {
+        (Op::Eq, rest.strip_prefix('=').unwrap_or(rest))
+    };
+    let term = |term: Term| Ok(Query::Term(term));
+    let text = value.to_lowercase();
+
+    match field.as_str() {
+        "text" => term(Term::Text(text)),
+        "name" => term(Term::Name(text)),
+        "details" => term(Term::Details(text)),
+        "tag" => term(Term::Tag(text)),
+        "under" => term(Term::Under(value.to_string())),
+        "status" | "is" => term(Term::Status(match text.as_str() {
+            "open" => Status::Open,
+            "done" | "completed" => Status::Done,
+            "cancelled" | "canceled" => Status::Cancelled,
+            "overdue" => Status::Overdue,
+            "today" => Status::Today,
+            _ => return Err(format!("Unknown status in query: {value}")),
+        })),
+        "has" => term(Term::Has(match text.as_str() {
+            "date" => Has::Date,
+            "deadline" | "ddl" => Has::Deadline,
+            "children" => Has::Children,
+            "checklist" => Has::Checklist,
+            "tags" | "tag" => Has::Tags,
+            "details" => Has::Details,
+            _ => return Err(format!("Unknown field of has in query: {value}")),
+        })),
+        "weight" => compare(op, value, |op, v| {
+            v.parse::<u32>()
+                .map(|weight| Term::Weight(op, weight))
+                .map_err(|_| format!("Invalid weight in query: {v}"))
+        }),
+        "date" | "deadline" | "ddl" | "created" | "completed" => {
+            let field = match field.as_str() {
+                "date" => DateField::Date,
+                "deadline" | "ddl" => DateField::Deadline,
+                "created" => DateField::Created,
+                _ => DateField::Completed,
+            };
+            if text == "none" {
+                return Ok(Query::Not(Box::new(Query::Term(match field {
+                    DateField::Date => Term::Has(Has::Date),
+                    DateField::Deadline => Term::Has(Has::Deadline),
+                    DateField::Created => return Ok(Query::Not(Box::new(Query::All))),
+                    DateField::Completed => Term::Status(Status::Done),
+                }))));
+            }
+            compare(op, value, |op, v| {
+                parse_query_date(v)
+                    .map(|date| Term::Date(field, op, date))
+                    .ok_or_else(|| format!("Invalid date in query: {v}"))
+            })
+        }
+        _ => Err(format!("Unknown field in query: {field}")),
+    }
+}
+
+/// Build a comparison, or the two bounds of a `from..to` range.
+fn compare(
+    op: Op,
+    value: &str,
+    term: impl Fn(Op, &str) -> Result<Term, String>,
+) -> Result<Query, String> {
+    if let (Op::Eq, Some((from, to))) = (op, value.split_once("..")) {
+        return Ok(Query::And(
+            Box::new(Query::Term(term(Op::Ge, from)?)),
+            Box::new(Query::Term(term(Op::Le, to)?)),
+        ));
+    }
+    Ok(Query::Term(term(op, value)?))
+}
+
+fn parse_query_date(string: &str) -> Option<NaiveDate> {
+    let today = Local::now().date_naive();
+    match string.to_lowercase().as_str() {
+        "tomorrow" => Some(today + Duration::days(1)),
+        "yesterday" => Some(today - Duration::days(1)),
+        s if s.starts_with(['+', '-']) => s[1..]
+            .parse::<i64>()
+            .ok()
+            .map(|days| today + Duration::days(if s.starts_with('-') { -days } else { days })),
+        _ => parse_date(string),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Todo;
+
+    fn text(text: &str) -> Query {
+        Query::Term(Term::Text(text.to_string()))
+    }
+
+    fn and(a: Query, b: Query) -> Query {
+        Query::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: Query, b: Query) -> Query {
+        Query::Or(Box::new(a), Box::new(b))
+    }
+
+    fn not(a: Query) -> Query {
+        Query::Not(Box::new(a))
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            Query::parse("a or b c").unwrap(),
+            or(text("a"), and(text("b"), text("c")))
+        );
+        assert_eq!(
+            Query::parse("a && b || c").unwrap(),
+            or(and(text("a"), text("b")), text("c"))
+        );
+        assert_eq!(
+            Query::parse("a and (b or c)").unwrap(),
+            and(text("a"), or(text("b"), text("c")))
+        );
+    }
+
+    #[test]
+    fn negation() {
+        assert_eq!(
+            Query::parse("not a b").unwrap(),
+            and(not(text("a")), text("b"))
+        );
+        assert_eq!(
+            Query::parse("-a !b").unwrap(),
+            and(not(text("a")), not(text("b")))
+        );
+        assert_eq!(Query::parse("not not a").unwrap(), not(not(text("a"))));
+        assert_eq!(
+            Query::parse("not (a or b)").unwrap(),
+            not(or(text("a"), text("b")))
+        );
+        // A dash before a space isn't an operator
+        assert_eq!(
+            Query::parse("a - b").unwrap(),
+            and(and(text("a"), text("-")), text("b"))
+        );
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(Query::parse("").unwrap(), Query::All);
+        assert_eq!(
+            Query::parse("Tag:Work").unwrap(),
+            Query::Term(Term::Tag(String::from("work")))
+        );
+        assert_eq!(
+            Query::parse(r#"tag:"to read" "or""#).unwrap(),
+            and(Query::Term(Term::Tag(String::from("to read"))), text("or"))
+        );
+        assert_eq!(
+            Query::parse("weight>=3").unwrap(),
+            Query::Term(Term::Weight(Op::Ge, 3))
+        );
+        assert_eq!(
+            Query::parse("weight:<3").unwrap(),
+            Query::Term(Term::Weight(Op::Lt, 3))
+        );
+        assert_eq!(
+            Query::parse("weight:2..4").unwrap(),
+            and(
+                Query::Term(Term::Weight(Op::Ge, 2)),
+                Query::Term(Term::Weight(Op::Le, 4))
+            )
+        );
+        assert_eq!(
+            Query::parse("ddl:none").unwrap(),
+            not(Query::Term(Term::Has(Has::Deadline)))
+        );
+        let today = Local::now().date_naive();
+        assert_eq!(
+            Query::parse("date<+7").unwrap(),
+            Query::Term(Term::Date(
+                DateField::Date,
+                Op::Lt,
+                today + Duration::days(7)
+            ))
+        );
+        assert_eq!(
+            Query::parse("is:done").unwrap(),
+            Query::Term(Term::Status(Status::Done))
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let error = |query: &str| Query::parse(query).unwrap_err();
+        assert_eq!(error("(a or b"), "Unclosed '(' in query");
+        assert_eq!(error("a)"), "Unmatched ')' in query");
+        assert_eq!(error(")"), "Unexpected ')' in query");
+        assert_eq!(error("a and"), "Unexpected end of query");
+        assert_eq!(
+            error("a or or b"),
+            "Expected a term after an operator in query"
+        );
+        assert_eq!(error(r#"tag:"to read"#), "Unclosed '\"' in query");
+        assert_eq!(error("weight:heavy"), "Invalid weight in query: heavy");
+        assert_eq!(error("date:someday"), "Invalid date in query: someday");
+        assert_eq!(error("status:later"), "Unknown status in query: later");
+        assert_eq!(error("has:wings"), "Unknown field of has in query: wings");
+        assert_eq!(error("color:red"), "Unknown field in query: color");
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(Query::words("Buy  Milk"), and(text("buy"), text("milk")));
+        assert_eq!(Query::words(" "), Query::All);
+    }
+
+    #[test]
+    fn evaluate() {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let mut report = Todo::create(String::from("Write report"));
+        report.tags = vec![String::from("Work")];
+        report.weight = 3;
+        report.deadline = Some(Local::now().naive_local() - Duration::days(1));
+        let mut milk = Todo::create(String::from("Buy milk"));
+        milk.tags = vec![String::from("home")];
+        milk.weight = 1;
+        milk.set_completed(true);
+        let mut call = Todo::create(String::from("Call the bank"));
+        call.cancelled = true;
+        call.weight = 5;
+        call.time = Some(Local::now().date_naive());
+        call.deadline = Some(Local::now().naive_local() - Duration::hours(1));
+        instance.insert(report);
+        instance.insert(milk);
+        instance.insert(call);
+
+        let matching = |query: &str| -> Vec<String> {
+            let query = Query::parse(query).unwrap();
+            instance
+                .todos
+                .iter()
+                .filter(|todo| query.matches(&instance, todo.id()))
+                .map(|todo| todo.metadata.name.clone())
+                .collect()
+        };
+        assert_eq!(matching("tag:work weight>=3"), ["Write report"]);
+        assert_eq!(
+            matching("not status:done"),
+            ["Write report", "Call the bank"]
+        );
+        assert_eq!(matching("status:open"), ["Write report"]);
+        assert_eq!(matching("status:canceled"), ["Call the bank"]);
+        assert_eq!(matching("status:overdue"), ["Write report"]);
+        assert_eq!(matching("status:today"), Vec::<String>::new());
+        assert_eq!(matching("-tag:work -call"), ["Buy milk"]);
+        assert_eq!(matching("MILK or report"), ["Write report", "Buy milk"]);
+        assert_eq!(matching("milk and report"), Vec::<String>::new());
+        assert_eq!(matching("ddl:none"), ["Buy milk"]);
+        assert_eq!(matching("weight:1..2"), ["Buy milk"]);
+        assert_eq!(matching(""), ["Write report", "Buy milk", "Call the bank"]);
+    }
+
+    #[test]
+    fn resolve_under() {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let work = Todo::create(String::from("Work"));
+        let work_id = work.id();
+        let mut report = Todo::create(String::from("Write report"));
+        report.dependents = vec![work_id];
+        let report_id = report.id();
+        instance.insert(work);
+        instance.insert(report);
+
+        let query = Query::parse("under:work or under:nowhere").unwrap();
+        let resolved = query.clone().resolve(&instance);
+        assert_eq!(
+            resolved,
+            or(
+                Query::Term(Term::Within(vec![work_id])),
+                Query::Term(Term::Within(Vec::new()))
+            )
+        );
+        for id in [work_id, report_id] {
+            assert_eq!(
+                resolved.matches(&instance, id),
+                query.matches(&instance, id)
+            );
+        }
+        assert!(resolved.matches(&instance, report_id));
+        assert!(!resolved.matches(&instance, work_id));
+    }
+}
@@ -0,0 +1,285 @@
+use crate::TodoInstance;
+use regex::Regex;
+use std::ops::Range;
+
+/// Scores of the fields a match is found in are multiplied by these.
+const NAME_WEIGHT: i64 = 3;
+const TAGS_WEIGHT: i64 = 2;
+const DETAILS_WEIGHT: i64 = 1;
+
+pub enum Matcher {
+    /// The chars of the pattern in order, case-insensitively.
+    Fuzzy(Vec<char>),
+    Regex(Regex),
+}
+
+/// A todo found by a search, with the byte ranges of the matched text.
+#[derive(Debug, Clone, Default)]
+pub struct Hit {
+    pub id: u64,
+    pub score: i64,
+    pub name: Vec<Range<usize>>,
+    pub details: Vec<Range<usize>>,
+    /// The matched ranges of every tag, in the order of the tags.
+    pub tags: Vec<Vec<Range<usize>>>,
+}
+
+impl Matcher {
+    pub fn fuzzy(pattern: &str) -> Self {
+        Matcher::Fuzzy(pattern.chars().map(lowercase).collect())
+    }
+
+    /// One fuzzy matcher for each word of the text.
+    pub fn words(text: &str) -> Vec<Self> {
+        text.split_whitespace().map(Self::fuzzy).collect()
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self, String> {
+        Regex::new(pattern)
+            .map(Matcher::Regex)
+            .map_err(|err| format!("Invalid regex: {err}"))
+    }
+
+    /// The score of the text and the byte ranges that matched, `None` if it doesn't match.
+    ///
+    /// Whole substrings score above scattered chars, and matches at the start
+    /// of the text or of a word score above the others.
+    pub fn find(&self, text: &str) -> Option<(i64, Vec<Range<usize>>)> {
+        match self {
+            Matcher::Regex(regex) => {
+                let ranges: Vec<Range<usize>> = regex
+                    .find_iter(text)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| m.range())
+                    .collect();
+                let first = ranges.first()?.start;
+                Some((
+                    100 + word_start_bonus(text, first) - first.min(20) as i64,
+                    ranges,
+                ))
+            }
+            Matcher::Fuzzy(pattern) => {
+                if pattern.is_empty() {
+                    return None;
+                }
+                let chars: Vec<(usize, char)> = text
+                    .char_indices()
+                    .map(|(i, c)| (i, lowercase(c)))
+                    .collect();
+                let span = |from: usize, to: usize| {
+                    chars[from].0..chars.get(to).map_or(text.len(), |(i, _)| *i)
+                };
+
+                // Substring
+                if let Some(start) = chars
+                    .windows(pattern.len())
+                    .position(|w| w.iter().map(|(_, c)| c).eq(pattern.iter()))
+                {
+                    let range = span(start, start + pattern.len());
+                    let score =
+                        100 + 10 * pattern.len() as i64 + word_start_bonus(text, range.start)
+                            - start.min(20) as i64;
+                    return Some((score, vec![range]));
+                }
+
+                // Subsequence
+                let mut score = 0;
+                let mut ranges: Vec<Range<usize>> = Vec::new();
+                let mut next = 0;
+                let mut last: Option<usize> = None;
+                for (index, (byte, c)) in chars.iter().enumerate() {
+                    if next == pattern.len() {
+                        break;
+                    }
+                    if *c != pattern[next] {
+                        continue;
+                    }
+
+                    score += 10 + word_start_bonus(text, *byte) / 2;
+                    let range = span(index, index + 1);
+                    match last {
+                        Some(l) if l + 1 == index => {
+                            score += 15;
+                            ranges.last_mut().unwrap().end = range.end;
+                        }
+                        Some(l) => {
+                            score -= ((index - l) as i64).min(10);
+                            ranges.push(range);
+                        }
+                        None => ranges.push(range),
+                    }
+                    last = Some(index);
+                    next += 1;
+                }
+
+                if next == pattern.len() {
+                    Some((score.max(1), ranges))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl TodoInstance {
+    /// The todos matched by every matcher in their name, details or tags,
+    /// the most relevant first.
+    pub fn search(&self, matchers: &[Matcher]) -> Vec<Hit> {
+        let mut hits = Vec::new();
+
+        'todos: for todo in &self.todos {
+            let mut hit = Hit {
+                id: todo.id(),
+                tags: vec![Vec::new(); todo.tags.len()],
+                ..Default::default()
+            };
+
+            for matcher in matchers {
+                let mut best = 0;
+                if let Some((score, mut ranges)) = matcher.find(&todo.metadata.name) {
+                    best = best.max(score * NAME_WEIGHT);
+                    hit.name.append(&mut ranges);
+                }
+                for (index, tag) in todo.tags.iter().enumerate() {
+                    if let Some((score, mut ranges)) = matcher.find(tag) {
+                        best = best.max(score * TAGS_WEIGHT);
+                        hit.tags[index].append(&mut ranges);
+                    }
+                }
+                if let Some((score, mut ranges)) = matcher.find(todo.metadata.details()) {
+                    best = best.max(score * DETAILS_WEIGHT);
+                    hit.details.append(&mut ranges);
+                }
+
+                if best == 0 {
+                    continue 'todos;
+                }
+                hit.score += best;
+            }
+
+            hits.push(hit);
+        }
+
+        hits.sort_by_key(|hit| -hit.score);
+        hits
+    }
+}
+
+/// Split the text into pieces, each with whether it's inside one of the ranges.
+pub fn split_ranges<'a>(text: &'a str, ranges: &[Range<usize>]) -> Vec<(&'a str, bool)> {
+    let mut ranges = ranges.to_vec();
+    ranges.sort_by_key(|range| range.start);
+
+    let mut pieces = Vec::new();
+    let mut pos = 0;
+    for range in ranges {
+        let start = range.start.max(pos);
+        if start >= range.end || range.end > text.len() {
+            continue;
+        }
+        if start > pos {
+            pieces.push((&text[pos..start], false));
+        }
+        pieces.push((&text[start..range.end], true));
+        pos = range.end;
+    }
+    if pos < text.len() {
+        pieces.push((&text[pos..], false));
+    }
+    pieces
+}
+
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn word_start_bonus(text: &str, byte: usize) -> i64 {
+    if byte == 0 {
+        50
+    } else if text[..byte]
+        .chars()
+        .next_back()
+        .is_some_and(|c| !c.is_alphanumeric())
+    {
+        25
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+    use crate::Todo;
+
+    fn score(pattern: &str, text: &str) -> Option<i64> {
+        Matcher::fuzzy(pattern).find(text).map(|(score, _)| score)
+    }
+
+    #[test]
+    fn fuzzy_ranking() {
+        // Substrings above scattered chars, word starts above the middle of words
+        assert!(score("rep", "Write report") > score("rep", "Prepare"));
+        assert!(score("rep", "Prepare") > score("rpt", "Write report"));
+        assert!(score("rep", "report") > score("rep", "Write report"));
+        assert!(score("wr", "Write report") > score("wt", "Write report"));
+        assert_eq!(score("xyz", "Write report"), None);
+        assert_eq!(score("", "Write report"), None);
+        assert!(score("REPORT", "write report").is_some());
+    }
+
+    #[test]
+    fn highlight_ranges() {
+        let find = |pattern: &str, text: &str| Matcher::fuzzy(pattern).find(text).unwrap().1;
+        assert_eq!(find("port", "Write report"), [8..12]);
+        assert_eq!(find("wrrp", "Write report"), [0..2, 6..7, 8..9]);
+        // Ranges are in bytes, whatever the width of the chars
+        assert_eq!(find("été", "Fête d'été"), [8..13]);
+        assert_eq!(find("fe", "Fête"), [0..1, 4..5]);
+
+        let regex = Matcher::regex("o.").unwrap();
+        assert_eq!(regex.find("foo bar boz").unwrap().1, [1..3, 9..11]);
+        assert!(Matcher::regex("(").is_err());
+
+        assert_eq!(
+            split_ranges("Write report", &[8..12, 0..2]),
+            [("Wr", true), ("ite re", false), ("port", true)]
+        );
+        assert_eq!(
+            split_ranges("abc", &[0..2, 1..3]),
+            [("ab", true), ("c", true)]
+        );
+        assert_eq!(split_ranges("abc", &[]), [("abc", false)]);
+    }
+
+    #[test]
+    fn search_ranks_fields() {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let mut by_name = Todo::create(String::from("Taxes"));
+        by_name.tags = vec![String::from("home")];
+        let mut by_tag = Todo::create(String::from("Send the forms"));
+        by_tag.tags = vec![String::from("home"), String::from("taxes")];
+        let mut by_details = Todo::create(String::from("Call the accountant"));
+        by_details
+            .metadata
+            .set_details(String::from("About the taxes"));
+        let ids = [by_details.id(), by_tag.id(), by_name.id()];
+        instance.insert(by_details);
+        instance.insert(by_tag);
+        instance.insert(by_name);
+
+        let hits = instance.search(&Matcher::words("taxes"));
+        let found: Vec<u64> = hits.iter().map(|hit| hit.id).collect();
+        assert_eq!(found, [ids[2], ids[1], ids[0]]);
+        assert_eq!(hits[1].tags, [vec![], vec![0..5]]);
+        assert_eq!(hits[2].details, [10..15]);
+
+        // Every word has to match
+        let hits = instance.search(&Matcher::words("taxes home"));
+        let found: Vec<u64> = hits.iter().map(|hit| hit.id).collect();
+        assert_eq!(found, [ids[2], ids[1]]);
+        assert!(instance.search(&Matcher::words("taxes garden")).is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::TodoInstance;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+
+/// The length of the periods todos are counted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    /// The key of the period the date is in, like `2024-05-01`, `2024-W18` or `2024-05`.
+    pub fn key(&self, date: NaiveDate) -> String {
+        match self {
+            Period::Day => date.to_string(),
+            Period::Week => {
+                let week = date.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            Period::Month => format!("{}-{:02}", date.year(), date.month()),
+        }
+    }
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "day" | "daily" => Ok(Period::Day),
+            "week" | "weekly" => Ok(Period::Week),
+            "month" | "monthly" => Ok(Period::Month),
+            _ => Err(format!("Unknown period: {s}")),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct PeriodStats {
+    pub period: String,
+    pub created: usize,
+    pub completed: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct GroupStats {
+    /// The tag, or the path of the project.
+    pub name: String,
+    pub total: usize,
+    pub completed: usize,
+    pub weight: u32,
+    pub completed_weight: u32,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Stats {
+    pub total: usize,
+    pub completed: usize,
+    pub open: usize,
+    pub overdue: usize,
+    /// Average days from creation to completion, of the todos that record
+    /// when they were completed.
+    pub average_cycle_days: Option<f64>,
+    pub periods: Vec<PeriodStats>,
+    pub tags: Vec<GroupStats>,
+    pub projects: Vec<GroupStats>,
+    /// Days in a row with completions, up to today or yesterday.
+    pub current_streak: u32,
+    pub longest_streak: u32,
+}
+
+impl TodoInstance {
+    /// Statistics of the todos with the ids.
+    pub fn stats(&self, ids: &[u64], period: Period, now: NaiveDateTime) -> Stats {
+        let mut stats = Stats::default();
+        let mut periods: BTreeMap<String, PeriodStats> = BTreeMap::new();
+        let mut tags: BTreeMap<String, GroupStats> = BTreeMap::new();
+        let mut completion_days = BTreeSet::new();
+        let mut cycles = Vec::new();
+
+        for id in ids {
+            let Some(todo) = self.get(*id) else {
+                continue;
+            };
+
+            stats.total += 1;
+            if todo.completed {
+                stats.completed += 1;
+            } else {
+                stats.open += 1;
+                if todo.deadline.is_some_and(|ddl| ddl < now) {
+                    stats.overdue += 1;
+                }
+            }
+
+            let created = period.key(todo.creation_date().date());
+            periods
+                .entry(created.clone())
+                .or_insert_with(|| PeriodStats {
+                    period: created,
+                    ..Default::default()
+                })
+                .created += 1;
+
+            if let Some(completion) = todo.completion_date().filter(|_| todo.completed) {
+                let completed = period.key(completion.date());
+                periods
+                    .entry(completed.clone())
+                    .or_insert_with(|| PeriodStats {
+                        period: completed,
+                        ..Default::default()
+                    })
+                    .completed += 1;
+                completion_days.insert(completion.date());
+                cycles.push((*completion - *todo.creation_date()).num_minutes());
+            }
+
+            for tag in &todo.tags {
+                let group = tags.entry(tag.to_owned()).or_insert_with(|| GroupStats {
+                    name: tag.to_owned(),
+                    ..Default::default()
+                });
+                group.total += 1;
+                group.weight += todo.weight;
+                if todo.completed {
+                    group.completed += 1;
+                    group.completed_weight += todo.weight;
+                }
+            }
+
+            if !self.children_once(*id).is_empty() {
+                let mut children = self.children(*id);
+                children.sort();
+                children.dedup();
+                stats.projects.push(GroupStats {
+                    name: self.todo_path(*id),
+                    total: children.len(),
+                    completed: children
+                        .iter()
+                        .filter(|child| self.get(**child).is_some_and(|todo| todo.completed))
+                        .count(),
+                    weight: self.weight(*id, false),
+                    completed_weight: self.weight(*id, true),
+                });
+            }
+        }
+
+        if !cycles.is_empty() {
+            let minutes = cycles.iter().sum::<i64>() as f64 / cycles.len() as f64;
+            stats.average_cycle_days = Some(minutes / (60.0 * 24.0));
+        }
+        stats.periods = periods.into_values().collect();
+        stats.tags = tags.into_values().collect();
+        stats.projects.sort_by(|a, b| a.name.cmp(&b.name));
+        (stats.current_streak, stats.longest_streak) = streaks(&completion_days, now.date());
+
+        stats
+    }
+}
+
+/// The current and the longest runs of consecutive days.
+fn streaks(days: &BTreeSet<NaiveDate>, today: NaiveDate) -> (u32, u32) {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for day in days {
+        run = match previous {
+            Some(p) if *day - p == Duration::days(1) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(*day);
+    }
+
+    // A streak isn't broken until a day passes without completions
+    let mut day = if days.contains(&today) {
+        today
+    } else {
+        today - Duration::days(1)
+    };
+    let mut current = 0;
+    while days.contains(&day) {
+        current += 1;
+        day -= Duration::days(1);
+    }
+
+    (current, longest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Todo;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, day).unwrap()
+    }
+
+    fn at(day: u32) -> NaiveDateTime {
+        date(day).and_hms_opt(12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn periods() {
+        assert_eq!(Period::Day.key(date(1)), "2024-05-01");
+        assert_eq!(Period::Week.key(date(1)), "2024-W18");
+        assert_eq!(Period::Month.key(date(1)), "2024-05");
+        // ISO weeks belong to the year of their Thursday
+        let new_year = NaiveDate::from_ymd_opt(2024, 12, 30).unwrap();
+        assert_eq!(Period::Week.key(new_year), "2025-W01");
+        assert_eq!("Weekly".parse::<Period>(), Ok(Period::Week));
+        assert!("yearly".parse::<Period>().is_err());
+    }
+
+    #[test]
+    fn streaks_of_days() {
+        let days: BTreeSet<NaiveDate> = [1, 2, 3, 7, 8].map(date).into();
+        assert_eq!(streaks(&days, date(8)), (2, 3));
+        // Still going the day after, broken the day after that
+        assert_eq!(streaks(&days, date(9)), (2, 3));
+        assert_eq!(streaks(&days, date(10)), (0, 3));
+        assert_eq!(streaks(&BTreeSet::new(), date(10)), (0, 0));
+    }
+
+    #[test]
+    fn stats_of_todos() {
+        let mut instance = TodoInstance::create("/nonexistent");
+        let mut project = Todo::create(String::from("Website"));
+        project.creation_date = at(1);
+        let project_id = project.id();
+        let todo = |name: &str, tag: &str, weight: u32, completed: Option<u32>| {
+            let mut todo = Todo::create(String::from(name));
+            todo.creation_date = at(1);
+            todo.tags = vec![String::from(tag)];
+            todo.weight = weight;
+            todo.dependents = vec![project_id];
+            todo.completed = completed.is_some();
+            todo.completion_date = completed.map(at);
+            todo
+        };
+        let todos = [
+            todo("Design", "work", 2, Some(2)),
+            todo("Write copy", "work", 1, Some(3)),
+            todo("Launch", "home", 3, None),
+        ];
+        instance.insert(project);
+        let mut late = Todo::create(String::from("Pay rent"));
+        late.creation_date = at(8);
+        late.deadline = Some(at(9));
+        instance.insert(late);
+        for todo in todos {
+            instance.insert(todo);
+        }
+
+        let stats = instance.stats(&instance.todos(), Period::Month, at(10));
+        assert_eq!((stats.total, stats.completed, stats.open), (5, 2, 3));
+        assert_eq!(stats.overdue, 1);
+        assert_eq!(stats.average_cycle_days, Some(1.5));
+        assert_eq!(stats.periods.len(), 1);
+        assert_eq!(
+            (stats.periods[0].created, stats.periods[0].completed),
+            (5, 2)
+        );
+        let tags: Vec<(&str, usize, usize, u32, u32)> = stats
+            .tags
+            .iter()
+            .map(|g| {
+                (
+                    g.name.as_str(),
+                    g.total,
+                    g.completed,
+                    g.weight,
+                    g.completed_weight,
+                )
+            })
+            .collect();
+        assert_eq!(tags, [("home", 1, 0, 3, 0), ("work", 2, 2, 3, 3)]);
+        assert_eq!(stats.projects.len(), 1);
+        let website = &stats.projects[0];
+        assert_eq!(website.name, "Website");
+        assert_eq!((website.total, website.completed), (3, 2));
+        assert_eq!((website.weight, website.completed_weight), (6, 3));
+        assert_eq!((stats.current_streak, stats.longest_streak), (0, 2));
+    }
+}
@@ -0,0 +1,675 @@
+//! Where the todos are kept: a file per todo in `.tuffous/todos`, or the
+//! append-only log `.tuffous/todos.jsonl`.
+//!
+//! Every line of the log is a whole todo or a tombstone like `{"removed": 42}`,
+//! and the last line about a todo wins. Compacting the log rewrites it with a
+//! line per todo left, after a line like `{"generation": 7}` with a random
+//! number, which tells readers the log was rewritten rather than appended to.
+//! Appending and rewriting take the lock `.tuffous/todos.lock`, so no line is
+//! lost to a compaction. Archived and trashed todos are kept in files either way.
+
+use crate::{config::RepoConfig, crypt, fsck::LOST_DIR, index::Stamp, Todo, TodoInstance};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Seek, SeekFrom, Write},
+    str::FromStr,
+};
+
+/// The log of the working set, relative to the repo.
+pub(crate) const LOG_FILE: &str = ".tuffous/todos.jsonl";
+
+/// The file locked while the log is appended to or rewritten, relative to the repo.
+const LOCK_FILE: &str = ".tuffous/todos.lock";
+
+/// The log is compacted once it has this many lines more than twice its todos.
+const COMPACT_SLACK: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    /// A file per todo, `.tuffous/todos/{id}.json`.
+    #[default]
+    Files,
+    /// The log `.tuffous/todos.jsonl`.
+    Jsonl,
+}
+
+impl FromStr for Storage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "files" => Ok(Storage::Files),
+            "jsonl" => Ok(Storage::Jsonl),
+            _ => Err(format!("Unknown storage: {s}")),
+        }
+    }
+}
+
+impl Display for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Storage::Files => write!(f, "files"),
+            Storage::Jsonl => write!(f, "jsonl"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Removed {
+        removed: u64,
+    },
+    #[allow(dead_code)]
+    Generation {
+        generation: u64,
+    },
+    Todo(Box<Todo>),
+}
+
+#[derive(Serialize)]
+struct Tombstone {
+    removed: u64,
+}
+
+#[derive(Serialize)]
+struct Generation {
+    generation: u64,
+}
+
+/// How far the log has been read.
+#[derive(Default)]
+pub(crate) struct Log {
+    stamp: Option<Stamp>,
+    offset: u64,
+    lines: usize,
+    /// The first line, which changes whenever the log is rewritten.
+    head: Option<String>,
+}
+
+/// A line of the log that is neither a todo nor a tombstone.
+pub(crate) struct BadLine {
+    pub number: usize,
+    pub text: String,
+    pub error: String,
+    /// Whether the line is sealed with a key that wasn't unlocked, rather than invalid.
+    pub sealed: bool,
+}
+
+/// What the lines of a log say about the todos.
+#[derive(Default)]
+pub(crate) struct Replay {
+    /// The last version of every todo, or `None` if it was removed.
+    pub todos: HashMap<u64, Option<Todo>>,
+    pub lines: usize,
+    pub invalid: Vec<BadLine>,
+    /// Whether lines that aren't sealed are read in encrypted repos too.
+    unsealed: bool,
+}
+
+impl Replay {
+    /// Read the whole lines. Returns how many bytes were read, without a
+    /// line still being written.
+    fn read(&mut self, log: &str, mut reader: impl BufRead) -> u64 {
+        let mut read = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match reader.read_line(&mut line) {
+                Ok(n) if n > 0 && line.ends_with('\n') => read += n as u64,
+                _ => return read,
+            }
+            self.lines += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let opened = match self.unsealed {
+                true => crypt::open_unsealed(log, line.trim_end()),
+                false => crypt::open(log, line.trim_end()),
+            };
+            let parsed = opened.and_then(|line| {
+                serde_json::from_str::<Line>(&line).map_err(|err| err.to_string())
+            });
+            match parsed {
+                Ok(Line::Removed { removed }) => {
+                    self.todos.insert(removed, None);
+                }
+                Ok(Line::Todo(todo)) => {
+                    self.todos.insert(todo.id(), Some(*todo));
+                }
+                Ok(Line::Generation { .. }) => (),
+                Err(error) => self.invalid.push(BadLine {
+                    number: self.lines,
+                    text: line.trim_end().to_owned(),
+                    error,
+                    sealed: !crypt::has_key(log, line.trim_end()),
+                }),
+            }
+        }
+    }
+
+    /// Replay the log of the repo at the path.
+    pub fn of(path: &str) -> Result<Replay, String> {
+        Replay::default().replay_log(path)
+    }
+
+    /// Replay the log of the repo at the path, with the lines left unsealed
+    /// in an encrypted repo, for fsck.
+    pub fn of_unsealed(path: &str) -> Result<Replay, String> {
+        Replay {
+            unsealed: true,
+            ..Replay::default()
+        }
+        .replay_log(path)
+    }
+
+    fn replay_log(mut self, path: &str) -> Result<Replay, String> {
+        let file = File::open(log_path(path)).map_err(|err| err.to_string())?;
+        self.read(&log_path(path), BufReader::new(file));
+        Ok(self)
+    }
+
+    /// The todos left, in the order of the repo.
+    pub fn left(self) -> Vec<Todo> {
+        let mut todos: Vec<Todo> = self.todos.into_values().flatten().collect();
+        todos.sort_by_key(|todo| (todo.creation_date, todo.id()));
+        todos
+    }
+}
+
+impl TodoInstance {
+    pub fn storage(&self) -> Storage {
+        self.storage
+    }
+
+    /// The lines of the log read or written so far.
+    pub fn log_lines(&self) -> usize {
+        self.log.lines
+    }
+
+    /// The todos of the lines added to the log since it was last read, or of
+    /// the whole log if it was rewritten, and the ids of the todos removed.
+    pub(crate) fn read_log(&mut self) -> (Vec<(Todo, Stamp)>, HashSet<u64>) {
+        let file = File::open(log_path(&self.path)).ok();
+        let Some((mut file, stamp)) = file.and_then(|file| {
+            let stamp = Stamp::of(&file.metadata().ok()?)?;
+            Some((file, stamp))
+        }) else {
+            self.log = Log::default();
+            return (Vec::new(), self.stamps.keys().copied().collect());
+        };
+        if self.log.stamp == Some(stamp) {
+            return (Vec::new(), HashSet::new());
+        }
+
+        // A log compacted and appended to past the offset is as long as an
+        // appended one, but starts with another generation
+        let head = first_line(&file);
+        let appended = self.log.offset > 0
+            && stamp.len >= self.log.offset
+            && head.is_some()
+            && head == self.log.head;
+        if !appended {
+            self.log = Log::default();
+        }
+        if file.seek(SeekFrom::Start(self.log.offset)).is_err() {
+            return (Vec::new(), HashSet::new());
+        }
+        let mut replay = Replay::default();
+        self.log.offset += replay.read(&log_path(&self.path), BufReader::new(file));
+        self.log.lines += replay.lines;
+        self.log.stamp = Some(stamp);
+        self.log.head = head;
+
+        let mut removed: HashSet<u64> = if appended {
+            HashSet::new()
+        } else {
+            self.stamps.keys().copied().collect()
+        };
+        let mut read = Vec::new();
+        for (id, todo) in replay.todos {
+            match todo {
+                Some(todo) => {
+                    removed.remove(&id);
+                    read.push((todo, stamp));
+                }
+                None => {
+                    removed.insert(id);
+                }
+            }
+        }
+        (read, removed)
+    }
+
+    /// Append the lines to the log, keeping up with it if nothing else was
+    /// written to it since it was read. Returns the stamp of the log after.
+    pub(crate) fn append(&mut self, lines: &[String]) -> Option<Stamp> {
+        let path = log_path(&self.path);
+        let _lock = lock(&self.path).ok()?;
+        let before = Stamp::read(&path);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .ok()?;
+        file.write_all(format!("{}\n", lines.join("\n")).as_bytes())
+            .ok()?;
+        let after = Stamp::read(&path)?;
+        if before == self.log.stamp {
+            self.log = Log {
+                stamp: Some(after),
+                offset: after.len,
+                lines: self.log.lines + lines.len(),
+                head: self.log.head.take().or_else(|| lines.first().cloned()),
+            };
+        }
+        Some(after)
+    }
+
+    /// Drop the todos from where the working set is kept. Todos already
+    /// missing from it count as dropped.
+    pub(crate) fn unstore(&mut self, ids: &[u64]) -> Result<(), String> {
+        match self.storage {
+            Storage::Files => {
+                for id in ids {
+                    match fs::remove_file(format!("{}/.tuffous/todos/{id}.json", self.path)) {
+                        Err(err) if err.kind() != ErrorKind::NotFound => {
+                            return Err(err.to_string())
+                        }
+                        _ => (),
+                    }
+                }
+                Ok(())
+            }
+            Storage::Jsonl if !ids.is_empty() => {
+                let lines = ids
+                    .iter()
+                    .map(|id| {
+                        crypt::seal(
+                            log_path(&self.path),
+                            serde_json::to_string(&Tombstone { removed: *id }).unwrap(),
+                        )
+                    })
+                    .collect::<Result<Vec<String>, String>>()?;
+                self.append(&lines)
+                    .map(|_| ())
+                    .ok_or_else(|| String::from("Can't append to the log"))
+            }
+            Storage::Jsonl => Ok(()),
+        }
+    }
+
+    /// Rewrite the log with a line per todo left.
+    /// Returns the number of lines before and after.
+    pub fn compact(&mut self) -> Result<(usize, usize), String> {
+        let _lock = lock(&self.path)?;
+        let before = Stamp::read(log_path(&self.path));
+        let result = compact_locked(&self.path);
+        let file = File::open(log_path(&self.path)).ok();
+        self.log = match (&result, before == self.log.stamp, file) {
+            (Ok((_, left)), true, Some(file)) => {
+                match file.metadata().ok().and_then(|m| Stamp::of(&m)) {
+                    Some(stamp) => Log {
+                        stamp: Some(stamp),
+                        offset: stamp.len,
+                        lines: *left,
+                        head: first_line(&file),
+                    },
+                    None => Log::default(),
+                }
+            }
+            // Read it again from the start the next time
+            _ => Log::default(),
+        };
+        result
+    }
+
+    /// Compact the log once most of its lines are outdated.
+    pub(crate) fn compact_if_needed(&mut self) {
+        if self.storage == Storage::Jsonl && self.log.lines > self.todos.len() * 2 + COMPACT_SLACK {
+            let _ = self.compact();
+        }
+    }
+}
+
+/// Rewrite the log of the repo at the path with a line per todo left.
+/// Returns the number of lines before and after.
+///
+/// Logs with lines that are neither todos nor tombstones are left as they
+/// are, since those lines can only be dropped by fsck.
+pub fn compact(path: &str) -> Result<(usize, usize), String> {
+    let _lock = lock(path)?;
+    compact_locked(path)
+}
+
+fn compact_locked(path: &str) -> Result<(usize, usize), String> {
+    let replay = Replay::of(path)?;
+    if !replay.invalid.is_empty() {
+        return Err(format!(
+            "{} line(s) of the log can't be read, check the repo with fsck",
+            replay.invalid.len()
+        ));
+    }
+    let lines = replay.lines;
+    let todos = replay.left();
+    write_log(path, &todos.iter().collect::<Vec<_>>())?;
+    // A line per todo after the generation
+    Ok((lines, todos.len() + 1))
+}
+
+/// Compact the log of the repo at the path, dropping the lines that are
+/// neither todos nor tombstones after adding them to `.tuffous/lost/todos.jsonl`.
+/// Returns how many lines were dropped.
+///
+/// Lines sealed with a key that wasn't unlocked are never dropped, and
+/// lines left unsealed in an encrypted repo are sealed.
+pub(crate) fn drop_invalid(path: &str) -> Result<usize, String> {
+    let _lock = lock(path)?;
+    let replay = Replay::of_unsealed(path)?;
+    if replay.invalid.iter().any(|line| line.sealed) {
+        return Err(String::from(
+            "lines of the log are encrypted with a key that wasn't unlocked",
+        ));
+    }
+    let dropped = replay.invalid.len();
+    if dropped > 0 {
+        let lost = format!("{path}/{LOST_DIR}");
+        fs::create_dir_all(&lost).map_err(|err| err.to_string())?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("{lost}/todos.jsonl"))
+            .map_err(|err| err.to_string())?;
+        for line in &replay.invalid {
+            writeln!(file, "{}", line.text).map_err(|err| err.to_string())?;
+        }
+        file.sync_all().map_err(|err| err.to_string())?;
+    }
+    let todos = replay.left();
+    write_log(path, &todos.iter().collect::<Vec<_>>())?;
+    Ok(dropped)
+}
+
+/// Move the todos of the repo at the path to the storage.
+/// Returns how many todos were moved.
+pub fn convert(path: &str, to: Storage) -> Result<usize, String> {
+    let mut config = RepoConfig::get(path);
+    if config.storage == to {
+        return Err(format!("The repo already uses {to}"));
+    }
+    let mut instance = TodoInstance::create(path);
+    instance.read_all();
+    let todos: Vec<&Todo> = instance.todos.iter().collect();
+    // The details of todos from the index are read from the files removed below
+    for todo in &todos {
+        todo.metadata.details();
+    }
+
+    match to {
+        Storage::Files => {
+            fs::create_dir_all(format!("{path}/.tuffous/todos")).map_err(|err| err.to_string())?;
+            for todo in &todos {
+                todo.write_to_file(path)?;
+            }
+        }
+        Storage::Jsonl => {
+            let _lock = lock(path)?;
+            write_log(path, &todos)?
+        }
+    }
+    // The old copies are only removed once the new ones are read back and used
+    let mut stored = match to {
+        Storage::Files => todos
+            .iter()
+            .filter_map(|todo| {
+                Todo::read_from_file(format!("{path}/.tuffous/todos/{}.json", todo.id()))
+            })
+            .map(|todo| (todo.id(), Some(todo)))
+            .collect(),
+        Storage::Jsonl => Replay::of(path)?.todos,
+    };
+    for todo in &todos {
+        let same = stored.remove(&todo.id()).flatten().is_some_and(|stored| {
+            serde_json::to_value(&stored).ok() == serde_json::to_value(todo).ok()
+        });
+        if !same {
+            return Err(format!(
+                "The todo {} wasn't stored as it is, the repo still uses {}",
+                todo.id(),
+                config.storage
+            ));
+        }
+    }
+    config.storage = to;
+    config.write(path).map_err(|err| err.to_string())?;
+    match to {
+        Storage::Files => {
+            let _ = fs::remove_file(log_path(path));
+        }
+        Storage::Jsonl => {
+            for todo in &todos {
+                let _ = fs::remove_file(format!("{path}/.tuffous/todos/{}.json", todo.id()));
+            }
+            let _ = fs::remove_file(crate::index::Index::path(path));
+        }
+    }
+    Ok(todos.len())
+}
+
+/// Add a line with the todo to the log of the repo at the path.
+pub(crate) fn append_todo(path: &str, todo: &Todo) -> Result<(), String> {
+    let _lock = lock(path)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path(path))
+        .map_err(|err| err.to_string())?;
+    writeln!(
+        file,
+        "{}",
+        crypt::seal(log_path(path), serde_json::to_string(todo).unwrap())?
+    )
+    .map_err(|err| err.to_string())
+}
+
+/// Write the log through a temporary file, so it's never left half written.
+/// The lock must be held.
+fn write_log(path: &str, todos: &[&Todo]) -> Result<(), String> {
+    let generation = Generation {
+        generation: getrandom::u64().map_err(|err| err.to_string())?,
+    };
+    let mut text = crypt::seal(log_path(path), serde_json::to_string(&generation).unwrap())?;
+    text.push('\n');
+    for todo in todos {
+        text.push_str(&crypt::seal(
+            log_path(path),
+            serde_json::to_string(todo).unwrap(),
+        )?);
+        text.push('\n');
+    }
+    let temp = format!("{}.tmp", log_path(path));
+    fs::write(&temp, text).map_err(|err| err.to_string())?;
+    fs::rename(temp, log_path(path)).map_err(|err| err.to_string())
+}
+
+pub(crate) fn log_path(path: &str) -> String {
+    format!("{path}/{LOG_FILE}")
+}
+
+/// Take the lock of the log of the repo at the path, waiting for other
+/// processes to release it. It is released when the file is dropped.
+pub(crate) fn lock(path: &str) -> Result<File, String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(format!("{path}/{LOCK_FILE}"))
+        .map_err(|err| err.to_string())?;
+    file.lock().map_err(|err| err.to_string())?;
+    Ok(file)
+}
+
+fn first_line(file: &File) -> Option<String> {
+    let mut line = String::new();
+    BufReader::new(file).read_line(&mut line).ok()?;
+    Some(line).filter(|line| line.ends_with('\n'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{temp_repo, CheckItem};
+
+    /// A todo with every field set.
+    fn full_todo(name: &str) -> Todo {
+        let mut todo = Todo::create(String::from(name));
+        todo.metadata.set_details(format!("details of {name}"));
+        todo.metadata.checklist = vec![CheckItem {
+            name: String::from("step"),
+            checked: true,
+        }];
+        todo.tags = vec![String::from("work")];
+        todo.weight = 3;
+        todo.estimate = Some(90);
+        todo.time = Some(chrono::Local::now().date_naive());
+        todo.order.insert(String::from("all"), 7);
+        todo.set_completed(true);
+        todo
+    }
+
+    fn snapshot(path: &str) -> Vec<String> {
+        let mut instance = TodoInstance::create(path);
+        instance.read_all();
+        instance
+            .todos
+            .iter()
+            .map(|todo| serde_json::to_string(todo).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn convert_round_trip() {
+        let path = temp_repo("convert");
+        let mut instance = TodoInstance::create(&path);
+        let mut child = full_todo("child");
+        let father = full_todo("father");
+        child.dependents.push(father.id());
+        instance.insert(father);
+        instance.insert(child);
+        instance.write_all();
+        let before = snapshot(&path);
+        // Read again from the index, with the details left in the files
+        assert_eq!(snapshot(&path), before);
+
+        assert_eq!(convert(&path, Storage::Jsonl), Ok(2));
+        assert_eq!(RepoConfig::get(&path).storage, Storage::Jsonl);
+        assert!(fs::read_dir(format!("{path}/.tuffous/todos"))
+            .unwrap()
+            .next()
+            .is_none());
+        assert_eq!(snapshot(&path), before);
+        assert!(convert(&path, Storage::Jsonl).is_err());
+
+        assert_eq!(convert(&path, Storage::Files), Ok(2));
+        assert!(fs::metadata(log_path(&path)).is_err());
+        assert_eq!(snapshot(&path), before);
+        assert!(before.iter().all(|todo| todo.contains("details of")));
+    }
+
+    fn jsonl_repo(name: &str) -> String {
+        let path = temp_repo(name);
+        let mut config = RepoConfig::get(&path);
+        config.storage = Storage::Jsonl;
+        config.write(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn tombstone_removes() {
+        let path = jsonl_repo("tombstone");
+        let mut instance = TodoInstance::create(&path);
+        let kept = Todo::create(String::from("kept"));
+        let removed = Todo::create(String::from("removed"));
+        let (kept, removed) = (kept.id(), {
+            let id = removed.id();
+            instance.insert(kept);
+            instance.insert(removed);
+            id
+        });
+        instance.write_all();
+        instance.remove(removed).unwrap();
+        instance.write_all();
+
+        let replay = Replay::of(&path).unwrap();
+        assert_eq!(replay.lines, 3);
+        assert!(replay.todos[&removed].is_none());
+        let mut instance = TodoInstance::create(&path);
+        instance.read_all();
+        assert!(instance.get(removed).is_none());
+        assert!(instance.get(kept).is_some());
+        assert_eq!(instance.todos.len(), 1);
+    }
+
+    #[test]
+    fn compact_keeps_unreadable_lines() {
+        let path = jsonl_repo("compact");
+        let mut instance = TodoInstance::create(&path);
+        let todo = Todo::create(String::from("first"));
+        let id = todo.id();
+        instance.insert(todo);
+        instance.write_all();
+        instance.get_mut(id).unwrap().metadata.name = String::from("second");
+        instance.write_all();
+        instance.append(&[String::from("{\"truncated\":")]);
+        let log = fs::read_to_string(log_path(&path)).unwrap();
+
+        assert!(compact(&path).is_err());
+        assert_eq!(fs::read_to_string(log_path(&path)).unwrap(), log);
+
+        assert_eq!(drop_invalid(&path), Ok(1));
+        let lost = fs::read_to_string(format!("{path}/{LOST_DIR}/todos.jsonl")).unwrap();
+        assert_eq!(lost, "{\"truncated\":\n");
+        let replay = Replay::of(&path).unwrap();
+        assert_eq!(replay.lines, 2);
+        assert_eq!(replay.left()[0].metadata.name, "second");
+        assert_eq!(compact(&path), Ok((2, 2)));
+    }
+
+    #[test]
+    fn rewritten_logs_are_read_again() {
+        let path = jsonl_repo("rewritten");
+        let mut reader = TodoInstance::create(&path);
+        let kept = Todo::create(String::from("kept"));
+        let removed = Todo::create(String::from("removed"));
+        let (kept, removed) = (kept.id(), {
+            let id = removed.id();
+            reader.insert(kept);
+            reader.insert(removed);
+            id
+        });
+        reader.write_all();
+
+        let mut writer = TodoInstance::create(&path);
+        writer.read_all();
+        writer.remove(removed).unwrap();
+        writer.write_all();
+        writer.compact().unwrap();
+        // Append to the compacted log until it's longer than what was read
+        while fs::metadata(log_path(&path)).unwrap().len() <= reader.log.offset {
+            writer.get_mut(kept).unwrap().weight += 1;
+            writer.write_all();
+        }
+
+        reader.read_all();
+        assert!(reader.get(removed).is_none());
+        assert_eq!(
+            reader.get(kept).unwrap().weight,
+            writer.get(kept).unwrap().weight
+        );
+        assert_eq!(reader.todos.len(), 1);
+    }
+}
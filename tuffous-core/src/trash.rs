@@ -0,0 +1,216 @@
+//! Removed todos are kept in `.tuffous/trash` until they are restored or purged.
+
+use crate::{
+    config::RepoConfig,
+    crypt,
+    id::{hex_id, MIN_SHORT_ID_LEN},
+    Todo, TodoInstance,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// A removed todo with the links it had.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Trashed {
+    pub todo: Todo,
+    pub deleted: NaiveDateTime,
+    /// The todos that had it as a father.
+    pub children: Vec<u64>,
+}
+
+impl TodoInstance {
+    /// Move the todo to the trash, recording its children.
+    pub(crate) fn trash(&self, id: u64) -> Result<(), String> {
+        let Some(todo) = self.get(id) else {
+            return Ok(());
+        };
+        let trashed = Trashed {
+            todo: todo.clone(),
+            deleted: Utc::now().naive_utc(),
+            children: self.children_once(id),
+        };
+        fs::create_dir_all(self.trash_dir()).map_err(|err| err.to_string())?;
+        crypt::write(
+            format!("{}/{id}.json", self.trash_dir()),
+            serde_json::to_string(&trashed).unwrap(),
+        )
+    }
+
+    /// Drop the trashed copy of a todo that couldn't be removed.
+    pub(crate) fn untrash(&self, id: u64) {
+        let _ = fs::remove_file(format!("{}/{id}.json", self.trash_dir()));
+    }
+
+    /// The trashed todos, the last removed first.
+    pub fn trashed(&self) -> Vec<Trashed> {
+        let Ok(dir) = fs::read_dir(self.trash_dir()) else {
+            return Vec::new();
+        };
+        let mut trashed: Vec<Trashed> = dir
+            .flatten()
+            .filter_map(|file| crypt::read(file.path()))
+            .filter_map(|s| serde_json::from_str::<Trashed>(&s).ok())
+            .collect();
+        trashed.sort_by_key(|t| std::cmp::Reverse(t.deleted));
+        trashed
+    }
+
+    /// Bring the trashed todo back and link it to its children again.
+    /// The todos are written to the repo.
+    pub fn restore(&mut self, id: u64) -> bool {
+        let path = format!("{}/{id}.json", self.trash_dir());
+        let Some(trashed) =
+            crypt::read(&path).and_then(|s| serde_json::from_str::<Trashed>(&s).ok())
+        else {
+            return false;
+        };
+
+        if self.get(id).is_none() {
+            self.insert(trashed.todo);
+            self.todos.sort_by_key(|todo| (todo.creation_date, todo.id));
+        }
+        for child in trashed.children {
+            if let Some(todo) = self.get_mut(child) {
+                if !todo.dependents.contains(&id) {
+                    todo.dependents.push(id);
+                }
+            }
+        }
+        // Fathers removed meanwhile are dropped
+        self.refresh();
+        self.write_all();
+        let _ = fs::remove_file(path);
+        true
+    }
+
+    /// Delete the trashed todos removed before the time, or all of them.
+    /// Returns how many were deleted.
+    pub fn empty_trash(&self, before: Option<NaiveDateTime>) -> usize {
+        let mut count = 0;
+        for trashed in self.trashed() {
+            if before.is_none_or(|time| trashed.deleted < time)
+                && fs::remove_file(format!("{}/{}.json", self.trash_dir(), trashed.todo.id()))
+                    .is_ok()
+            {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Delete the todos trashed longer ago than the `trash_purge_days` of the repo.
+    pub fn purge_trash_by_policy(&self) -> usize {
+        let Some(days) = RepoConfig::get(&self.path).trash_purge_days else {
+            return 0;
+        };
+        self.empty_trash(Some(Utc::now().naive_utc() - Duration::days(days as i64)))
+    }
+
+    /// The trashed todos with the id, id prefix or name.
+    pub fn find_trashed(&self, reference: &str) -> Vec<u64> {
+        let reference = reference.to_lowercase();
+        self.trashed()
+            .iter()
+            .map(|trashed| &trashed.todo)
+            .filter(|todo| {
+                reference == todo.id().to_string()
+                    || (reference.len() >= MIN_SHORT_ID_LEN
+                        && hex_id(todo.id()).starts_with(&reference))
+                    || reference == todo.metadata.name.to_lowercase()
+            })
+            .map(|todo| todo.id())
+            .collect()
+    }
+
+    fn trash_dir(&self) -> String {
+        format!("{}/.tuffous/trash", self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_repo;
+
+    #[test]
+    fn restore_relinks_children() {
+        let path = temp_repo("trash");
+        let mut instance = TodoInstance::create(&path);
+        let father = Todo::create(String::from("Move"));
+        let father_id = father.id();
+        let mut child = Todo::create(String::from("Pack"));
+        child.dependents.push(father_id);
+        let child_id = child.id();
+        instance.insert(father);
+        instance.insert(child);
+        instance.write_all();
+
+        instance.remove(father_id).unwrap();
+        instance.write_all();
+        assert!(instance.get(father_id).is_none());
+        assert!(instance.get(child_id).unwrap().dependents.is_empty());
+        let trashed = instance.trashed();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].children, [child_id]);
+
+        let mut instance = TodoInstance::create(&path);
+        instance.read_all();
+        assert_eq!(instance.find_trashed("move"), [father_id]);
+        assert!(instance.restore(father_id));
+        assert!(instance.trashed().is_empty());
+        assert!(!instance.restore(father_id));
+
+        let mut instance = TodoInstance::create(&path);
+        instance.read_all();
+        assert_eq!(instance.todos.len(), 2);
+        assert_eq!(instance.get(child_id).unwrap().dependents, [father_id]);
+    }
+
+    #[test]
+    fn children_restored_first_are_relinked() {
+        let path = temp_repo("trash-children");
+        let mut instance = TodoInstance::create(&path);
+        let father = Todo::create(String::from("Move"));
+        let father_id = father.id();
+        let mut child = Todo::create(String::from("Pack"));
+        child.dependents.push(father_id);
+        let child_id = child.id();
+        instance.insert(father);
+        instance.insert(child);
+        instance.write_all();
+
+        instance.remove(father_id).unwrap();
+        instance.remove(child_id).unwrap();
+        instance.write_all();
+        // The child was trashed without its father, who takes it back
+        assert!(instance.restore(child_id));
+        assert!(instance.get(child_id).unwrap().dependents.is_empty());
+        assert!(instance.restore(father_id));
+        assert_eq!(instance.get(child_id).unwrap().dependents, [father_id]);
+    }
+
+    #[test]
+    fn empty_trash() {
+        let path = temp_repo("trash-empty");
+        let mut instance = TodoInstance::create(&path);
+        let ids: Vec<u64> = ["Move", "Pack"]
+            .map(|name| {
+                let todo = Todo::create(String::from(name));
+                let id = todo.id();
+                instance.insert(todo);
+                id
+            })
+            .into();
+        instance.write_all();
+        for id in &ids {
+            instance.remove(*id).unwrap();
+        }
+
+        let long_ago = Utc::now().naive_utc() - Duration::days(1);
+        assert_eq!(instance.empty_trash(Some(long_ago)), 0);
+        assert_eq!(instance.purge_trash_by_policy(), 0);
+        assert_eq!(instance.empty_trash(None), 2);
+        assert!(instance.trashed().is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::icons::IconSet;
 use chrono::{Datelike, Local, NaiveDate, NaiveDateTime};
 
 pub fn remove_from_vec<T: PartialEq>(vec: &mut Vec<T>, instance: &T) {
@@ -52,11 +53,11 @@ pub fn parse_date_and_time(string: &str) -> Option<NaiveDateTime> {
     let temp_str = string.replace('/', "-");
     let now = Local::now();
 
-    for variant in vec![
+    for variant in [
         format!("{}-{}", now.year(), temp_str),
         format!("{}-{}-00:00:00", now.year(), temp_str),
         format!("{}-{}:00", now.year(), temp_str),
-        format!("{}", temp_str),
+        temp_str.to_string(),
         format!("{}-00:00:00", temp_str),
         format!("{}:00", temp_str),
     ] {
@@ -76,10 +77,7 @@ pub fn parse_date(string: &str) -> Option<NaiveDate> {
     let temp_str = string.replace('/', "-");
     let now = Local::now();
 
-    for variant in vec![
-        format!("{}-{}", now.year(), temp_str),
-        format!("{}", temp_str),
-    ] {
+    for variant in [format!("{}-{}", now.year(), temp_str), temp_str.to_string()] {
         if let Ok(r) = NaiveDate::parse_from_str(&variant, "%Y-%m-%d") {
             return Some(r);
         }
@@ -96,7 +94,7 @@ pub fn join_str_with(vec: Vec<&str>, with: &str) -> String {
     if vec.is_empty() {
         return String::new();
     }
-    let mut string = vec.get(0).unwrap().to_string();
+    let mut string = vec.first().unwrap().to_string();
     for i in 1..vec.len() {
         string.push_str(with);
         string.push_str(vec.get(i).unwrap())
@@ -105,25 +103,5 @@ pub fn join_str_with(vec: Vec<&str>, with: &str) -> String {
 }
 
 pub fn progression_char(percent: u32) -> char {
-    if percent == 0 {
-        '󰝦'
-    } else if percent > 0 && percent <= 13 {
-        '󰪞'
-    } else if percent > 13 && percent <= 25 {
-        '󰪟'
-    } else if percent > 25 && percent <= 38 {
-        '󰪠'
-    } else if percent > 38 && percent <= 50 {
-        '󰪡'
-    } else if percent > 50 && percent <= 63 {
-        '󰪢'
-    } else if percent > 63 && percent <= 75 {
-        '󰪣'
-    } else if percent > 75 && percent <= 88 {
-        '󰪤'
-    } else if percent > 88 && percent <= 100 {
-        '󰪥'
-    } else {
-        unreachable!()
-    }
+    IconSet::Nerd.progress(percent).chars().next().unwrap()
 }
@@ -4,17 +4,17 @@ use iced::{
     Font, Theme,
 };
 use once_cell::sync::Lazy;
+use tuffous_core::palette::{Palette, Rgb};
 
 const ICONS: Font = Font::with_name("Symbols Nerd Font");
 
 pub static FONT: Lazy<Option<Font>> = Lazy::new(|| {
     let config = super::config::ConfigInstance::get();
 
-    if let Some(font) = config.fonts.get(0) {
-        Some(Font::with_name(Box::leak(Box::new(font.to_string()))))
-    } else {
-        None
-    }
+    config
+        .fonts
+        .first()
+        .map(|font| Font::with_name(Box::leak(Box::new(font.to_string()))))
 });
 
 pub fn icon(unicode: char) -> Text<'static> {
@@ -54,21 +54,25 @@ impl StyleSheet {
     pub fn from_theme(theme: &iced::Theme) -> StyleSheet {
         match theme {
             Theme::Dark => StyleSheet {
-                flag: iced::Color::from_rgb(0.843, 0.251, 0.267),
-                star: iced::Color::from_rgb(1.0, 0.843, 0.0),
                 checkbox: theme.palette().primary,
-                gray: iced::Color::from_rgb(0.5, 0.5, 0.5),
-                green: iced::Color::from_rgb(0.196, 0.8039, 0.196),
-                blue_green: iced::Color::from_rgb(0.0, 0.545, 0.545),
-            },
-            _ => StyleSheet {
-                flag: iced::Color::from_rgb(0.86, 0.078, 0.235),
-                star: iced::Color::from_rgb(1.0, 0.843, 0.0),
-                checkbox: iced::Color::from_rgb(0.07, 0.23, 0.591),
-                gray: iced::Color::from_rgb(0.5, 0.5, 0.5),
-                green: iced::Color::from_rgb(0.086, 0.596, 0.1686),
-                blue_green: iced::Color::from_rgb(0.0, 0.502, 0.502),
+                ..Self::from_palette(&Palette::dark())
             },
+            _ => Self::from_palette(&Palette::light()),
         }
     }
+
+    pub fn from_palette(palette: &Palette) -> StyleSheet {
+        StyleSheet {
+            flag: color(palette.flag),
+            star: color(palette.star),
+            checkbox: color(palette.checkbox),
+            gray: color(palette.gray),
+            green: color(palette.green),
+            blue_green: color(palette.blue_green),
+        }
+    }
+}
+
+pub fn color(rgb: Rgb) -> iced::Color {
+    iced::Color::from_rgb8(rgb.0, rgb.1, rgb.2)
 }
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::{fs, path::Path};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default)]
 pub struct ConfigInstance {
     pub dark_theme: bool,
     pub fonts: Vec<String>,
@@ -9,11 +9,7 @@ pub struct ConfigInstance {
 
 impl ConfigInstance {
     pub fn get() -> Self {
-        if let Some(c) = Self::read_from_file(Self::path()) {
-            c
-        } else {
-            Self::default()
-        }
+        Self::read_from_file(Self::path()).unwrap_or_default()
     }
 
     pub fn read_from_file<P: AsRef<Path>>(path: P) -> Option<Self> {
@@ -22,7 +18,7 @@ impl ConfigInstance {
                 return Some(x);
             }
         };
-        return None;
+        None
     }
 
     pub fn write(&self) {
@@ -33,12 +29,3 @@ impl ConfigInstance {
         String::from("./.tuffous/config_gui.json")
     }
 }
-
-impl Default for ConfigInstance {
-    fn default() -> Self {
-        Self {
-            dark_theme: false,
-            fonts: Vec::new(),
-        }
-    }
-}
@@ -1,7 +1,7 @@
 mod appearance;
 mod config;
 
-use chrono::{Datelike, Local};
+use chrono::{Datelike, Local, TimeZone};
 use iced::{
     alignment, executor, theme,
     widget::{
@@ -10,18 +10,41 @@ use iced::{
     },
     window, Application, Color, Element, Length, Renderer, Settings, Theme,
 };
-use tuffous_core::{util, Todo, TodoInstance};
+use std::collections::{HashMap, HashSet};
+use tuffous_core::{
+    agenda,
+    config::{Perspective, RepoConfig},
+    crypt,
+    order::{OrderScope, SortKey},
+    query::Query,
+    search::{split_ranges, Hit, Matcher},
+    trash::Trashed,
+    util, CheckItem, Todo, TodoInstance,
+};
 
 struct App {
     pub instance: TodoInstance,
-    pub states: Vec<TodoState>,
+    pub states: HashMap<u64, TodoState>,
     pub dep_selection: Option<(u64, Vec<u64>)>,
     pub range: Vec<u64>,
     pub complete_filter: TodoCompleteFilter,
     pub view: TodoView,
     pub search_cache: String,
     pub search: bool,
+    /// Todos matching the words of the search, by id.
+    pub hits: HashMap<u64, Hit>,
+    /// Whether archived todos are loaded into the Logbook.
+    pub show_archived: bool,
+    /// The removed todos, loaded when the Trash is shown.
+    pub trash: Vec<Trashed>,
     pub config: config::ConfigInstance,
+    pub repo_config: RepoConfig,
+    /// Whether the repo is encrypted and waits for its passphrase.
+    pub locked: bool,
+    pub passphrase: String,
+    pub unlock_error: Option<String>,
+    /// Why the last action failed, shown until the next one.
+    pub error: Option<String>,
 }
 
 fn main() -> iced::Result {
@@ -62,6 +85,8 @@ enum TodoView {
     Logbook,
     All,
     Project(u64),
+    Perspective(Perspective),
+    Trash,
 }
 
 impl TodoView {
@@ -78,6 +103,12 @@ impl TodoView {
                 instance.get(*id).unwrap().metadata.name.to_owned(),
                 style().gray,
             ),
+            TodoView::Perspective(perspective) => (
+                perspective.icon.unwrap_or('󰈲'),
+                perspective.name.to_owned(),
+                perspective.color.map_or(style().gray, appearance::color),
+            ),
+            TodoView::Trash => ('󰩹', String::from("Trash"), style().gray),
         }
     }
 
@@ -85,7 +116,7 @@ impl TodoView {
         &self,
         instance: &TodoInstance,
         complete: &TodoCompleteFilter,
-        keywords: &Vec<String>,
+        query: &Query,
     ) -> Vec<u64> {
         let get_relatives = |id: u64| {
             let mut vec = Vec::new();
@@ -98,30 +129,22 @@ impl TodoView {
             vec
         };
 
+        // The query of a perspective is parsed and resolved once for all the todos
+        let query = match self {
+            TodoView::Perspective(perspective) => match Query::parse(&perspective.query) {
+                Ok(own) => Query::And(Box::new(own), Box::new(query.clone())),
+                Err(_) => return Vec::new(),
+            },
+            _ => query.clone(),
+        }
+        .resolve(instance);
+
         let mut vec = Vec::new();
 
         for todo in &instance.todos {
             if complete.test(todo)
                 && self.test(todo.id(), instance)
-                && (keywords.is_empty() || {
-                    let mut b = true;
-                    for key in keywords {
-                        b = b
-                            && (todo.metadata.name.to_lowercase().contains(key)
-                                || todo.metadata.details.to_lowercase().contains(key)
-                                || {
-                                    let mut c = false;
-                                    for tag in &todo.tags {
-                                        if tag.to_lowercase().contains(key) {
-                                            c = true;
-                                            break;
-                                        }
-                                    }
-                                    c
-                                })
-                    }
-                    b
-                })
+                && query.matches(instance, todo.id())
             {
                 if !vec.contains(&todo.id()) {
                     vec.push(todo.id());
@@ -151,36 +174,25 @@ impl TodoView {
     pub fn test(&self, id: u64, instance: &TodoInstance) -> bool {
         let todo = instance.get(id).unwrap();
         match self {
-            TodoView::Today => {
-                if let Some(date) = todo.time {
-                    date.eq(&Local::now().date_naive())
-                } else if let Some(ddl) = todo.deadline {
-                    ddl.date() <= Local::now().date_naive()
-                } else {
-                    false
-                }
-            }
-            TodoView::Upcoming => {
-                if let Some(date) = todo.time {
-                    !date.eq(&Local::now().date_naive())
-                } else if let Some(ddl) = todo.deadline {
-                    Local::now().date_naive() < ddl.date()
-                } else {
-                    false
-                }
-            }
-            TodoView::Anytime => todo.time.is_none() && todo.deadline.is_none(),
+            TodoView::Today => agenda::is_today(todo, Local::now().date_naive()),
+            TodoView::Upcoming => agenda::is_upcoming(todo, Local::now().date_naive()),
+            TodoView::Anytime => agenda::is_anytime(todo),
             TodoView::Logbook => todo.completed,
             TodoView::All => true,
             TodoView::Project(project_id) => {
                 instance.children(*project_id).contains(&id) || &id == project_id
             }
+            // Matched by its query in `filter`
+            TodoView::Perspective(_) => true,
+            // Trashed todos aren't in the instance
+            TodoView::Trash => false,
         }
     }
 
     pub fn default_complete_filter(&self) -> TodoCompleteFilter {
         match self {
             Self::Logbook => TodoCompleteFilter::Completed,
+            Self::Perspective(perspective) if perspective.completed => TodoCompleteFilter::All,
             _ => TodoCompleteFilter::NotComplete,
         }
     }
@@ -201,6 +213,20 @@ impl TodoView {
         }
     }
 
+    /// The key of the view for manual ordering of its top-level todos.
+    pub fn order_key(&self) -> String {
+        match self {
+            TodoView::Today => String::from("today"),
+            TodoView::Upcoming => String::from("upcoming"),
+            TodoView::Anytime => String::from("anytime"),
+            TodoView::Logbook => String::from("logbook"),
+            TodoView::All => String::from("all"),
+            TodoView::Project(id) => format!("project:{id}"),
+            TodoView::Perspective(perspective) => format!("perspective:{}", perspective.name),
+            TodoView::Trash => String::from("trash"),
+        }
+    }
+
     pub fn allow_create_todo(&self) -> bool {
         matches!(
             self,
@@ -211,22 +237,21 @@ impl TodoView {
 
 impl App {
     pub fn state(&self, id: u64) -> Option<&TodoState> {
-        self.states.iter().find(|&state| state.id == id)
+        self.states.get(&id)
     }
 
     pub fn state_mut(&mut self, id: u64) -> Option<&mut TodoState> {
-        self.states.iter_mut().find(|state| state.id == id)
+        self.states.get_mut(&id)
     }
 
     pub fn refresh_states(&mut self) {
-        util::remove_from_vec_if(&mut self.states, &|state| {
-            !self.instance.todos().contains(&state.id)
-        });
+        let ids: HashSet<u64> = self.instance.todos().into_iter().collect();
+        self.states.retain(|id, _| ids.contains(id));
 
         for todo in &self.instance.todos {
-            if util::vec_none_match(&self.states, &|state| state.id == todo.id()) {
-                self.states.push(TodoState::new(todo));
-            }
+            self.states
+                .entry(todo.id())
+                .or_insert_with(|| TodoState::new(todo));
         }
     }
 
@@ -234,7 +259,11 @@ impl App {
         appearance::StyleSheet::from_theme(&self.theme())
     }
 
-    fn view_todos(&self) -> iced::Element<Message> {
+    fn view_todos(&self) -> iced::Element<'_, Message> {
+        if self.view == TodoView::Trash {
+            return self.view_trash();
+        }
+
         container(if self.range.is_empty() && !self.search {
             container(
                 appearance::icon(self.view.title(&self.instance, self.theme()).0)
@@ -264,7 +293,7 @@ impl App {
                         vec.push(
                             container(
                                 text_input("Search", &self.search_cache)
-                                    .on_input(|s| Message::CacheSearchContent(s))
+                                    .on_input(Message::CacheSearchContent)
                                     .width(360),
                             )
                             .center_x()
@@ -275,19 +304,19 @@ impl App {
 
                     vec.push(horizontal_space(35).into());
 
-                    for todo in &self.instance.todos {
-                        if todo.dependents.is_empty() && self.range.contains(&todo.id()) {
-                            for view in &mut self.state(todo.id()).unwrap().view(self) {
-                                let mut row_c: Vec<Element<'_, Message, Renderer>> = Vec::new();
-                                row_c.push(horizontal_space(view.0).into());
-                                row_c.append(&mut view.1);
-                                vec.push(
-                                    container(container(row(row_c)).max_width(1500))
-                                        .align_x(alignment::Horizontal::Center)
-                                        .width(Length::Fill)
-                                        .into(),
-                                );
-                            }
+                    let mut roots = self.root_todos();
+                    self.sort_by_relevance(&mut roots);
+                    for todo_id in roots {
+                        for view in &mut self.state(todo_id).unwrap().view(self) {
+                            let mut row_c: Vec<Element<'_, Message, Renderer>> = Vec::new();
+                            row_c.push(horizontal_space(view.0).into());
+                            row_c.append(&mut view.1);
+                            vec.push(
+                                container(container(row(row_c)).max_width(1500))
+                                    .align_x(alignment::Horizontal::Center)
+                                    .width(Length::Fill)
+                                    .into(),
+                            );
                         }
                     }
                     vec
@@ -302,7 +331,130 @@ impl App {
         .into()
     }
 
-    fn view_sidebar(&self) -> iced::Element<Message> {
+    /// Why the last action failed, if it did.
+    fn view_error(&self) -> Option<iced::Element<'_, Message>> {
+        let err = self.error.as_ref()?;
+        Some(
+            container(
+                text(err)
+                    .size(14)
+                    .style(theme::Text::Color(self.style_sheet().flag)),
+            )
+            .center_x()
+            .width(Length::Fill)
+            .into(),
+        )
+    }
+
+    /// Asks for the passphrase of the encrypted repo.
+    fn view_unlock(&self) -> iced::Element<'_, Message> {
+        let mut vec: Vec<Element<'_, Message, Renderer>> = vec![
+            appearance::icon('󰌾')
+                .style(theme::Text::Color(self.style_sheet().gray))
+                .size(80)
+                .width(Length::Fill)
+                .into(),
+            text_input("Passphrase", &self.passphrase)
+                .password()
+                .on_input(Message::CachePassphrase)
+                .on_submit(Message::Unlock)
+                .width(360)
+                .into(),
+            button(text("Unlock")).on_press(Message::Unlock).into(),
+        ];
+        if let Some(err) = &self.unlock_error {
+            vec.push(
+                text(err)
+                    .size(14)
+                    .style(theme::Text::Color(self.style_sheet().flag))
+                    .into(),
+            );
+        }
+
+        container(column(vec).spacing(15).align_items(iced::Alignment::Center))
+            .center_x()
+            .center_y()
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    /// Unlock the repo with the passphrase typed in, then read its todos.
+    fn unlock(&mut self) {
+        match crypt::unlock(self.instance.path(), &self.passphrase) {
+            Ok(()) => {
+                self.locked = false;
+                self.unlock_error = None;
+                self.load();
+            }
+            Err(err) => self.unlock_error = Some(err),
+        }
+        self.passphrase = String::new();
+    }
+
+    fn load(&mut self) {
+        self.instance.read_all();
+        self.instance.refresh();
+        self.refresh_states();
+        self.refresh_range();
+    }
+
+    /// The removed todos, the last removed first, each with a restore button.
+    fn view_trash(&self) -> iced::Element<'_, Message> {
+        if self.trash.is_empty() {
+            return container(
+                appearance::icon('󰩹')
+                    .style(theme::Text::Color(self.style_sheet().gray))
+                    .size(80)
+                    .width(Length::Fill),
+            )
+            .center_x()
+            .center_y()
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into();
+        }
+
+        let mut vec: Vec<Element<'_, Message, Renderer>> = vec![horizontal_space(35).into()];
+        for trashed in &self.trash {
+            let deleted = Local.from_utc_datetime(&trashed.deleted);
+            vec.push(
+                container(
+                    container(row!(
+                        horizontal_space(22.5),
+                        button(
+                            appearance::icon('󰦛')
+                                .style(theme::Text::Color(self.style_sheet().gray))
+                        )
+                        .style(theme::Button::Text)
+                        .on_press(Message::RestoreTodo(trashed.todo.id())),
+                        text(&trashed.todo.metadata.name),
+                        horizontal_space(Length::Fill),
+                        text(format!(
+                            "removed {} {}",
+                            util::month_str(deleted.month()),
+                            deleted.day()
+                        ))
+                        .size(14)
+                        .style(theme::Text::Color(self.style_sheet().gray)),
+                        horizontal_space(22.5),
+                    ))
+                    .max_width(1500),
+                )
+                .align_x(alignment::Horizontal::Center)
+                .width(Length::Fill)
+                .into(),
+            );
+        }
+
+        container(scrollable(column(vec).spacing(7.5)))
+            .center_x()
+            .height(Length::Fill)
+            .width(Length::Fill)
+            .into()
+    }
+
+    fn view_sidebar(&self) -> iced::Element<'_, Message> {
         let height = 30;
         let mut self_vec: Vec<Element<'_, Message, Renderer>> = Vec::new();
 
@@ -341,6 +493,14 @@ impl App {
         self_vec.push(view_button(TodoView::Anytime));
         self_vec.push(view_button(TodoView::Logbook));
         self_vec.push(view_button(TodoView::All));
+        self_vec.push(view_button(TodoView::Trash));
+
+        if !self.repo_config.perspectives.is_empty() {
+            self_vec.push(vertical_space(15).into());
+            for perspective in &self.repo_config.perspectives {
+                self_vec.push(view_button(TodoView::Perspective(perspective.clone())));
+            }
+        }
 
         {
             let mut pinned = Vec::new();
@@ -363,8 +523,8 @@ impl App {
 
         self_vec.push(vertical_space(Length::Fill).into());
 
-        let mut controls_vec: Vec<Element<'_, Message, Renderer>> = Vec::new();
-        controls_vec.push(horizontal_space(7.5).into());
+        let mut controls_vec: Vec<Element<'_, Message, Renderer>> =
+            vec![horizontal_space(7.5).into()];
 
         controls_vec.push(
             container(
@@ -377,6 +537,28 @@ impl App {
             .into(),
         );
 
+        controls_vec.push(
+            container(
+                button(row!(
+                    appearance::icon('󰒺').style(theme::Text::Color(self.style_sheet().gray)),
+                    text(
+                        self.repo_config
+                            .secondary_sort
+                            .first()
+                            .map(|key| format!(" {key}"))
+                            .unwrap_or_default()
+                    )
+                    .size(14)
+                    .style(theme::Text::Color(self.style_sheet().gray))
+                ))
+                .style(theme::Button::Text)
+                .on_press(Message::UpdateConfig(ConfigMessage::CycleSecondarySort)),
+            )
+            .height(height)
+            .center_y()
+            .into(),
+        );
+
         self_vec.push(
             container(row(controls_vec).height(height))
                 .align_x(alignment::Horizontal::Left)
@@ -392,7 +574,7 @@ impl App {
             .into()
     }
 
-    pub fn view_controls(&self) -> iced::Element<Message> {
+    pub fn view_controls(&self) -> iced::Element<'_, Message> {
         let mut self_vec: Vec<Element<'_, Message, Renderer>> = Vec::new();
         let height = 45;
         self_vec.push(horizontal_space(Length::FillPortion(1)).into());
@@ -443,6 +625,44 @@ impl App {
             self_vec.push(horizontal_space(Length::FillPortion(2)).into());
         }
 
+        if self.view == TodoView::Trash && !self.trash.is_empty() {
+            self_vec.push(
+                container(
+                    button(
+                        appearance::icon('󰗨')
+                            .style(theme::Text::Color(self.style_sheet().flag))
+                            .size(25)
+                            .width(Length::FillPortion(2)),
+                    )
+                    .style(theme::Button::Text)
+                    .on_press(Message::EmptyTrash),
+                )
+                .height(height)
+                .center_y()
+                .into(),
+            );
+            self_vec.push(horizontal_space(Length::FillPortion(2)).into());
+        }
+
+        if self.view == TodoView::Logbook {
+            self_vec.push(
+                container(
+                    button(
+                        appearance::icon(if self.show_archived { '󰀼' } else { '󱈎' })
+                            .style(theme::Text::Color(self.style_sheet().gray))
+                            .size(25)
+                            .width(Length::FillPortion(2)),
+                    )
+                    .style(theme::Button::Text)
+                    .on_press(Message::ToggleArchived),
+                )
+                .height(height)
+                .center_y()
+                .into(),
+            );
+            self_vec.push(horizontal_space(Length::FillPortion(2)).into());
+        }
+
         self_vec.push(
             container(
                 button(
@@ -467,19 +687,80 @@ impl App {
             .into()
     }
 
-    pub fn refresh_range(&mut self) {
-        self.range = self.view.filter(
-            &self.instance,
-            &self.complete_filter,
-            &if self.search {
-                self.search_cache
-                    .split_whitespace()
-                    .map(|s| s.to_lowercase())
-                    .collect()
-            } else {
-                Vec::new()
-            },
+    /// The list the todo is displayed in, with its siblings in the current range.
+    pub fn order_scope(&self, id: u64) -> (OrderScope, Vec<u64>) {
+        let todo = self.instance.get(id).unwrap();
+        if let Some(father) = todo
+            .dependents
+            .iter()
+            .find(|dep| self.range.contains(dep))
+            .or(todo.dependents.first())
+        {
+            (
+                OrderScope::Parent(*father),
+                self.instance
+                    .children_once(*father)
+                    .into_iter()
+                    .filter(|child| self.range.contains(child))
+                    .collect(),
+            )
+        } else {
+            (OrderScope::View(self.view.order_key()), self.root_todos())
+        }
+    }
+
+    /// Top-level todos in the current range in display order.
+    pub fn root_todos(&self) -> Vec<u64> {
+        let mut roots: Vec<u64> = self
+            .instance
+            .todos
+            .iter()
+            .filter(|todo| todo.dependents.is_empty() && self.range.contains(&todo.id()))
+            .map(|todo| todo.id())
+            .collect();
+        self.instance.sort(
+            &mut roots,
+            &OrderScope::View(self.view.order_key()),
+            &self.repo_config.secondary_sort,
         );
+        roots
+    }
+
+    pub fn refresh_range(&mut self) {
+        let query = if self.search {
+            // Half-typed expressions still search for their words
+            Query::parse(&self.search_cache).unwrap_or_else(|_| Query::words(&self.search_cache))
+        } else {
+            Query::All
+        };
+        self.range = self
+            .view
+            .filter(&self.instance, &self.complete_filter, &query);
+
+        let matchers: Vec<Matcher> = query.texts().iter().map(|t| Matcher::fuzzy(t)).collect();
+        self.hits = if matchers.is_empty() {
+            HashMap::new()
+        } else {
+            self.instance
+                .search(&matchers)
+                .into_iter()
+                .map(|hit| (hit.id, hit))
+                .collect()
+        };
+    }
+
+    /// Put the todos whose subtree matches the search best first.
+    pub fn sort_by_relevance(&self, ids: &mut [u64]) {
+        if self.hits.is_empty() {
+            return;
+        }
+        ids.sort_by_cached_key(|id| {
+            -std::iter::once(*id)
+                .chain(self.instance.children(*id))
+                .filter_map(|id| self.hits.get(&id).map(|hit| hit.score))
+                .max()
+                .unwrap_or(0)
+        });
     }
 }
 
@@ -495,9 +776,20 @@ impl Application for App {
     fn new(flags: Self::Flags) -> (Self, iced::Command<Self::Message>) {
         let config = config::ConfigInstance::get();
 
+        let mut unlock_error = None;
+        if crypt::is_encrypted(&flags.path) {
+            match crypt::passphrase_from_env() {
+                Ok(Some(passphrase)) => {
+                    unlock_error = crypt::unlock(&flags.path, &passphrase).err()
+                }
+                Ok(None) => (),
+                Err(err) => unlock_error = Some(err),
+            }
+        }
+
         let mut app = App {
             instance: TodoInstance::create(&flags.path),
-            states: Vec::new(),
+            states: HashMap::new(),
             dep_selection: None,
             range: Vec::new(),
             complete_filter: TodoCompleteFilter::NotComplete,
@@ -505,18 +797,25 @@ impl Application for App {
             search_cache: String::new(),
             search: false,
             config,
+            repo_config: RepoConfig::get(&flags.path),
+            hits: HashMap::new(),
+            show_archived: false,
+            trash: Vec::new(),
+            locked: crypt::is_encrypted(&flags.path) && !crypt::is_unlocked(&flags.path),
+            passphrase: String::new(),
+            unlock_error,
+            error: None,
         };
 
-        app.instance.read_all();
-        app.instance.refresh();
-        app.refresh_states();
-        app.refresh_range();
+        if !app.locked {
+            app.load();
+        }
         app.config.write();
 
         (
             app,
             iced::font::load(include_bytes!("../fonts/nerd_font.ttf").as_slice())
-                .map(|e| Message::LoadFont(e)),
+                .map(Message::LoadFont),
         )
     }
 
@@ -533,21 +832,34 @@ impl Application for App {
     }
 
     fn update(&mut self, message: Self::Message) -> iced::Command<Self::Message> {
-        self.instance.refresh();
+        if self.locked {
+            match message {
+                Message::CachePassphrase(passphrase) => self.passphrase = passphrase,
+                Message::Unlock => self.unlock(),
+                _ => (),
+            }
+            return iced::Command::none();
+        }
+
+        let mut command = iced::Command::none();
+        self.error = None;
         match message {
             Message::TodoMessage(id, msg) => match msg {
                 TodoMessage::ToggleComplete => {
                     let todo = self.instance.get_mut(id).unwrap();
-                    todo.completed = !todo.completed;
+                    todo.set_completed(!todo.completed);
                     self.refresh_range();
                 }
                 TodoMessage::Edit(edit_msg) => match edit_msg {
                     EditMessage::Name(name) => {
                         self.instance.get_mut(id).unwrap().metadata.name = name
                     }
-                    EditMessage::Details(details) => {
-                        self.instance.get_mut(id).unwrap().metadata.details = details
-                    }
+                    EditMessage::Details(details) => self
+                        .instance
+                        .get_mut(id)
+                        .unwrap()
+                        .metadata
+                        .set_details(details),
                     EditMessage::ToggleEdit => {
                         {
                             let todo = self.instance.get(id).unwrap();
@@ -570,7 +882,7 @@ impl Application for App {
                             }
                         }
                         if self.state(id).unwrap().editing {
-                            for state in &mut self.states {
+                            for state in self.states.values_mut() {
                                 if !state.id == id {
                                     state.editing = false;
                                 }
@@ -583,6 +895,8 @@ impl Application for App {
                                 if todo.metadata.name.is_empty() {
                                     todo.metadata.name = String::from("untitled todo");
                                 }
+                                // Past dates typed in move to today once done editing
+                                self.instance.refresh();
                             }
                         }
 
@@ -611,6 +925,44 @@ impl Application for App {
                             todo.tags.push(tag.to_string());
                         }
                     }
+                    EditMessage::CheckItemName(index, name) => {
+                        if let Some(item) = self
+                            .instance
+                            .get_mut(id)
+                            .unwrap()
+                            .metadata
+                            .checklist
+                            .get_mut(index)
+                        {
+                            item.name = name;
+                        }
+                    }
+                    EditMessage::ToggleCheckItem(index) => {
+                        if let Some(item) = self
+                            .instance
+                            .get_mut(id)
+                            .unwrap()
+                            .metadata
+                            .checklist
+                            .get_mut(index)
+                        {
+                            item.checked = !item.checked;
+                        }
+                    }
+                    EditMessage::AddCheckItem => {
+                        self.instance
+                            .get_mut(id)
+                            .unwrap()
+                            .metadata
+                            .checklist
+                            .push(CheckItem::new(String::new()));
+                    }
+                    EditMessage::RemoveCheckItem(index) => {
+                        let checklist = &mut self.instance.get_mut(id).unwrap().metadata.checklist;
+                        if index < checklist.len() {
+                            checklist.remove(index);
+                        }
+                    }
                     EditMessage::ToggleSelectChildren => {
                         if self.dep_selection.is_some() {
                             self.dep_selection = None;
@@ -624,18 +976,40 @@ impl Application for App {
                     state.expanded = !state.expanded;
                 }
                 TodoMessage::Delete => {
-                    match self.view {
-                        TodoView::Project(idd) => {
-                            if idd == id {
-                                self.view = TodoView::Today;
-                            }
-                        }
-                        _ => (),
+                    if self.view == TodoView::Project(id) {
+                        self.view = TodoView::Today;
+                    }
+                    if let Err(err) = self.instance.remove(id) {
+                        self.error = Some(format!("Can't move the todo to the trash: {err}"));
                     }
-                    self.instance.remove(id);
                     self.refresh_states();
                     self.refresh_range();
                 }
+                TodoMessage::Move(up) => {
+                    let (scope, mut siblings) = self.order_scope(id);
+                    self.instance
+                        .sort(&mut siblings, &scope, &self.repo_config.secondary_sort);
+                    if let Some(position) = siblings.iter().position(|sibling| *sibling == id) {
+                        let target = if up {
+                            position.checked_sub(1)
+                        } else {
+                            Some(position + 1)
+                        };
+                        if let Some(target) = target.and_then(|t| siblings.get(t)).copied() {
+                            self.instance.reorder(
+                                &siblings,
+                                id,
+                                target,
+                                !up,
+                                &scope,
+                                &self.repo_config.secondary_sort,
+                            );
+                        }
+                    }
+                }
+                TodoMessage::CopyId => {
+                    command = iced::clipboard::write(self.instance.short_id(id));
+                }
                 TodoMessage::ToggleChild => {
                     if let Some((father_id, child_vec)) = &mut self.dep_selection {
                         if child_vec.contains(&id) {
@@ -652,7 +1026,14 @@ impl Application for App {
                 }
             },
             Message::SwitchView(view) => {
+                if self.show_archived && view != TodoView::Logbook {
+                    self.show_archived = false;
+                    self.instance.unload_archive();
+                }
                 self.view = view;
+                if self.view == TodoView::Trash {
+                    self.trash = self.instance.trashed();
+                }
                 self.complete_filter = self.view.default_complete_filter();
                 self.refresh_range();
                 self.states.clear();
@@ -663,7 +1044,7 @@ impl Application for App {
                 self.view.process_todo(&mut todo);
                 let id = todo.id();
                 if !self.instance.todos().contains(&id) {
-                    self.instance.todos.push(todo);
+                    self.instance.insert(todo);
                 }
                 self.refresh_states();
                 self.refresh_range();
@@ -685,6 +1066,31 @@ impl Application for App {
                 }
                 self.refresh_range();
             }
+            Message::RestoreTodo(id) => {
+                self.instance.restore(id);
+                self.trash = self.instance.trashed();
+                self.refresh_states();
+            }
+            Message::EmptyTrash => {
+                self.instance.empty_trash(None);
+                self.trash.clear();
+            }
+            Message::ToggleArchived => {
+                self.show_archived = !self.show_archived;
+                if self.show_archived {
+                    self.instance.read_archive();
+                } else {
+                    self.instance.unload_archive();
+                }
+                self.refresh_states();
+                self.refresh_range();
+            }
+            Message::Reload => {
+                self.instance.read_all();
+                self.instance.refresh();
+                self.refresh_states();
+                self.refresh_range();
+            }
             Message::ToggleSearch => {
                 self.search = !self.search;
                 if !self.search {
@@ -700,6 +1106,18 @@ impl Application for App {
                     ConfigMessage::ToggleDarkMode => {
                         self.config.dark_theme = !self.config.dark_theme;
                     }
+                    ConfigMessage::CycleSecondarySort => {
+                        let sort = &mut self.repo_config.secondary_sort;
+                        let next = match sort.first() {
+                            Some(key) => SortKey::ALL
+                                .iter()
+                                .position(|k| k == key)
+                                .and_then(|index| SortKey::ALL.get(index + 1)),
+                            None => SortKey::ALL.first(),
+                        };
+                        *sort = next.into_iter().copied().collect();
+                        let _ = self.repo_config.write(self.instance.path());
+                    }
                 }
                 self.config.write();
             }
@@ -707,16 +1125,31 @@ impl Application for App {
         };
 
         self.instance.write_all();
-        self.refresh_states();
-        iced::Command::none()
+        command
     }
 
-    fn view(&self) -> iced::Element<Self::Message> {
+    fn subscription(&self) -> iced::Subscription<Self::Message> {
+        iced::subscription::events_with(|event, _| match event {
+            iced::Event::Window(window::Event::Focused) => Some(Message::Reload),
+            _ => None,
+        })
+    }
+
+    fn view(&self) -> iced::Element<'_, Self::Message> {
+        if self.locked {
+            return self.view_unlock();
+        }
         row(vec![
             self.view_sidebar(),
-            column(vec![self.view_todos(), self.view_controls()])
-                .width(Length::Fill)
-                .into(),
+            column(
+                [self.view_todos()]
+                    .into_iter()
+                    .chain(self.view_error())
+                    .chain([self.view_controls()])
+                    .collect(),
+            )
+            .width(Length::Fill)
+            .into(),
         ])
         .height(Length::Fill)
         .into()
@@ -736,11 +1169,19 @@ impl Default for Flags {
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 enum Message {
     TodoMessage(u64, TodoMessage),
     SwitchView(TodoView),
     CreateTodo,
     SwitchCompleteFilter,
+    ToggleArchived,
+    RestoreTodo(u64),
+    EmptyTrash,
+    /// Read the files changed by other programs.
+    Reload,
+    CachePassphrase(String),
+    Unlock,
     ToggleSearch,
     CacheSearchContent(String),
     UpdateConfig(ConfigMessage),
@@ -750,6 +1191,7 @@ enum Message {
 #[derive(Debug, Clone)]
 enum ConfigMessage {
     ToggleDarkMode,
+    CycleSecondarySort,
 }
 
 #[derive(Debug, Clone)]
@@ -759,6 +1201,8 @@ enum TodoMessage {
     ExpandToggle,
     Delete,
     ToggleChild,
+    Move(bool),
+    CopyId,
 }
 
 #[derive(Debug, Clone)]
@@ -768,6 +1212,10 @@ enum EditMessage {
     Date(String),
     Deadline(String),
     Tags(String),
+    CheckItemName(usize, String),
+    ToggleCheckItem(usize),
+    AddCheckItem,
+    RemoveCheckItem(usize),
     ToggleEdit,
     ToggleSelectChildren,
 }
@@ -791,7 +1239,7 @@ impl TodoState {
         }
     }
 
-    pub fn view<'a>(&'a self, app: &'a App) -> Vec<(u16, Vec<Element<'_, Message, Renderer>>)> {
+    pub fn view<'a>(&'a self, app: &'a App) -> Vec<(u16, Vec<Element<'a, Message, Renderer>>)> {
         let height = 28.0;
 
         let todo = app.instance.get(self.id).unwrap();
@@ -827,7 +1275,7 @@ impl TodoState {
                     appearance::icon(completion_state_view(self.id, &app.instance))
                         .size(17)
                         .style(theme::Text::Color(
-                            if app.instance.children_once(self.id).is_empty()
+                            if !app.instance.has_progress(self.id)
                                 && !app.instance.get(self.id).unwrap().completed
                             {
                                 app.style_sheet().gray
@@ -891,13 +1339,30 @@ impl TodoState {
 
             left_vec.push(
                 container(
-                    button(text(&todo.metadata.name).size(15))
-                        .style(theme::Button::Text)
-                        .on_press(Message::TodoMessage(
-                            self.id,
-                            TodoMessage::Edit(EditMessage::ToggleEdit),
-                        ))
-                        .height(Length::Fill),
+                    button(row(split_ranges(
+                        &todo.metadata.name,
+                        &app.hits
+                            .get(&self.id)
+                            .map_or(Vec::new(), |hit| hit.name.clone()),
+                    )
+                    .into_iter()
+                    .map(|(piece, matched)| {
+                        let piece = text(piece).size(15);
+                        if matched {
+                            piece
+                                .style(theme::Text::Color(app.style_sheet().star))
+                                .into()
+                        } else {
+                            piece.into()
+                        }
+                    })
+                    .collect()))
+                    .style(theme::Button::Text)
+                    .on_press(Message::TodoMessage(
+                        self.id,
+                        TodoMessage::Edit(EditMessage::ToggleEdit),
+                    ))
+                    .height(Length::Fill),
                 )
                 .height(height)
                 .center_y()
@@ -1017,7 +1482,7 @@ impl TodoState {
                 row!(
                     container(appearance::icon('󰟃')).height(height).center_y(),
                     container(
-                        text_input("Input details here", &todo.metadata.details)
+                        text_input("Input details here", todo.metadata.details())
                             .on_input(|input| {
                                 Message::TodoMessage(
                                     self.id.to_owned(),
@@ -1099,6 +1564,91 @@ impl TodoState {
                 .into(),
             );
 
+            col_vec.push(
+                row!(
+                    container(appearance::icon('󰻾')).height(height).center_y(),
+                    container(
+                        button(row!(
+                            text(format!(" {} ", app.instance.short_id(self.id)))
+                                .size(14)
+                                .style(theme::Text::Color(app.style_sheet().gray)),
+                            appearance::icon('󰆏')
+                                .size(14)
+                                .style(theme::Text::Color(app.style_sheet().gray))
+                        ))
+                        .padding(0)
+                        .style(theme::Button::Text)
+                        .on_press(Message::TodoMessage(self.id, TodoMessage::CopyId))
+                    )
+                    .height(height)
+                    .center_y()
+                )
+                .into(),
+            );
+            for (index, item) in todo.metadata.checklist.iter().enumerate() {
+                col_vec.push(
+                    row!(
+                        container(
+                            button(appearance::icon(if item.checked { '󰄲' } else { '󰄱' }))
+                                .padding(0)
+                                .style(theme::Button::Text)
+                                .on_press(Message::TodoMessage(
+                                    self.id,
+                                    TodoMessage::Edit(EditMessage::ToggleCheckItem(index)),
+                                ))
+                        )
+                        .height(height)
+                        .center_y(),
+                        container(
+                            text_input("Input checklist item here", &item.name)
+                                .on_input(move |input| {
+                                    Message::TodoMessage(
+                                        self.id,
+                                        TodoMessage::Edit(EditMessage::CheckItemName(index, input)),
+                                    )
+                                })
+                                .width(330)
+                        )
+                        .height(height)
+                        .center_y(),
+                        container(
+                            button(
+                                appearance::icon('󰅖')
+                                    .style(theme::Text::Color(app.style_sheet().gray))
+                            )
+                            .padding(0)
+                            .style(theme::Button::Text)
+                            .on_press(Message::TodoMessage(
+                                self.id,
+                                TodoMessage::Edit(EditMessage::RemoveCheckItem(index)),
+                            ))
+                        )
+                        .height(height)
+                        .center_y()
+                    )
+                    .into(),
+                );
+            }
+            col_vec.push(
+                row!(container(
+                    button(row!(
+                        appearance::icon('󰐕').style(theme::Text::Color(app.style_sheet().gray)),
+                        text(" Add checklist item")
+                            .size(14)
+                            .style(theme::Text::Color(app.style_sheet().gray))
+                    ))
+                    .padding(0)
+                    .style(theme::Button::Text)
+                    .on_press(Message::TodoMessage(
+                        self.id,
+                        TodoMessage::Edit(EditMessage::AddCheckItem),
+                    ))
+                )
+                .height(height)
+                .center_y())
+                .into(),
+            );
+
             self_vec.push(column(col_vec).into());
 
             right_vec.push(horizontal_space(8.5).into());
@@ -1137,6 +1687,21 @@ impl TodoState {
                 .center_y()
                 .into(),
             );
+            for (icon, up) in [('󰁝', true), ('󰁅', false)] {
+                controls_vec.push(
+                    container(
+                        button(
+                            appearance::icon(icon)
+                                .style(theme::Text::Color(app.style_sheet().gray)),
+                        )
+                        .style(theme::Button::Text)
+                        .on_press(Message::TodoMessage(self.id, TodoMessage::Move(up))),
+                    )
+                    .height(height)
+                    .center_y()
+                    .into(),
+                );
+            }
             controls_vec.push(
                 container(
                     button(appearance::icon('󰩹').style(theme::Text::Color(app.style_sheet().gray)))
@@ -1180,11 +1745,34 @@ impl TodoState {
                 vec![column({
                     let mut column_items = vec![row(self_vec).into()];
 
-                    if !todo.metadata.details.is_empty() {
+                    if !todo.metadata.details().is_empty() {
                         column_items.push(
                             row![
                                 horizontal_space(60),
-                                text(todo.metadata.details.clone())
+                                text(todo.metadata.details().to_owned())
+                                    .style(theme::Text::Color(app.style_sheet().gray))
+                                    .size(13.5)
+                            ]
+                            .into(),
+                        );
+                    }
+
+                    for (index, item) in todo.metadata.checklist.iter().enumerate() {
+                        column_items.push(
+                            row![
+                                horizontal_space(57.5),
+                                button(
+                                    appearance::icon(if item.checked { '󰄲' } else { '󰄱' })
+                                        .size(14)
+                                        .style(theme::Text::Color(app.style_sheet().gray))
+                                )
+                                .padding(0)
+                                .style(theme::Button::Text)
+                                .on_press(Message::TodoMessage(
+                                    self.id,
+                                    TodoMessage::Edit(EditMessage::ToggleCheckItem(index)),
+                                )),
+                                text(&item.name)
                                     .style(theme::Text::Color(app.style_sheet().gray))
                                     .size(13.5)
                             ]
@@ -1199,7 +1787,14 @@ impl TodoState {
         ));
 
         if self.expanded {
-            for todo_id in app.instance.children_once(self.id) {
+            let mut children = app.instance.children_once(self.id);
+            app.instance.sort(
+                &mut children,
+                &OrderScope::Parent(self.id),
+                &app.repo_config.secondary_sort,
+            );
+            app.sort_by_relevance(&mut children);
+            for todo_id in children {
                 if app.range.contains(&todo_id) && {
                     let mut b = true;
                     for c in app.instance.children(self.id) {
@@ -1220,16 +1815,20 @@ impl TodoState {
 }
 
 fn completion_state_view(id: u64, instance: &TodoInstance) -> char {
-    let todo = instance.get(id).unwrap();
+    let Some(todo) = instance.get(id) else {
+        return '󰄱';
+    };
     if todo.completed {
         if instance.children_once(id).is_empty() {
             '󰄲'
         } else {
             '󰗠'
         }
-    } else if instance.children_once(id).is_empty() {
+    } else if todo.cancelled {
+        '󰜺'
+    } else if !instance.has_progress(id) {
         '󰄱'
     } else {
-        util::progression_char((instance.weight(id, true) * 100) / instance.weight(id, false))
+        util::progression_char(instance.progress(id).unwrap_or_default().percent)
     }
 }